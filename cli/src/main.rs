@@ -7,6 +7,7 @@ extern crate slog_term;
 
 mod operation;
 
+use std::collections::BTreeMap;
 use std::env;
 use std::path::Path;
 use std::result;
@@ -16,7 +17,7 @@ use std::sync::{atomic, Arc};
 use std::time::Instant;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
-use psqlpack::{ChainedError, PsqlpackResult, Semver};
+use psqlpack::{ChainedError, PsqlpackResult, ReportFormat, Semver};
 use slog::{Drain, Logger};
 
 /// A thread safe toggle.
@@ -80,6 +81,26 @@ fn main() {
     let matches = App::new("psqlpack")
         .version(crate_version!())
         .author(crate_authors!())
+        .subcommand(
+            SubCommand::with_name("drift")
+                .about("Lists changes made to a database since the last publish")
+                .arg(
+                    Arg::with_name("TARGET")
+                        .long("target")
+                        .short("t")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The target database connection string"),
+                )
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .long("output")
+                        .short("o")
+                        .required(false)
+                        .takes_value(true)
+                        .help("The JSON file to write the changes to"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("extension")
                 .about("Creates a psqlpack from an extension installed on an existing database")
@@ -136,6 +157,26 @@ fn main() {
                         .help("The folder location to export the psqlpack to"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Lists the packages that have been published to a database")
+                .arg(
+                    Arg::with_name("TARGET")
+                        .long("target")
+                        .short("t")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The target database connection string"),
+                )
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .long("output")
+                        .short("o")
+                        .required(false)
+                        .takes_value(true)
+                        .help("The JSON file to write the deployment history to"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("new")
                 .about("Creates a new project or publish profile based upon the specified template")
@@ -208,6 +249,22 @@ fn main() {
                         .required(true)
                         .takes_value(true)
                         .help("The publish profile to use for publishing"),
+                )
+                .arg(
+                    Arg::with_name("VARIABLE")
+                        .long("variable")
+                        .short("v")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Sets a variable declared within the project, in the form name=value"),
+                )
+                .arg(
+                    Arg::with_name("VERIFY")
+                        .long("verify")
+                        .required(false)
+                        .help("Executes the changes within a transaction that is rolled back, reporting any failures"),
                 ),
         )
         .subcommand(
@@ -244,6 +301,24 @@ fn main() {
                         .required(true)
                         .takes_value(true)
                         .help("The report file to generate"),
+                )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .long("format")
+                        .short("f")
+                        .required(false)
+                        .takes_value(true)
+                        .help("The format of the report: json (default), markdown or html"),
+                )
+                .arg(
+                    Arg::with_name("VARIABLE")
+                        .long("variable")
+                        .short("v")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Sets a variable declared within the project, in the form name=value"),
                 ),
         )
         .subcommand(
@@ -280,6 +355,24 @@ fn main() {
                         .required(true)
                         .takes_value(true)
                         .help("The SQL file to generate"),
+                )
+                .arg(
+                    Arg::with_name("ROLLBACK")
+                        .long("rollback")
+                        .short("r")
+                        .required(false)
+                        .takes_value(true)
+                        .help("The SQL file to generate that reverts the changes"),
+                )
+                .arg(
+                    Arg::with_name("VARIABLE")
+                        .long("variable")
+                        .short("v")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Sets a variable declared within the project, in the form name=value"),
                 ),
         )
         .arg(
@@ -338,6 +431,13 @@ enum HandleResult {
 fn handle(log: &Logger, matches: &ArgMatches) -> HandleResult {
     // TODO: do some validation
     match matches.subcommand() {
+        (command @ "drift", Some(drift)) => {
+            let log = log.new(o!("command" => command.to_owned()));
+            let target = String::from(drift.value_of("TARGET").unwrap());
+            let output_file = drift.value_of("OUTPUT").map(Path::new);
+            let result = operation::drift(log, &target, output_file);
+            HandleResult::Outcome(command.to_owned(), result)
+        }
         (command @ "extension", Some(extension)) => {
             let log = log.new(o!("command" => command.to_owned()));
             let source = String::from(extension.value_of("SOURCE").unwrap());
@@ -373,6 +473,13 @@ fn handle(log: &Logger, matches: &ArgMatches) -> HandleResult {
             let result = operation::extract_database(log, &source, output);
             HandleResult::Outcome(command.to_owned(), result)
         }
+        (command @ "history", Some(history)) => {
+            let log = log.new(o!("command" => command.to_owned()));
+            let target = String::from(history.value_of("TARGET").unwrap());
+            let output_file = history.value_of("OUTPUT").map(Path::new);
+            let result = operation::history(log, &target, output_file);
+            HandleResult::Outcome(command.to_owned(), result)
+        }
         (command @ "new", Some(new)) => {
             let log = log.new(o!("command" => command.to_owned()));
             let template = String::from(new.value_of("TEMPLATE").unwrap());
@@ -411,7 +518,12 @@ fn handle(log: &Logger, matches: &ArgMatches) -> HandleResult {
             let source = Path::new(publish.value_of("SOURCE").unwrap());
             let target = String::from(publish.value_of("TARGET").unwrap());
             let profile = Path::new(publish.value_of("PROFILE").unwrap());
-            let result = operation::publish(log, source, &target, profile);
+            let verify = publish.is_present("VERIFY");
+            let variables = match parse_variables(publish) {
+                Ok(variables) => variables,
+                Err(reason) => return HandleResult::InvalidArgument("variable".into(), reason),
+            };
+            let result = operation::publish(log, source, &target, profile, variables, verify);
             HandleResult::Outcome(command.to_owned(), result)
         }
         (command @ "report", Some(report)) => {
@@ -421,7 +533,15 @@ fn handle(log: &Logger, matches: &ArgMatches) -> HandleResult {
             let target = String::from(report.value_of("TARGET").unwrap());
             let profile = Path::new(report.value_of("PROFILE").unwrap());
             let output_file = Path::new(report.value_of("OUTPUT").unwrap());
-            let result = operation::generate_report(log, source, &target, profile, output_file);
+            let format = match ReportFormat::from_str(report.value_of("FORMAT").unwrap_or("json")) {
+                Ok(format) => format,
+                Err(reason) => return HandleResult::InvalidArgument("format".into(), reason),
+            };
+            let variables = match parse_variables(report) {
+                Ok(variables) => variables,
+                Err(reason) => return HandleResult::InvalidArgument("variable".into(), reason),
+            };
+            let result = operation::generate_report(log, source, &target, profile, output_file, format, variables);
             HandleResult::Outcome(command.to_owned(), result)
         }
         (command @ "script", Some(script)) => {
@@ -431,9 +551,33 @@ fn handle(log: &Logger, matches: &ArgMatches) -> HandleResult {
             let target = String::from(script.value_of("TARGET").unwrap());
             let profile = Path::new(script.value_of("PROFILE").unwrap());
             let output_file = Path::new(script.value_of("OUTPUT").unwrap());
-            let result = operation::generate_sql(log, source, &target, profile, output_file);
+            let rollback_file = script.value_of("ROLLBACK").map(Path::new);
+            let variables = match parse_variables(script) {
+                Ok(variables) => variables,
+                Err(reason) => return HandleResult::InvalidArgument("variable".into(), reason),
+            };
+            let result = operation::generate_sql(log, source, &target, profile, output_file, rollback_file, variables);
             HandleResult::Outcome(command.to_owned(), result)
         }
         _ => HandleResult::UnknownSubcommand,
     }
 }
+
+// Parses each `name=value` given for a variable
+fn parse_variables(matches: &ArgMatches) -> Result<BTreeMap<String, String>, String> {
+    let mut variables = BTreeMap::new();
+    for variable in matches.values_of("VARIABLE").into_iter().flatten() {
+        match variable.find('=') {
+            Some(position) if position > 0 => {
+                variables.insert(variable[..position].to_owned(), variable[position + 1..].to_owned());
+            }
+            _ => {
+                return Err(format!(
+                    "Expected a variable in the form name=value but found `{}`",
+                    variable
+                ))
+            }
+        }
+    }
+    Ok(variables)
+}
@@ -1,9 +1,11 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 
 use slog::Logger;
 
 use psqlpack::{
-    template, Capabilities, Delta, Package, Project, PsqlpackErrorKind, PsqlpackResult, PublishProfile, Semver,
+    template, Capabilities, Delta, Deployment, Drift, Package, Project, PsqlpackErrorKind, PsqlpackResult,
+    PublishProfile, ReportFormat, Semver,
 };
 
 pub fn package<L: Into<Logger>>(log: L, project_file: &Path, output_path: &Path) -> PsqlpackResult<()> {
@@ -98,10 +100,14 @@ pub fn publish<L: Into<Logger>>(
     source_file: &Path,
     target_connection_string: &str,
     publish_profile: &Path,
+    variables: BTreeMap<String, String>,
+    verify: bool,
 ) -> PsqlpackResult<()> {
     let log = log.into().new(o!("operation" => "publish"));
-    let package = Package::from_path(&log, source_file)?;
-    let publish_profile = PublishProfile::from_path(publish_profile)?;
+    let mut publish_profile = PublishProfile::from_path(publish_profile)?;
+    // Variables given on the command line take precedence over the publish profile
+    publish_profile.variables.extend(variables);
+    let package = Package::from_path(&log, source_file)?.resolve_variables(&publish_profile.variables)?;
     let connection = target_connection_string.parse()?;
 
     trace!(log, "Loading Server Capabilities");
@@ -118,7 +124,11 @@ pub fn publish<L: Into<Logger>>(
         &capabilities,
         &publish_profile,
     )?;
-    delta.apply(&log, &connection)
+    if verify {
+        delta.verify(&log, &connection, &publish_profile)
+    } else {
+        delta.apply(&log, &connection, &publish_profile)
+    }
 }
 
 pub fn generate_sql<L: Into<Logger>>(
@@ -127,10 +137,13 @@ pub fn generate_sql<L: Into<Logger>>(
     target_connection_string: &str,
     publish_profile: &Path,
     output_file: &Path,
+    rollback_file: Option<&Path>,
+    variables: BTreeMap<String, String>,
 ) -> PsqlpackResult<()> {
     let log = log.into().new(o!("operation" => "generate_sql"));
-    let package = Package::from_path(&log, source_package_path)?;
-    let publish_profile = PublishProfile::from_path(publish_profile)?;
+    let mut publish_profile = PublishProfile::from_path(publish_profile)?;
+    publish_profile.variables.extend(variables);
+    let package = Package::from_path(&log, source_package_path)?.resolve_variables(&publish_profile.variables)?;
     let connection = target_connection_string.parse()?;
 
     trace!(log, "Loading Server Capabilities");
@@ -147,7 +160,14 @@ pub fn generate_sql<L: Into<Logger>>(
         &capabilities,
         &publish_profile,
     )?;
-    delta.write_sql(&log, output_file)
+    delta.write_sql(&log, output_file)?;
+
+    // The target package was consumed generating the delta, so is loaded again as the state to revert to
+    if let Some(rollback_file) = rollback_file {
+        let before_package = Package::from_connection(&log, &connection, &capabilities)?;
+        delta.rollback(before_package.as_ref()).write_sql(&log, rollback_file)?;
+    }
+    Ok(())
 }
 
 pub fn generate_report<L: Into<Logger>>(
@@ -156,10 +176,13 @@ pub fn generate_report<L: Into<Logger>>(
     target_connection_string: &str,
     publish_profile: &Path,
     output_file: &Path,
+    format: ReportFormat,
+    variables: BTreeMap<String, String>,
 ) -> PsqlpackResult<()> {
     let log = log.into().new(o!("operation" => "generate_report"));
-    let package = Package::from_path(&log, source_package_path)?;
-    let publish_profile = PublishProfile::from_path(publish_profile)?;
+    let mut publish_profile = PublishProfile::from_path(publish_profile)?;
+    publish_profile.variables.extend(variables);
+    let package = Package::from_path(&log, source_package_path)?.resolve_variables(&publish_profile.variables)?;
     let connection = target_connection_string.parse()?;
 
     trace!(log, "Loading Server Capabilities");
@@ -176,5 +199,64 @@ pub fn generate_report<L: Into<Logger>>(
         &capabilities,
         &publish_profile,
     )?;
-    delta.write_report(output_file)
+
+    // The target package was consumed generating the delta, so is loaded again to describe previous values
+    let before_package = Package::from_connection(&log, &connection, &capabilities)?;
+    delta.write_report(&log, before_package.as_ref(), format, output_file)
+}
+
+pub fn drift<L: Into<Logger>>(
+    log: L,
+    target_connection_string: &str,
+    output_file: Option<&Path>,
+) -> PsqlpackResult<()> {
+    let log = log.into().new(o!("operation" => "drift"));
+    let connection = target_connection_string.parse()?;
+
+    trace!(log, "Comparing database against last deployment");
+    let drift = Drift::detect(&log, &connection)?;
+    if drift.changes.is_empty() {
+        info!(log, "No changes since deployment {}", drift.deployment; "hash" => &drift.package_hash);
+    }
+    for change in &drift.changes {
+        warn!(log, "{}", change);
+    }
+    match output_file {
+        Some(output_file) => {
+            trace!(log, "Writing drift"; "output" => output_file.to_str().unwrap());
+            drift.write_to(output_file)
+        }
+        None => Ok(()),
+    }
+}
+
+pub fn history<L: Into<Logger>>(
+    log: L,
+    target_connection_string: &str,
+    output_file: Option<&Path>,
+) -> PsqlpackResult<()> {
+    let log = log.into().new(o!("operation" => "history"));
+    let connection = target_connection_string.parse()?;
+
+    trace!(log, "Loading deployment history");
+    let history = Deployment::history(&log, &connection)?;
+    if history.is_empty() {
+        info!(log, "No deployments have been recorded");
+    }
+    for deployment in &history {
+        info!(
+            log,
+            "Deployment {} finished at {}", deployment.id, deployment.finished_at;
+            "hash" => &deployment.package_hash,
+            "psqlpackVersion" => &deployment.psqlpack_version,
+            "executedBy" => &deployment.executed_by
+        );
+    }
+    match output_file {
+        Some(output_file) => {
+            trace!(log, "Writing history"; "output" => output_file.to_str().unwrap());
+            Deployment::write_history(&history, output_file)
+        }
+        None => Ok(()),
+    }
 }
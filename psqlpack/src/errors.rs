@@ -38,6 +38,41 @@ error_chain! {
             description("Couldn't parse publish profile file")
             display("Couldn't parse publish profile file: {}", path.as_path().display())
         }
+        RefactorLogReadError(path: PathBuf) {
+            description("Couldn't read refactor log file")
+            display("Couldn't read refactor log file: {}", path.as_path().display())
+        }
+        RefactorLogParseError(path: PathBuf) {
+            description("Couldn't parse refactor log file")
+            display("Couldn't parse refactor log file: {}", path.as_path().display())
+        }
+        PackageQueryRefactorsError {
+            description("Couldn't query applied refactorings")
+        }
+        PackageQueryScriptsError {
+            description("Couldn't query executed scripts")
+        }
+        UnknownVariableError(name: String) {
+            description("Unknown variable")
+            display("Variable `{}` is not declared within the project", name)
+        }
+        MissingVariableError(name: String) {
+            description("Missing variable value")
+            display("No value was provided for variable `{}`", name)
+        }
+        UnresolvedVariablesError {
+            description("Package variables must be resolved before generating changes")
+        }
+        DeploymentHistoryQueryError {
+            description("Couldn't query deployment history")
+        }
+        DeploymentNotFoundError {
+            description("No deployment has been recorded within the target database")
+        }
+        DeploymentHistoryRecordError {
+            description("Couldn't record deployment history")
+            display("Changes were published, however the deployment couldn't be recorded within psqlpack.deployments")
+        }
         PackageCreationError(message: String) {
             description("Failed to create package")
             display("Failed to create package: {}", message)
@@ -94,6 +129,15 @@ error_chain! {
             description("Unsafe Operation")
             display("Couldn't publish database due to an unsafe operation: {}", message)
         }
+        PublishPhaseError(phase: usize, phases: usize, committed: usize) {
+            description("Couldn't publish database as a phase failed")
+            display("Couldn't publish database as phase {} of {} failed. Changes within this phase were not applied, \
+                     however {} change(s) from earlier phases were committed.", phase, phases, committed)
+        }
+        PublishVerifyError(change: String) {
+            description("Couldn't verify publish as a change failed")
+            display("Couldn't verify publish as a change failed: {}. All changes were rolled back.", change)
+        }
         GlobPatternError(err: PatternError) {
             description("An error in the glob pattern was found")
             display("An error in the glob pattern was found: {}", err)
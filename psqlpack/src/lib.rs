@@ -14,6 +14,7 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 #[macro_use]
 extern crate slog;
 extern crate slog_stdlog;
@@ -36,7 +37,9 @@ pub mod ast {
 pub use crate::connection::ConnectionBuilder;
 pub use crate::errors::{PsqlpackErrorKind, PsqlpackResult};
 pub use crate::model::{
-    template, Capabilities, Delta, Dependency, GenerationOptions, Package, Project, PublishProfile, Toggle,
+    template, Capabilities, ColumnConversion, Delta, Dependency, DeployScript, Deployment, Drift, DriftEntry,
+    DriftKind, GenerationOptions, ObjectType, Operation, Package, Project, PublishProfile, RefactorDefinition,
+    RefactorOperation, RenameConfidence, Report, ReportEntry, ReportFormat, Risk, Toggle, Variable, REPORT_VERSION,
 };
 pub use crate::semver::Semver;
 
@@ -5,10 +5,10 @@ use crate::ast::*;
 use crate::connection::Connection;
 use crate::errors::PsqlpackErrorKind::*;
 use crate::errors::{PsqlpackError, PsqlpackResult, PsqlpackResultExt};
-use crate::model::Extension;
+use crate::model::{Extension, RefactorDefinition};
 use crate::semver::Semver;
 use crate::sql::lexer;
-use crate::sql::parser::{FunctionArgumentListParser, FunctionReturnTypeParser, SqlTypeParser};
+use crate::sql::parser::{ExpressionParser, FunctionArgumentListParser, FunctionReturnTypeParser, SqlTypeParser};
 
 use postgres::rows::Row;
 use postgres::types::{FromSql, Type, TEXT};
@@ -69,6 +69,71 @@ impl Capabilities {
         }
     }
 
+    /// Returns the refactorings already applied to the database. These are recorded in the `psqlpack` schema which is
+    /// otherwise excluded from the catalog.
+    pub fn refactors(&self, conn: &PostgresConnection) -> PsqlpackResult<Vec<RefactorDefinition>> {
+        let exists = conn
+            .query(Q_REFACTOR_LOG_EXISTS, &[])
+            .chain_err(|| PackageQueryRefactorsError)?
+            .iter()
+            .any(|row| row.get::<_, bool>(0));
+        if !exists {
+            return Ok(Vec::new());
+        }
+        let mut refactors = Vec::new();
+        for row in &conn.query(Q_REFACTORS, &[]).chain_err(|| PackageQueryRefactorsError)? {
+            let definition: String = row.get(0);
+            refactors.push(serde_json::from_str(&definition).chain_err(|| PackageQueryRefactorsError)?);
+        }
+        Ok(refactors)
+    }
+
+    /// Returns the tracked deployment scripts already run against the database, recorded alongside the refactorings
+    pub fn executed_scripts(&self, conn: &PostgresConnection) -> PsqlpackResult<Vec<ExecutedScript>> {
+        let exists = conn
+            .query(Q_SCRIPT_LOG_EXISTS, &[])
+            .chain_err(|| PackageQueryScriptsError)?
+            .iter()
+            .any(|row| row.get::<_, bool>(0));
+        if !exists {
+            return Ok(Vec::new());
+        }
+        Ok(conn
+            .query(Q_EXECUTED_SCRIPTS, &[])
+            .chain_err(|| PackageQueryScriptsError)?
+            .iter()
+            .map(|row| ExecutedScript {
+                name: row.get(0),
+                hash: row.get(1),
+            })
+            .collect())
+    }
+
+    /// Returns the objects using an enum type that aren't modelled, and so can't be recreated if the type is replaced
+    pub fn type_dependents(&self, conn: &PostgresConnection) -> PsqlpackResult<Vec<TypeDependent>> {
+        Ok(conn
+            .query(Q_TYPE_DEPENDENTS, &[])
+            .chain_err(|| PackageQueryTypesError)?
+            .iter()
+            .map(|row| TypeDependent {
+                type_name: ObjectName {
+                    schema: Some(row.get(0)),
+                    name: row.get(1),
+                },
+                description: row.get(2),
+            })
+            .collect())
+    }
+
+    pub fn supports_enum_value_rename(&self) -> bool {
+        self.server_version >= Semver::new(10, 0, None)
+    }
+
+    // From PG12 a valid CHECK (col IS NOT NULL) constraint lets SET NOT NULL skip scanning the table
+    pub fn supports_not_null_from_check(&self) -> bool {
+        self.server_version >= Semver::new(12, 0, None)
+    }
+
     pub fn available_extensions(&self, name: &str, version: Option<Semver>) -> Vec<&Extension> {
         let mut available = self
             .extensions
@@ -140,8 +205,9 @@ impl DefinableCatalog for Capabilities {
             .query(&format!("{} {}", CTE_TABLES, Q_CTE_STANDARD), &[])
             .chain_err(|| PackageQueryTablesError)?;
         for row in query {
-            let table: TableDefinition = row.into();
-            tables.insert(table.name.to_string(), table);
+            // Columns and constraints refer to their table by its unquoted name
+            let fqn = format!("{}.{}", row.get::<_, String>(1), row.get::<_, String>(2));
+            tables.insert(fqn, TableDefinition::from(row));
         }
 
         // Get a list of columns and map them to the appropriate tables
@@ -227,8 +293,9 @@ impl<'a> DefinableCatalog for ExtensionCapabilities<'a> {
             .query(&format!("{} {}", CTE_TABLES, Q_CTE_EXTENSION), &[&self.extension.name])
             .chain_err(|| PackageQueryTablesError)?;
         for row in query {
-            let table: TableDefinition = row.into();
-            tables.insert(table.name.to_string(), table);
+            // Columns and constraints refer to their table by its unquoted name
+            let fqn = format!("{}.{}", row.get::<_, String>(1), row.get::<_, String>(2));
+            tables.insert(fqn, TableDefinition::from(row));
         }
 
         // Get a list of columns and map them to the appropriate tables
@@ -316,11 +383,39 @@ impl<'row> From<Row<'row>> for Extension {
     }
 }
 
-static Q_SCHEMAS: &'static str = "SELECT schema_name FROM information_schema.schemata
-                                  WHERE catalog_name = $1 AND schema_name !~* 'pg_|information_schema'";
+static Q_REFACTOR_LOG_EXISTS: &'static str = "SELECT to_regclass('psqlpack.refactor_log') IS NOT NULL";
+static Q_REFACTORS: &'static str = "SELECT definition FROM psqlpack.refactor_log ORDER BY applied_at";
+static Q_SCRIPT_LOG_EXISTS: &'static str = "SELECT to_regclass('psqlpack.script_log') IS NOT NULL";
+static Q_EXECUTED_SCRIPTS: &'static str = "SELECT name, hash FROM psqlpack.script_log ORDER BY executed_at";
+
+// Anything depending on an enum or its array type other than table columns, functions and column defaults, which are
+// handled when the type is replaced
+static Q_TYPE_DEPENDENTS: &'static str = "SELECT DISTINCT tn.nspname, t.typname,
+                                                 pg_describe_object(d.classid, d.objid, d.objsubid)
+                                          FROM pg_depend d
+                                          JOIN pg_type t ON t.oid = d.refobjid OR t.typarray = d.refobjid
+                                          JOIN pg_namespace tn ON tn.oid = t.typnamespace
+                                          LEFT JOIN pg_class c ON d.classid = 'pg_class'::regclass AND c.oid = d.objid
+                                          WHERE d.refclassid = 'pg_type'::regclass AND d.deptype = 'n'
+                                            AND t.typtype = 'e' AND tn.nspname !~* 'pg_|information_schema'
+                                            AND d.classid NOT IN ('pg_proc'::regclass, 'pg_attrdef'::regclass)
+                                            AND NOT COALESCE(c.relkind IN ('r', 'p') AND d.objsubid > 0, false)
+                                          ORDER BY 1, 2, 3";
+
+// Schemas created by an extension are excluded since they are managed by the extension
+static Q_SCHEMAS: &'static str = "SELECT schema_name, schema_owner FROM information_schema.schemata
+                                  WHERE catalog_name = $1 AND schema_name !~* 'pg_|information_schema|^psqlpack$'
+                                  AND NOT EXISTS (
+                                      SELECT 1 FROM pg_depend
+                                      JOIN pg_namespace ON pg_namespace.oid = pg_depend.objid
+                                      WHERE pg_depend.classid = 'pg_namespace'::regclass AND pg_depend.deptype = 'e'
+                                        AND pg_namespace.nspname = schema_name)";
 impl<'row> From<Row<'row>> for SchemaDefinition {
     fn from(row: Row) -> Self {
-        SchemaDefinition { name: row.get(0) }
+        SchemaDefinition {
+            name: row.get(0),
+            owner: row.get(1),
+        }
     }
 }
 
@@ -328,20 +423,20 @@ impl<'row> From<Row<'row>> for SchemaDefinition {
 // typcategory: https://www.postgresql.org/docs/9.6/catalog-pg-type.html#CATALOG-TYPCATEGORY-TABLE
 static CTE_TYPES: &'static str = "
     WITH cte AS (
-        SELECT pg_type.oid, typcategory, nspname, typname, array_agg(labels.enumlabel) AS enumlabels
+        SELECT pg_type.oid, typcategory, nspname, typname, array_agg(labels.enumlabel ORDER BY labels.enumsortorder) AS enumlabels,
+               pg_get_userbyid(typowner) AS owner
         FROM pg_type
         INNER JOIN pg_namespace ON pg_namespace.oid=typnamespace
         LEFT JOIN (
-            SELECT enumtypid, enumlabel
+            SELECT enumtypid, enumlabel, enumsortorder
             FROM pg_catalog.pg_enum
-            ORDER BY enumtypid, enumsortorder
         ) labels ON labels.enumtypid=pg_type.oid
         WHERE
             -- exclude pg schemas and information catalog
-            nspname !~* 'pg_|information_schema' AND
+            nspname !~* 'pg_|information_schema|^psqlpack$' AND
             -- Types beginning with _ are auto created (e.g. arrays)
             typname !~ '^_'
-        GROUP BY pg_type.oid, typcategory, nspname, typname
+        GROUP BY pg_type.oid, typcategory, nspname, typname, typowner
         ORDER BY pg_type.oid, typcategory, nspname, typname
     )
 ";
@@ -364,6 +459,7 @@ impl<'row> From<Row<'row>> for TypeDefinition {
         TypeDefinition {
             name: ObjectName { schema, name },
             kind,
+            owner: row.get(5),
         }
     }
 }
@@ -377,13 +473,14 @@ static CTE_FUNCTIONS: &'static str = "
             prosrc,
             pg_get_function_arguments(pg_proc.oid),
             lanname,
-            pg_get_function_result(pg_proc.oid)
+            pg_get_function_result(pg_proc.oid),
+            pg_get_userbyid(proowner) AS owner
         FROM pg_proc
         JOIN pg_namespace ON
             pg_namespace.oid = pg_proc.pronamespace
         JOIN pg_language ON
             pg_language.oid = pg_proc.prolang
-        WHERE nspname !~* 'pg_|information_schema' AND
+        WHERE nspname !~* 'pg_|information_schema|^psqlpack$' AND
             proname !~ '^_'
     )";
 
@@ -394,6 +491,7 @@ fn parse_function(row: &Row) -> PsqlpackResult<FunctionDefinition> {
     let raw_args: String = row.get(4);
     let lan_name: String = row.get(5);
     let raw_result: String = row.get(6);
+    let owner: String = row.get(7);
 
     // Parse some of the results
     let language = match &lan_name[..] {
@@ -440,6 +538,7 @@ fn parse_function(row: &Row) -> PsqlpackResult<FunctionDefinition> {
         return_type,
         body: function_src,
         language,
+        owner: Some(owner),
     })
 }
 
@@ -448,11 +547,12 @@ static CTE_TABLES: &'static str = "
         SELECT
             pg_class.oid,
             nspname,
-            relname
+            relname,
+            pg_get_userbyid(relowner) AS owner
         FROM pg_class
         JOIN pg_namespace ON pg_namespace.oid = pg_class.relnamespace
         WHERE pg_class.relkind='r' AND
-              nspname !~* 'pg_|information_schema'
+              nspname !~* 'pg_|information_schema|^psqlpack$'
     )";
 
 impl<'row> From<Row<'row>> for TableDefinition {
@@ -464,6 +564,7 @@ impl<'row> From<Row<'row>> for TableDefinition {
             },
             columns: Vec::new(),     // This gets loaded later
             constraints: Vec::new(), // This gets loaded later
+            owner: row.get(3),
         }
     }
 }
@@ -478,7 +579,7 @@ static CTE_COLUMNS: &'static str = "
             a.attnum as num,
             a.attname as name,
             CASE WHEN a.atttypid = ANY ('{int,int8,int2}'::regtype[])
-                  AND def.adsrc = 'nextval('''
+                  AND pg_get_expr(def.adbin, def.adrelid) = 'nextval('''
                         || (pg_get_serial_sequence (a.attrelid::regclass::text, a.attname))::regclass
                         || '''::regclass)'
                 THEN CASE a.atttypid
@@ -490,13 +591,13 @@ static CTE_COLUMNS: &'static str = "
             END AS data_type,
             a.attnotnull as notnull,
             coalesce(i.indisprimary,false) as primary_key,
-            def.adsrc as default
+            pg_get_expr(def.adbin, def.adrelid) as default
         FROM pg_attribute a
         INNER JOIN pg_class pgc ON pgc.oid = a.attrelid
         INNER JOIN pg_namespace ns ON ns.oid = pgc.relnamespace
         LEFT JOIN pg_index i ON pgc.oid = i.indrelid AND i.indkey[0] = a.attnum
         LEFT JOIN pg_attrdef def ON a.attrelid = def.adrelid AND a.attnum = def.adnum
-        WHERE attnum > 0 AND pgc.relkind='r' AND NOT a.attisdropped AND ns.nspname !~* 'pg_|information_schema'
+        WHERE attnum > 0 AND pgc.relkind='r' AND NOT a.attisdropped AND ns.nspname !~* 'pg_|information_schema|^psqlpack$'
         ORDER BY pgc.relname, a.attnum
     )";
 
@@ -506,7 +607,7 @@ impl<'row> From<Row<'row>> for ColumnDefinition {
         let mut constraints = Vec::new();
         let not_null: bool = row.get(7);
         let primary_key: bool = row.get(8);
-        // TODO: Default value + unique
+        // TODO: Unique
         constraints.push(if not_null {
             ColumnConstraint::NotNull
         } else {
@@ -515,11 +616,30 @@ impl<'row> From<Row<'row>> for ColumnDefinition {
         if primary_key {
             constraints.push(ColumnConstraint::PrimaryKey);
         }
-        let sql_type: String = row.get(6);
+        let sql_type: SqlType = row.get::<_, String>(6).into();
+
+        // Serial types imply their own default. Any default we can't parse is left unmanaged.
+        let default: Option<String> = row.get(9);
+        if let Some(default) = default {
+            let serial = matches!(
+                sql_type,
+                SqlType::Simple(SimpleSqlType::SmallSerial, _)
+                    | SqlType::Simple(SimpleSqlType::Serial, _)
+                    | SqlType::Simple(SimpleSqlType::BigSerial, _)
+            );
+            if !serial {
+                if let Some(expr) = lexer::tokenize_body(&default)
+                    .ok()
+                    .and_then(|tokens| ExpressionParser::new().parse(tokens).ok())
+                {
+                    constraints.push(ColumnConstraint::Default(expr));
+                }
+            }
+        }
 
         ColumnDefinition {
             name: row.get(5),
-            sql_type: sql_type.into(),
+            sql_type,
             constraints,
         }
     }
@@ -554,7 +674,7 @@ static CTE_TABLE_CONSTRAINTS: &'static str = "
         WHERE
             constraint_type in ('PRIMARY KEY','FOREIGN KEY')
         GROUP BY
-            oid,
+            tcls.oid,
              fqn,
             tc.constraint_schema,
             tc.table_name,
@@ -684,7 +804,7 @@ static CTE_INDEXES_94_THRU_96: &'static str = "
         JOIN pg_am AS am ON ic.relam = am.oid
         JOIN pg_namespace AS ns ON ic.relnamespace = ns.OID
         JOIN pg_class AS tc ON tc.oid = idx.indrelid
-        WHERE ns.nspname !~* 'pg_|information_schema' AND idx.indisprimary = false
+        WHERE ns.nspname !~* 'pg_|information_schema|^psqlpack$' AND idx.indisprimary = false
     )
 ";
 
@@ -717,7 +837,7 @@ static CTE_INDEXES: &'static str = "
         JOIN pg_am AS am ON ic.relam = am.oid
         JOIN pg_namespace AS ns ON ic.relnamespace = ns.OID
         JOIN pg_class AS tc ON tc.oid = idx.indrelid
-        WHERE ns.nspname !~* 'pg_|information_schema' AND idx.indisprimary = false
+        WHERE ns.nspname !~* 'pg_|information_schema|^psqlpack$' AND idx.indisprimary = false
     )
 ";
 
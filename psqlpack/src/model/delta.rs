@@ -3,19 +3,31 @@ use std::fmt;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
+use chrono::Utc;
+use postgres::error::DbError;
+use postgres::{self, Connection as PostgresConnection, GenericConnection};
+use rust_decimal::Decimal;
 use serde_json;
 use slog::Logger;
 
 use crate::connection::Connection;
 use crate::errors::PsqlpackErrorKind::*;
 use crate::errors::{PsqlpackResult, PsqlpackResultExt};
-use crate::model::{Capabilities, Dependency, Node, Package, PublishProfile, Toggle};
+use crate::model::history::record_deployment;
+use crate::model::{
+    Capabilities, ColumnConversion, Dependency, DriftEntry, DriftKind, Node, ObjectType, Operation, Package,
+    PublishProfile, RefactorDefinition, RefactorOperation, RenameConfidence, Report, ReportEntry, ReportFormat, Risk,
+    Toggle, REPORT_VERSION,
+};
 use crate::sql::ast::*;
+use crate::sql::quote::{quote_identifier, quote_literal};
 use crate::Semver;
 
 enum DbObject<'a> {
-    Column(&'a TableDefinition, &'a ColumnDefinition),
+    Column(&'a TableDefinition, &'a ColumnDefinition, Option<&'a ColumnConversion>),
     Constraint(&'a TableDefinition, &'a TableConstraint),
     ExtensionRequest(&'a Dependency), // 2
     Function(&'a FunctionDefinition), // 6 (ordered)
@@ -29,7 +41,7 @@ enum DbObject<'a> {
 impl<'a> fmt::Display for DbObject<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            DbObject::Column(table, column) => write!(f, "Table: {}, Column: {}", table.name, column.name),
+            DbObject::Column(table, column, _) => write!(f, "Table: {}, Column: {}", table.name, column.name),
             DbObject::Constraint(table, constraint) => {
                 write!(f, "Table: {}, Constraint: {}", table.name, constraint.name())
             }
@@ -55,6 +67,14 @@ trait Diffable<'a, T> {
     ) -> PsqlpackResult<()>;
 }
 
+// Ownership is only managed when the source explicitly declares an owner
+fn owner_changed(source: &Option<String>, target: Option<&Option<String>>) -> bool {
+    match *source {
+        Some(ref owner) => target.and_then(|t| t.as_ref()) != Some(owner),
+        None => false,
+    }
+}
+
 impl<'a> Diffable<'a, Package> for DbObject<'a> {
     fn generate(
         &self,
@@ -65,9 +85,10 @@ impl<'a> Diffable<'a, Package> for DbObject<'a> {
         log: &Logger,
     ) -> PsqlpackResult<()> {
         match *self {
-            DbObject::Column(table, column) => LinkedColumn {
+            DbObject::Column(table, column, conversion) => LinkedColumn {
                 table: &table,
                 column: &column,
+                conversion,
             }
             .generate(change_set, target, target_capabilities, publish_profile, log),
             DbObject::Constraint(table, constraint) => LinkedTableConstraint {
@@ -193,7 +214,7 @@ impl<'a> Diffable<'a, Package> for &'a FunctionDefinition {
     fn generate(
         &self,
         change_set: &mut Vec<ChangeInstruction<'a>>,
-        _target: &Package,
+        target: &Package,
         _target_capabilities: &Capabilities,
         _publish_profile: &PublishProfile,
         _log: &Logger,
@@ -202,6 +223,12 @@ impl<'a> Diffable<'a, Package> for &'a FunctionDefinition {
         // add it as is and rely on CREATE OR REPLACE. In the future, it'd
         // be good to check the hash or something to only do this when required
         change_set.push(ChangeInstruction::ModifyFunction(self));
+
+        // CREATE OR REPLACE retains the existing owner so we need to check it separately
+        let target_function = target.functions.iter().find(|f| f.name.eq(&self.name));
+        if owner_changed(&self.owner, target_function.map(|f| &f.owner)) {
+            change_set.push(ChangeInstruction::SetFunctionOwner(self));
+        }
         Ok(())
     }
 }
@@ -216,10 +243,13 @@ impl<'a> Diffable<'a, Package> for &'a SchemaDefinition {
         _log: &Logger,
     ) -> PsqlpackResult<()> {
         // Only add schema's, we do not drop them at this point
-        let schema_exists = target.schemas.iter().any(|s| s.name == self.name);
-        if !schema_exists {
+        let target_schema = target.schemas.iter().find(|s| s.name == self.name);
+        if target_schema.is_none() {
             change_set.push(ChangeInstruction::AddSchema(self));
         }
+        if owner_changed(&self.owner, target_schema.map(|s| &s.owner)) {
+            change_set.push(ChangeInstruction::SetSchemaOwner(self));
+        }
         Ok(())
     }
 }
@@ -228,12 +258,34 @@ impl<'a> Diffable<'a, Package> for &'a ScriptDefinition {
     fn generate(
         &self,
         change_set: &mut Vec<ChangeInstruction<'a>>,
-        _target: &Package,
+        target: &Package,
         _target_capabilities: &Capabilities,
         _publish_profile: &PublishProfile,
-        _log: &Logger,
+        log: &Logger,
     ) -> PsqlpackResult<()> {
+        let executed = target.executed_scripts.iter().find(|s| s.name == self.name);
+        let run = match self.run {
+            ScriptRunPolicy::Always => true,
+            ScriptRunPolicy::Once => executed.is_none(),
+            ScriptRunPolicy::OnChange => executed.map_or(true, |s| s.hash != self.hash()),
+        };
+        if !run {
+            trace!(log, "Skipping script that has already run"; "script" => &self.name);
+            return Ok(());
+        }
+
         change_set.push(ChangeInstruction::RunScript(self));
+        if self.run != ScriptRunPolicy::Always {
+            // The script log is created alongside the first script that needs recording
+            if !change_set
+                .iter()
+                .any(|c| matches!(c, ChangeInstruction::CreateScriptLog))
+            {
+                let position = change_set.len() - 1;
+                change_set.insert(position, ChangeInstruction::CreateScriptLog);
+            }
+            change_set.push(ChangeInstruction::RecordScript(self));
+        }
         Ok(())
     }
 }
@@ -254,7 +306,13 @@ impl<'a> Diffable<'a, Package> for &'a TableDefinition {
                 if !self.columns.iter().any(|src| tgt.name.eq(&src.name)) {
                     // Column in target but not in source
                     match publish_profile.generation_options.drop_columns {
-                        Toggle::Allow => change_set.push(ChangeInstruction::DropColumn(self, tgt.name.to_owned())),
+                        Toggle::Allow => {
+                            change_set.push(ChangeInstruction::CheckDataLoss(
+                                DataLoss::Column(self.name.clone(), tgt.name.to_owned()),
+                                publish_profile.generation_options.block_on_possible_data_loss,
+                            ));
+                            change_set.push(ChangeInstruction::DropColumn(self, tgt.name.to_owned()));
+                        }
                         Toggle::Error => {
                             bail!(PublishUnsafeOperationError(format!(
                                 "Unable to drop column as dropping columns is currently disabled: {}",
@@ -292,13 +350,19 @@ impl<'a> Diffable<'a, Package> for &'a TableDefinition {
                         }
                     };
                     if remove_ok {
-                        change_set.push(ChangeInstruction::DropConstraint(self, tgt.name().to_owned()));
+                        change_set.push(ChangeInstruction::DropConstraint(
+                            self.name.clone(),
+                            tgt.name().to_owned(),
+                        ));
                     }
                 }
             }
         } else {
             change_set.push(ChangeInstruction::AddTable(self));
         }
+        if owner_changed(&self.owner, table_result.map(|t| &t.owner)) {
+            change_set.push(ChangeInstruction::SetTableOwner(self));
+        }
         Ok(())
     }
 }
@@ -306,6 +370,7 @@ impl<'a> Diffable<'a, Package> for &'a TableDefinition {
 struct LinkedColumn<'a> {
     table: &'a TableDefinition,
     column: &'a ColumnDefinition,
+    conversion: Option<&'a ColumnConversion>,
 }
 
 impl<'a> Diffable<'a, Package> for LinkedColumn<'a> {
@@ -313,8 +378,8 @@ impl<'a> Diffable<'a, Package> for LinkedColumn<'a> {
         &self,
         change_set: &mut Vec<ChangeInstruction<'a>>,
         target: &Package,
-        _target_capabilities: &Capabilities,
-        _publish_profile: &PublishProfile,
+        target_capabilities: &Capabilities,
+        publish_profile: &PublishProfile,
         _log: &Logger,
     ) -> PsqlpackResult<()> {
         // We only generate items here if the table doesn't exist (for the time being)
@@ -326,7 +391,53 @@ impl<'a> Diffable<'a, Package> for LinkedColumn<'a> {
             if let Some(target_column) = target_column {
                 // Check the type
                 if !self.column.sql_type.eq(&target_column.sql_type) {
-                    change_set.push(ChangeInstruction::ModifyColumnType(self.table, &self.column));
+                    let using = self.conversion.and_then(|c| c.using.as_deref());
+                    let change = classify_type_change(&target_column.sql_type, &self.column.sql_type);
+                    let narrowing = matches!(change, TypeChange::Narrowing);
+                    let modify = match change {
+                        TypeChange::Widening => true,
+                        TypeChange::Narrowing => match publish_profile.generation_options.narrow_column_types {
+                            Toggle::Allow => true,
+                            Toggle::Error => bail!(PublishUnsafeOperationError(format!(
+                                "Attempted to narrow column {} on table {} from {} to {} however narrowing column \
+                                 types is currently disabled",
+                                self.column.name, self.table.name, target_column.sql_type, self.column.sql_type
+                            ))),
+                            _ => false,
+                        },
+                        TypeChange::Incompatible => {
+                            if using.is_none() {
+                                bail!(PublishInvalidOperationError(format!(
+                                    "Unable to convert column {} on table {} from {} to {} without a USING expression \
+                                     (see columnConversions)",
+                                    self.column.name, self.table.name, target_column.sql_type, self.column.sql_type
+                                )));
+                            }
+                            true
+                        }
+                    };
+                    if modify {
+                        // A conversion takes responsibility for how values are narrowed
+                        if using.is_none() && narrowing {
+                            change_set.push(ChangeInstruction::CheckDataLoss(
+                                DataLoss::Narrowing(
+                                    self.table.name.clone(),
+                                    self.column.name.to_owned(),
+                                    target_column.sql_type.clone(),
+                                    self.column.sql_type.clone(),
+                                ),
+                                publish_profile.generation_options.block_on_possible_data_loss,
+                            ));
+                        }
+                        change_set.push(ChangeInstruction::ModifyColumnType(self.table, self.column, using));
+                    }
+                }
+
+                // Defaults are compared separately since Postgres normalizes the stored expression
+                let src_default = column_default(self.column).map(normalize_expression);
+                let target_default = column_default(target_column).map(normalize_expression);
+                if src_default != target_default {
+                    change_set.push(ChangeInstruction::ModifyColumnDefault(self.table, self.column));
                 }
 
                 // Check column constraints
@@ -341,14 +452,26 @@ impl<'a> Diffable<'a, Package> for LinkedColumn<'a> {
                             // If it doesn't then it's likely implicitly implied to be null.
                             // Also, we only check not null as if null is specified then we've got nothing to change!
                             if self.column.constraints.iter().any(|c| ColumnConstraint::NotNull.eq(c)) {
-                                change_set.push(ChangeInstruction::ModifyColumnNull(self.table, &self.column));
+                                // Existing nulls are populated first, falling back to the column default
+                                let backfill = self
+                                    .conversion
+                                    .and_then(|c| c.backfill.clone())
+                                    .or_else(|| column_default(self.column).map(|expr| expr.to_string()));
+                                if let Some(backfill) = backfill {
+                                    change_set.push(ChangeInstruction::BackfillColumn(
+                                        self.table,
+                                        self.column,
+                                        backfill,
+                                    ));
+                                }
+                                set_not_null(change_set, self.table, self.column, target_capabilities);
                             }
                         }
                         ColumnConstraint::NotNull => {
                             change_set.push(ChangeInstruction::ModifyColumnNull(self.table, &self.column))
                         }
                         ColumnConstraint::Default(_) => {
-                            change_set.push(ChangeInstruction::ModifyColumnDefault(self.table, &self.column))
+                            // Handled above
                         }
                         ColumnConstraint::Unique => change_set.push(ChangeInstruction::ModifyColumnUniqueConstraint(
                             self.table,
@@ -361,6 +484,20 @@ impl<'a> Diffable<'a, Package> for LinkedColumn<'a> {
                 }
 
             // TODO: src_sec - target_set (e.g. what column constraints have been removed)
+            } else if let Some(backfill) = self.conversion.and_then(|c| c.backfill.as_ref()) {
+                // Existing rows are populated before the default and NOT NULL are applied
+                change_set.push(ChangeInstruction::AddNullableColumn(self.table, self.column));
+                change_set.push(ChangeInstruction::BackfillColumn(
+                    self.table,
+                    self.column,
+                    backfill.to_owned(),
+                ));
+                if column_default(self.column).is_some() {
+                    change_set.push(ChangeInstruction::ModifyColumnDefault(self.table, self.column));
+                }
+                if self.column.constraints.iter().any(|c| ColumnConstraint::NotNull.eq(c)) {
+                    set_not_null(change_set, self.table, self.column, target_capabilities);
+                }
             } else {
                 // Doesn't exist, add it
                 change_set.push(ChangeInstruction::AddColumn(self.table, &self.column));
@@ -370,6 +507,661 @@ impl<'a> Diffable<'a, Package> for LinkedColumn<'a> {
     }
 }
 
+// Adds a constraint to a table that already exists. Existing rows are checked separately when requested, so that
+// only a brief lock is needed to add the constraint itself.
+fn add_constraint<'a>(
+    change_set: &mut Vec<ChangeInstruction<'a>>,
+    table: &'a TableDefinition,
+    constraint: &'a TableConstraint,
+    publish_profile: &PublishProfile,
+) {
+    match *constraint {
+        TableConstraint::Foreign { ref name, .. } if publish_profile.generation_options.online_constraints => {
+            change_set.push(ChangeInstruction::AddConstraintNotValid(table, constraint));
+            change_set.push(ChangeInstruction::ValidateConstraint(table, name.to_owned()));
+        }
+        _ => change_set.push(ChangeInstruction::AddConstraint(table, constraint)),
+    }
+}
+
+// Where supported, the column is validated against a CHECK constraint outside of the transaction first so that
+// SET NOT NULL doesn't need to hold an exclusive lock while scanning the table
+fn set_not_null<'a>(
+    change_set: &mut Vec<ChangeInstruction<'a>>,
+    table: &'a TableDefinition,
+    column: &'a ColumnDefinition,
+    target_capabilities: &Capabilities,
+) {
+    if target_capabilities.supports_not_null_from_check() {
+        change_set.push(ChangeInstruction::AddNotNullCheck(table, column));
+        change_set.push(ChangeInstruction::ValidateNotNullCheck(table, column));
+        change_set.push(ChangeInstruction::ModifyColumnNull(table, column));
+        change_set.push(ChangeInstruction::DropNotNullCheck(table, column));
+    } else {
+        change_set.push(ChangeInstruction::ModifyColumnNull(table, column));
+    }
+}
+
+fn not_null_check_name(table: &TableDefinition, column: &ColumnDefinition) -> String {
+    format!("{}_{}_not_null", table.name.name, column.name)
+}
+
+/// How changing the type of a column affects the values already stored within it
+#[derive(Debug, PartialEq)]
+enum TypeChange {
+    /// Every existing value can be represented by the new type
+    Widening,
+    /// Existing values may be truncated, rounded or rejected by the new type
+    Narrowing,
+    /// Postgres has no implicit or assignment cast between the types so a `USING` expression is required
+    Incompatible,
+}
+
+fn classify_type_change(from: &SqlType, to: &SqlType) -> TypeChange {
+    use self::SimpleSqlType::*;
+    use self::TypeChange::*;
+
+    // Compares an optional limit where no limit is the widest
+    fn within(from: Option<u32>, to: Option<u32>) -> TypeChange {
+        match (from, to) {
+            (_, None) => Widening,
+            (Some(from), Some(to)) if from <= to => Widening,
+            _ => Narrowing,
+        }
+    }
+    fn string_length(sql_type: &SimpleSqlType) -> Option<Option<u32>> {
+        match *sql_type {
+            FixedLengthString(size) | VariableLengthString(size) => Some(Some(size)),
+            UnsizedVariableLengthString | Text => Some(None),
+            _ => None,
+        }
+    }
+    // The number of decimal digits that are guaranteed to be preserved
+    fn integer_digits(sql_type: &SimpleSqlType) -> Option<u32> {
+        match *sql_type {
+            SmallInteger | SmallSerial => Some(5),
+            Integer | Serial => Some(10),
+            BigInteger | BigSerial => Some(19),
+            _ => None,
+        }
+    }
+    fn float_digits(sql_type: &SimpleSqlType) -> Option<u32> {
+        match *sql_type {
+            Single => Some(6),
+            Double => Some(15),
+            _ => None,
+        }
+    }
+
+    let (from, to) = match (from, to) {
+        (&SqlType::Simple(ref from, from_dim), &SqlType::Simple(ref to, to_dim)) if from_dim == to_dim => (from, to),
+        // Anything can be converted to unbounded text
+        (&SqlType::Custom(_, _, from_dim), &SqlType::Simple(Text, to_dim))
+        | (&SqlType::Custom(_, _, from_dim), &SqlType::Simple(UnsizedVariableLengthString, to_dim))
+            if from_dim == to_dim =>
+        {
+            return Widening
+        }
+        _ => return Incompatible,
+    };
+    if let (Some(from_length), Some(to_length)) = (string_length(from), string_length(to)) {
+        return within(from_length, to_length);
+    }
+    if let Some(to_length) = string_length(to) {
+        return match to_length {
+            None => Widening,
+            Some(_) => Narrowing,
+        };
+    }
+    if let (Some(from_digits), Some(to_digits)) = (integer_digits(from), integer_digits(to)) {
+        return within(Some(from_digits), Some(to_digits));
+    }
+    match (from, to) {
+        (_, &Numeric(to)) if integer_digits(from).is_some() => within(
+            integer_digits(from),
+            to.map(|(precision, scale)| precision.saturating_sub(scale)),
+        ),
+        (_, _) if integer_digits(from).is_some() && float_digits(to).is_some() => {
+            within(integer_digits(from), float_digits(to))
+        }
+        (&Numeric(from), &Numeric(to)) => match (from, to) {
+            (_, None) => Widening,
+            (Some((from_precision, from_scale)), Some((to_precision, to_scale)))
+                if to_scale >= from_scale
+                    && to_precision.saturating_sub(to_scale) >= from_precision.saturating_sub(from_scale) =>
+            {
+                Widening
+            }
+            _ => Narrowing,
+        },
+        (&Single, &Double) => Widening,
+        (&Numeric(_), _) | (&Single, _) | (&Double, _)
+            if integer_digits(to).is_some() || float_digits(to).is_some() =>
+        {
+            Narrowing
+        }
+        (&Single, &Numeric(_)) | (&Double, &Numeric(_)) => Narrowing,
+        // Fixed length bit strings must match exactly so only varying lengths are comparable
+        (&FixedLengthBitString(from), &VariableLengthBitString(to))
+        | (&VariableLengthBitString(from), &VariableLengthBitString(to)) => within(Some(from), Some(to)),
+        (&Date, &DateTime(_)) | (&Date, &DateTimeWithTimeZone(_)) => Widening,
+        (&DateTime(_), &Date) | (&DateTimeWithTimeZone(_), &Date) => Narrowing,
+        (&DateTime(_), &Time(_)) | (&DateTimeWithTimeZone(_), &Time(_)) => Narrowing,
+        (&DateTimeWithTimeZone(_), &TimeWithTimeZone(_)) => Narrowing,
+        // Precision defaults to the maximum of 6
+        (&DateTime(from), &DateTime(to))
+        | (&DateTime(from), &DateTimeWithTimeZone(to))
+        | (&DateTimeWithTimeZone(from), &DateTimeWithTimeZone(to))
+        | (&Time(from), &Time(to))
+        | (&Time(from), &TimeWithTimeZone(to))
+        | (&TimeWithTimeZone(from), &TimeWithTimeZone(to)) => within(from.or(Some(6)), to.or(Some(6))),
+        (&DateTimeWithTimeZone(_), &DateTime(_)) | (&TimeWithTimeZone(_), &Time(_)) => Narrowing,
+        (&Interval(from_fields, from), &Interval(to_fields, to)) => {
+            if to_fields.is_some() && to_fields != from_fields {
+                Narrowing
+            } else {
+                within(from.or(Some(6)), to.or(Some(6)))
+            }
+        }
+        (&Jsonb, &Json) => Widening,
+        (&Json, &Jsonb) => Narrowing, // Whitespace, key order and duplicate keys are not preserved
+        (&Cidr, &Inet) => Widening,
+        (&Inet, &Cidr) => Narrowing,
+        _ => Incompatible,
+    }
+}
+
+fn refactor_sql(operation: &RefactorOperation) -> String {
+    match *operation {
+        RefactorOperation::RenameTable {
+            ref table,
+            ref new_name,
+        } => {
+            // Postgres doesn't rename constraints along with the table. Renaming the index of a primary
+            // key named by convention keeps it in line with the name psqlpack would otherwise generate.
+            let pkey = ObjectName {
+                schema: table.schema.clone(),
+                name: format!("{}_pkey", table.name),
+            };
+            format!(
+                "ALTER TABLE {} RENAME TO {};\nALTER INDEX IF EXISTS {} RENAME TO {}",
+                table,
+                quote_identifier(new_name),
+                pkey,
+                quote_identifier(&format!("{}_pkey", new_name))
+            )
+        }
+        RefactorOperation::RenameColumn {
+            ref table,
+            ref column,
+            ref new_name,
+        } => format!(
+            "ALTER TABLE {} RENAME COLUMN {} TO {}",
+            table,
+            quote_identifier(column),
+            quote_identifier(new_name)
+        ),
+        RefactorOperation::MoveSchema {
+            ref table,
+            ref new_schema,
+        } => format!("ALTER TABLE {} SET SCHEMA {}", table, quote_identifier(new_schema)),
+        RefactorOperation::RenameEnumValue {
+            ref type_name,
+            ref value,
+            ref new_name,
+        } => format!(
+            "ALTER TYPE {} RENAME VALUE {} TO {}",
+            type_name,
+            quote_literal(value),
+            quote_literal(new_name)
+        ),
+    }
+}
+
+// Checks the target for data that would be lost, raising an error or a warning if there is any
+fn data_loss_sql(loss: &DataLoss, block: bool) -> String {
+    let level = if block { "EXCEPTION" } else { "WARNING" };
+    match *loss {
+        DataLoss::Table(ref table) => format!(
+            "DO $$\nBEGIN\n  IF EXISTS (SELECT 1 FROM {}) THEN\n    RAISE {} USING MESSAGE = {};\n  END IF;\nEND\n$$",
+            table,
+            level,
+            quote_literal(&format!("Dropping table {} would lose data as it contains rows", table))
+        ),
+        DataLoss::Column(ref table, ref column) => format!(
+            "DO $$\nBEGIN\n  IF EXISTS (SELECT 1 FROM {} WHERE {} IS NOT NULL) THEN\n    \
+             RAISE {} USING MESSAGE = {};\n  END IF;\nEND\n$$",
+            table,
+            quote_identifier(column),
+            level,
+            quote_literal(&format!(
+                "Dropping column {} on table {} would lose data as it contains values",
+                column, table
+            ))
+        ),
+        // Values are converted and back again to find any that change. A conversion failing (e.g. an overflow) is
+        // also treated as data loss.
+        DataLoss::Narrowing(ref table, ref column, ref from, ref to) => format!(
+            "DO $$\nDECLARE\n  lost boolean;\nBEGIN\n  BEGIN\n    \
+             lost := EXISTS (SELECT 1 FROM {0} WHERE {1} IS DISTINCT FROM {1}::{2}::{3});\n  \
+             EXCEPTION WHEN others THEN\n    lost := true;\n  END;\n  IF lost THEN\n    \
+             RAISE {4} USING MESSAGE = {5};\n  END IF;\nEND\n$$",
+            table,
+            quote_identifier(column),
+            to,
+            from,
+            level,
+            quote_literal(&format!(
+                "Changing column {} on table {} from {} to {} would lose data as some values can't be converted",
+                column, table, from, to
+            ))
+        ),
+    }
+}
+
+// Postgres can't remove or reorder enum values so instead a replacement type is created, each dependent column is
+// converted to it via text and it then takes over the name of the original type. Functions can't be altered to use
+// the replacement so are dropped and recreated around the swap, which resets any privileges granted on them.
+fn replace_enum_sql(
+    ty: &TypeDefinition,
+    values: &[String],
+    removed: &[String],
+    columns: &[DependentColumn],
+    functions: &[FunctionDefinition],
+) -> String {
+    let replacement = ObjectName {
+        schema: ty.name.schema.clone(),
+        name: format!("{}__new", ty.name.name),
+    };
+    let mut statements = Vec::new();
+
+    // Fail before changing anything if a value being removed is still in use
+    if !removed.is_empty() && !columns.is_empty() {
+        let removed_values = removed.iter().map(|v| quote_literal(v)).collect::<Vec<_>>().join(", ");
+        let mut check = String::from("DO $$\nBEGIN\n");
+        for dependent in columns {
+            let column = quote_identifier(&dependent.column.name);
+            let condition = if column_dimensions(&dependent.column.sql_type).is_some() {
+                format!("{}::text[] && ARRAY[{}]", column, removed_values)
+            } else {
+                format!("{}::text IN ({})", column, removed_values)
+            };
+            let message = format!(
+                "Unable to remove enum value(s) from {} as they are still used by {}.{}",
+                ty.name, dependent.table, dependent.column.name
+            );
+            check.push_str(&format!(
+                "  IF EXISTS (SELECT 1 FROM {} WHERE {}) THEN\n    RAISE EXCEPTION USING MESSAGE = {};\n  END IF;\n",
+                dependent.table,
+                condition,
+                quote_literal(&message)
+            ));
+        }
+        check.push_str("END\n$$");
+        statements.push(check);
+    }
+
+    statements.push(format!(
+        "CREATE TYPE {} AS ENUM ({})",
+        replacement,
+        values.iter().map(|v| quote_literal(v)).collect::<Vec<_>>().join(", ")
+    ));
+    for function in functions {
+        statements.push(format!(
+            "DROP FUNCTION IF EXISTS {}({})",
+            function.name,
+            function_signature(function)
+        ));
+    }
+    for dependent in columns {
+        let column = quote_identifier(&dependent.column.name);
+        // Defaults are typed so won't be converted along with the column
+        if column_default(&dependent.column).is_some() {
+            statements.push(format!(
+                "ALTER TABLE {} ALTER COLUMN {} DROP DEFAULT",
+                dependent.table, column
+            ));
+        }
+        let dim = column_dimensions(&dependent.column.sql_type);
+        let new_type = SqlType::Custom(replacement.clone(), None, dim);
+        statements.push(format!(
+            "ALTER TABLE {} ALTER COLUMN {} TYPE {} USING {}::{}::{}",
+            dependent.table,
+            column,
+            new_type,
+            column,
+            SqlType::Simple(SimpleSqlType::Text, dim),
+            new_type
+        ));
+    }
+    statements.push(format!("DROP TYPE {}", ty.name));
+    statements.push(format!(
+        "ALTER TYPE {} RENAME TO {}",
+        replacement,
+        quote_identifier(&ty.name.name)
+    ));
+    for dependent in columns {
+        if let Some(default) = column_default(&dependent.column) {
+            // A default using a removed value can't be restored so is left for the column to redefine
+            let default = default.to_string();
+            if !removed.iter().any(|v| default.contains(&quote_literal(v))) {
+                statements.push(format!(
+                    "ALTER TABLE {} ALTER COLUMN {} SET DEFAULT {}",
+                    dependent.table,
+                    quote_identifier(&dependent.column.name),
+                    default
+                ));
+            }
+        }
+    }
+    for function in functions {
+        statements.push(function_sql(function));
+    }
+    statements.join(";\n")
+}
+
+fn column_dimensions(sql_type: &SqlType) -> Option<u32> {
+    match *sql_type {
+        SqlType::Simple(_, dim) | SqlType::Custom(_, _, dim) => dim,
+    }
+}
+
+fn function_sql(function: &FunctionDefinition) -> String {
+    let mut func = String::new();
+    func.push_str(&format!("CREATE OR REPLACE FUNCTION {} (", function.name)[..]);
+    let mut arg_comma_required = false;
+    for arg in &function.arguments {
+        if arg_comma_required {
+            func.push_str(", ");
+        } else {
+            arg_comma_required = true;
+        }
+
+        func.push_str(&arg.to_string());
+    }
+    func.push_str(")\n");
+    func.push_str("RETURNS ");
+    match function.return_type {
+        FunctionReturnType::Table(ref columns) => {
+            func.push_str("TABLE (\n");
+            let mut column_comma_required = false;
+            for column in columns {
+                if column_comma_required {
+                    func.push_str(",\n");
+                } else {
+                    column_comma_required = true;
+                }
+                func.push_str(&format!("  {} {}", quote_identifier(&column.name), column.sql_type)[..]);
+            }
+            func.push_str("\n)\n");
+        }
+        FunctionReturnType::SetOf(ref sql_type) => {
+            func.push_str(&format!("SETOF {}", sql_type)[..]);
+        }
+        FunctionReturnType::SqlType(ref sql_type) => {
+            func.push_str(&format!("{} ", sql_type)[..]);
+        }
+    }
+    func.push_str("AS $$");
+    func.push_str(&function.body[..]);
+    func.push_str("$$\n");
+    func.push_str("LANGUAGE ");
+    func.push_str(&function.language.to_string());
+    func
+}
+
+// Functions are identified by their signature. OUT arguments are ignored by PG.
+fn function_signature(function: &FunctionDefinition) -> String {
+    function
+        .arguments
+        .iter()
+        .filter(|arg| arg.mode != Some(FunctionArgumentMode::Out))
+        .map(|arg| match arg.mode {
+            Some(ref mode) => format!("{} {}", mode, arg.sql_type),
+            None => arg.sql_type.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn quote_identifiers(idents: &[String]) -> String {
+    idents
+        .iter()
+        .map(|i| quote_identifier(i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn uses_type(sql_type: &SqlType, type_name: &ObjectName) -> bool {
+    match *sql_type {
+        SqlType::Custom(ref name, _, _) => name.eq(type_name),
+        _ => false,
+    }
+}
+
+fn function_uses_type(function: &FunctionDefinition, type_name: &ObjectName) -> bool {
+    function.arguments.iter().any(|arg| uses_type(&arg.sql_type, type_name))
+        || match function.return_type {
+            FunctionReturnType::Table(ref columns) => columns.iter().any(|c| uses_type(&c.sql_type, type_name)),
+            FunctionReturnType::SetOf(ref sql_type) | FunctionReturnType::SqlType(ref sql_type) => {
+                uses_type(sql_type, type_name)
+            }
+        }
+}
+
+fn column_default(column: &ColumnDefinition) -> Option<&Expression> {
+    column.constraints.iter().find_map(|c| match *c {
+        ColumnConstraint::Default(ref expr) => Some(expr),
+        _ => None,
+    })
+}
+
+fn nullability(column: &ColumnDefinition) -> &'static str {
+    if column.constraints.iter().any(|c| ColumnConstraint::NotNull.eq(c)) {
+        "NOT NULL"
+    } else {
+        "NULL"
+    }
+}
+
+fn enum_values(ty: &TypeDefinition) -> Option<String> {
+    match ty.kind {
+        TypeDefinitionKind::Enum(ref values) => Some(values.join(", ")),
+        _ => None,
+    }
+}
+
+// Postgres adds casts to literals and may change the case of identifiers when storing an
+// expression, so we strip these to allow comparison against the source.
+fn normalize_expression(expr: &Expression) -> Expression {
+    fn without_cast(value: &AnyValue) -> AnyValue {
+        match *value {
+            AnyValue::Array(ref items, _) => AnyValue::Array(items.iter().map(without_cast).collect(), None),
+            AnyValue::Boolean(b, _) => AnyValue::Boolean(b, None),
+            AnyValue::Decimal(d, _) => AnyValue::Decimal(d, None),
+            AnyValue::Integer(i, _) => AnyValue::Integer(i, None),
+            // Negative and large numbers are stored as strings with a numeric cast
+            AnyValue::String(ref s, Some(SqlType::Simple(ref ty, None))) if is_numeric(ty) => match s.parse::<i64>() {
+                Ok(i) => AnyValue::Integer(i, None),
+                Err(_) => match s.parse::<Decimal>() {
+                    Ok(d) => AnyValue::Decimal(d, None),
+                    Err(_) => AnyValue::String(s.to_owned(), None),
+                },
+            },
+            AnyValue::String(ref s, _) => AnyValue::String(s.to_owned(), None),
+            AnyValue::Null(_) => AnyValue::Null(None),
+        }
+    }
+
+    fn is_numeric(sql_type: &SimpleSqlType) -> bool {
+        matches!(
+            *sql_type,
+            SimpleSqlType::SmallInteger
+                | SimpleSqlType::Integer
+                | SimpleSqlType::BigInteger
+                | SimpleSqlType::Numeric(_)
+                | SimpleSqlType::Double
+                | SimpleSqlType::Single
+        )
+    }
+
+    match *expr {
+        Expression::Value(ref value) => Expression::Value(without_cast(value)),
+        Expression::Identifier(ref ident) => Expression::Identifier(ident.to_lowercase()),
+        Expression::FunctionCall(ref name, ref args) => Expression::FunctionCall(
+            ObjectName {
+                schema: name.schema.as_ref().map(|s| s.to_lowercase()),
+                name: name.name.to_lowercase(),
+            },
+            args.iter().map(normalize_expression).collect(),
+        ),
+        Expression::BinaryOperation(ref left, op, ref right) => Expression::BinaryOperation(
+            Box::new(normalize_expression(left)),
+            op,
+            Box::new(normalize_expression(right)),
+        ),
+        Expression::Cast(ref inner, ref sql_type) => match **inner {
+            Expression::Value(ref value) => Expression::Value(without_cast(value)),
+            _ => Expression::Cast(Box::new(normalize_expression(inner)), sql_type.clone()),
+        },
+    }
+}
+
+/// A dropped and added table or column within the delta that look like they were intended to be a rename
+#[derive(Debug, Serialize)]
+pub struct RenameSuggestion {
+    pub operation: RefactorOperation,
+    pub confidence: RenameConfidence,
+}
+
+// Compares everything but the name. Nullable is implied, so an explicit NULL constraint is ignored.
+fn same_column_shape(source: &ColumnDefinition, target: &ColumnDefinition) -> bool {
+    let constraints = |column: &ColumnDefinition| {
+        column
+            .constraints
+            .iter()
+            .filter(|c| !matches!(c, ColumnConstraint::Null | ColumnConstraint::Default(_)))
+            .cloned()
+            .collect::<HashSet<_>>()
+    };
+    source.sql_type == target.sql_type
+        && constraints(source) == constraints(target)
+        && column_default(source).map(normalize_expression) == column_default(target).map(normalize_expression)
+}
+
+// Pairs dropped objects with added objects that match. If either side of a pair matches more than one object then
+// the pairing is ambiguous, otherwise confidence depends on whether there were other drops or adds to choose from.
+fn pair_renames<'a, T, F>(dropped: &[&'a T], added: &[&'a T], matches: F) -> Vec<(&'a T, &'a T, RenameConfidence)>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let mut pairs = Vec::new();
+    for &old in dropped {
+        let candidates = added.iter().filter(|new| matches(old, new)).collect::<Vec<_>>();
+        for &&new in &candidates {
+            let ambiguous = candidates.len() > 1 || dropped.iter().filter(|other| matches(other, new)).count() > 1;
+            let confidence = if ambiguous {
+                RenameConfidence::Low
+            } else if dropped.len() == 1 && added.len() == 1 {
+                RenameConfidence::High
+            } else {
+                RenameConfidence::Medium
+            };
+            pairs.push((old, new, confidence));
+        }
+    }
+    pairs
+}
+
+fn detect_renames(source: &Package, target: &Package) -> Vec<RenameSuggestion> {
+    let mut suggestions = Vec::new();
+
+    // Tables are matched on their column set. We only consider a rename within a schema, or a move between schemas.
+    let dropped = target
+        .tables
+        .iter()
+        .filter(|t| !source.tables.iter().any(|s| s.name.eq(&t.name)))
+        .collect::<Vec<_>>();
+    let added = source
+        .tables
+        .iter()
+        .filter(|s| !target.tables.iter().any(|t| t.name.eq(&s.name)))
+        .collect::<Vec<_>>();
+    let same_table = |old: &TableDefinition, new: &TableDefinition| {
+        (old.name.schema == new.name.schema || old.name.name == new.name.name)
+            && old.columns.len() == new.columns.len()
+            && new.columns.iter().all(|n| {
+                old.columns
+                    .iter()
+                    .any(|o| o.name.eq(&n.name) && same_column_shape(n, o))
+            })
+    };
+    for (old, new, confidence) in pair_renames(&dropped, &added, same_table) {
+        let operation = if old.name.schema == new.name.schema {
+            RefactorOperation::RenameTable {
+                table: old.name.clone(),
+                new_name: new.name.name.to_owned(),
+            }
+        } else {
+            RefactorOperation::MoveSchema {
+                table: old.name.clone(),
+                new_schema: new.name.schema.clone().unwrap_or_default(),
+            }
+        };
+        suggestions.push(RenameSuggestion { operation, confidence });
+    }
+
+    // Columns are matched on type and constraints within tables that exist on both sides
+    for source_table in &source.tables {
+        let target_table = match target.tables.iter().find(|t| t.name.eq(&source_table.name)) {
+            Some(target_table) => target_table,
+            None => continue,
+        };
+        let dropped = target_table
+            .columns
+            .iter()
+            .filter(|t| !source_table.columns.iter().any(|s| s.name.eq(&t.name)))
+            .collect::<Vec<_>>();
+        let added = source_table
+            .columns
+            .iter()
+            .filter(|s| !target_table.columns.iter().any(|t| t.name.eq(&s.name)))
+            .collect::<Vec<_>>();
+        for (old, new, confidence) in pair_renames(&dropped, &added, |old, new| same_column_shape(new, old)) {
+            suggestions.push(RenameSuggestion {
+                operation: RefactorOperation::RenameColumn {
+                    table: target_table.name.clone(),
+                    column: old.name.to_owned(),
+                    new_name: new.name.to_owned(),
+                },
+                confidence,
+            });
+        }
+    }
+
+    suggestions
+}
+
+// Tables can only be moved into a schema that already exists
+fn ensure_schema_for_move<'a>(
+    change_set: &mut Vec<ChangeInstruction<'a>>,
+    package: &'a Package,
+    target: &mut Package,
+    operation: &RefactorOperation,
+) {
+    if let RefactorOperation::MoveSchema { ref new_schema, .. } = *operation {
+        if !target.schemas.iter().any(|s| s.name.eq(new_schema)) {
+            if let Some(schema) = package.schemas.iter().find(|s| s.name.eq(new_schema)) {
+                change_set.push(ChangeInstruction::AddSchema(schema));
+                target.push_schema(SchemaDefinition {
+                    name: new_schema.to_owned(),
+                    owner: None,
+                });
+            }
+        }
+    }
+}
+
 struct LinkedTableConstraint<'a> {
     table: &'a TableDefinition,
     constraint: &'a TableConstraint,
@@ -496,15 +1288,15 @@ impl<'a> Diffable<'a, Package> for LinkedTableConstraint<'a> {
                     };
                     if remove_ok {
                         change_set.push(ChangeInstruction::DropConstraint(
-                            self.table,
+                            self.table.name.clone(),
                             self.constraint.name().to_owned(),
                         ));
-                        change_set.push(ChangeInstruction::AddConstraint(self.table, self.constraint));
+                        add_constraint(change_set, self.table, self.constraint, publish_profile);
                     }
                 }
             } else {
                 // Doesn't exist, add it
-                change_set.push(ChangeInstruction::AddConstraint(self.table, &self.constraint));
+                add_constraint(change_set, self.table, self.constraint, publish_profile);
             }
         } else {
             change_set.push(ChangeInstruction::AddConstraint(self.table, &self.constraint));
@@ -549,11 +1341,61 @@ impl<'a> Diffable<'a, Package> for &'a TypeDefinition {
     ) -> PsqlpackResult<()> {
         let ty = target.types.iter().find(|t| t.name == self.name);
         if let Some(ty) = ty {
-            self.generate(change_set, ty, _target_capabilities, publish_profile, log)
+            let start = change_set.len();
+            self.generate(change_set, ty, _target_capabilities, publish_profile, log)?;
+
+            // Replacing a type requires every column and function using it to be moved across to the new type
+            for instruction in &mut change_set[start..] {
+                if let ChangeInstruction::ModifyType(
+                    _,
+                    TypeModificationAction::ReplaceEnumValues {
+                        ref mut columns,
+                        ref mut functions,
+                        ..
+                    },
+                ) = *instruction
+                {
+                    // Anything else using the type would stop the original type from being dropped
+                    let dependents = target
+                        .type_dependents
+                        .iter()
+                        .filter(|d| d.type_name == self.name)
+                        .map(|d| d.description.to_owned())
+                        .collect::<Vec<_>>();
+                    if !dependents.is_empty() {
+                        bail!(PublishUnsafeOperationError(format!(
+                            "Unable to replace the values of enum {} as it is used by {}. These must be dropped \
+                             before publishing and recreated afterwards.",
+                            self.name,
+                            dependents.join(", ")
+                        )));
+                    }
+                    for table in &target.tables {
+                        for column in &table.columns {
+                            if uses_type(&column.sql_type, &self.name) {
+                                columns.push(DependentColumn {
+                                    table: table.name.clone(),
+                                    column: column.clone(),
+                                });
+                            }
+                        }
+                    }
+                    functions.extend(
+                        target
+                            .functions
+                            .iter()
+                            .filter(|f| function_uses_type(f, &self.name))
+                            .cloned(),
+                    );
+                }
+            }
         } else {
             change_set.push(ChangeInstruction::AddType(self));
-            Ok(())
         }
+        if owner_changed(&self.owner, ty.map(|t| &t.owner)) {
+            change_set.push(ChangeInstruction::SetTypeOwner(self));
+        }
+        Ok(())
     }
 }
 
@@ -578,27 +1420,51 @@ impl<'a> Diffable<'a, TypeDefinition> for &'a TypeDefinition {
                 match target.kind {
                     TypeDefinitionKind::Enum(ref target_values) => {
                         // Detect if anything needs to be deleted in the target
-                        let mut to_delete = target_values
+                        let mut removed = target_values
                             .iter()
                             .filter(|v| !source_values.contains(v))
-                            .map(|v| TypeModificationAction::RemoveEnumValue { value: v.to_owned() })
+                            .cloned()
                             .collect::<Vec<_>>();
-                        if !to_delete.is_empty() {
+                        if !removed.is_empty() {
                             match publish_profile.generation_options.drop_enum_values {
-                                Toggle::Allow => {
-                                    change_set
-                                        .extend(to_delete.drain(..).map(|d| ChangeInstruction::ModifyType(self, d)));
-                                }
+                                Toggle::Allow => {}
                                 Toggle::Error => {
                                     bail!(PublishUnsafeOperationError(format!(
                                         "Unable to remove enum value(s) as unsafe operations are disabled: {:?}",
-                                        to_delete
+                                        removed
                                     )));
                                 }
-                                _ => {}
+                                _ => removed.clear(),
                             }
                         }
 
+                        // Postgres can neither remove nor reorder enum values so for either of these we need to
+                        // replace the type entirely.
+                        let reordered = source_values
+                            .iter()
+                            .filter(|v| target_values.contains(v))
+                            .ne(target_values.iter().filter(|v| source_values.contains(v)));
+                        if !removed.is_empty() || reordered {
+                            // Values that are being ignored rather than removed are kept at the end
+                            let mut values = source_values.clone();
+                            values.extend(
+                                target_values
+                                    .iter()
+                                    .filter(|v| !source_values.contains(v) && !removed.contains(v))
+                                    .cloned(),
+                            );
+                            change_set.push(ChangeInstruction::ModifyType(
+                                self,
+                                TypeModificationAction::ReplaceEnumValues {
+                                    values,
+                                    removed,
+                                    columns: Vec::new(),
+                                    functions: Vec::new(),
+                                },
+                            ));
+                            return Ok(());
+                        }
+
                         // Our working group after items being deleted
                         let mut working = target_values
                             .iter()
@@ -641,8 +1507,13 @@ impl<'a> Diffable<'a, TypeDefinition> for &'a TypeDefinition {
     }
 }
 
+// The changes, along with the package they were generated from and the target they were generated against
 #[derive(Debug)]
-pub struct Delta<'package>(Vec<ChangeInstruction<'package>>);
+pub struct Delta<'package>(
+    Vec<ChangeInstruction<'package>>,
+    Option<&'package Package>,
+    Option<Package>,
+);
 
 impl<'package> Delta<'package> {
     pub fn generate(
@@ -655,6 +1526,11 @@ impl<'package> Delta<'package> {
     ) -> PsqlpackResult<Delta<'package>> {
         let log = log.new(o!("delta" => "generate"));
 
+        // Placeholders would otherwise be published verbatim
+        if !package.variables.is_empty() {
+            bail!(UnresolvedVariablesError);
+        }
+
         // Start the change_set
         let mut change_set = Vec::new();
 
@@ -666,7 +1542,7 @@ impl<'package> Delta<'package> {
         }
 
         // For an empty database use an empty package, but also push a CREATE DB instruction
-        let target_package = match target {
+        let mut target_package = match target {
             Some(target_package) => target_package,
             None => {
                 change_set.push(ChangeInstruction::CreateDatabase(target_database_name.to_owned()));
@@ -677,6 +1553,58 @@ impl<'package> Delta<'package> {
         // Set the connection instruction
         change_set.push(ChangeInstruction::UseDatabase(target_database_name.to_owned()));
 
+        // Refactorings are applied to the target model before anything else so that renamed objects are diffed
+        // against their new names rather than being dropped and recreated
+        let pending_refactors = package
+            .refactors
+            .iter()
+            .filter(|refactor| {
+                !target_package
+                    .refactors
+                    .iter()
+                    .any(|applied| applied.id.eq(&refactor.id))
+            })
+            .collect::<Vec<_>>();
+        if !pending_refactors.is_empty() {
+            change_set.push(ChangeInstruction::CreateRefactorLog);
+        }
+        for refactor in pending_refactors {
+            ensure_schema_for_move(&mut change_set, package, &mut target_package, &refactor.operation);
+
+            // Enum values can only be renamed from PG10 onwards. Prior to this the value is left to be replaced.
+            if let RefactorOperation::RenameEnumValue { .. } = refactor.operation {
+                if !target_capabilities.supports_enum_value_rename() {
+                    warn!(log, "Unable to rename enum value on this version of PostgreSQL"; "id" => &refactor.id);
+                    change_set.push(ChangeInstruction::RecordRefactor(refactor));
+                    continue;
+                }
+            }
+
+            // If the object no longer exists (e.g. a new database) then there is nothing to apply
+            if target_package.apply_refactor(&refactor.operation) {
+                trace!(log, "Applying refactoring"; "id" => &refactor.id);
+                change_set.push(ChangeInstruction::ApplyRefactor(refactor));
+            }
+            change_set.push(ChangeInstruction::RecordRefactor(refactor));
+        }
+
+        // Anything that still looks like a rename is either suggested, or accepted if confident enough
+        let accept_renames = publish_profile.generation_options.accept_renames;
+        if publish_profile.generation_options.detect_renames || accept_renames.is_some() {
+            for suggestion in detect_renames(package, &target_package) {
+                let accepted = accept_renames.map_or(false, |minimum| suggestion.confidence >= minimum);
+                if accepted {
+                    ensure_schema_for_move(&mut change_set, package, &mut target_package, &suggestion.operation);
+                    if target_package.apply_refactor(&suggestion.operation) {
+                        trace!(log, "Accepting detected rename"; "operation" => suggestion.operation.to_string());
+                        change_set.push(ChangeInstruction::AcceptRename(suggestion));
+                        continue;
+                    }
+                }
+                change_set.push(ChangeInstruction::SuggestRename(suggestion));
+            }
+        }
+
         // Create the build order - including all document types outside the topological sort.
         let mut build_order = Vec::new();
 
@@ -719,28 +1647,133 @@ impl<'package> Delta<'package> {
             }
         }
 
-        // Drop functions next - first figure out if there are any to drop
-        for function in &target_package.functions {
-            if !package.functions.iter().any(|t| t.name.eq(&function.name)) {
-                match publish_profile.generation_options.drop_functions {
-                    Toggle::Allow => change_set.push(ChangeInstruction::DropFunction(function.name.to_string())),
+        // Functions and tables are dropped in reverse dependency order so that anything referencing an object, such
+        // as a foreign key from another table, is dropped before the object itself
+        let dropped_table = |name: &ObjectName| !package.tables.iter().any(|t| t.name.eq(name));
+        let drop_tables = matches!(publish_profile.generation_options.drop_tables, Toggle::Allow);
+        let mut dropped_constraints = Vec::new();
+        for node in target_package.generate_dependency_graph(&log)?.into_iter().rev() {
+            match node {
+                Node::Function(function) if !package.functions.iter().any(|f| f.name.eq(&function.name)) => {
+                    match publish_profile.generation_options.drop_functions {
+                        Toggle::Allow => change_set.push(ChangeInstruction::DropFunction(function.name.to_string())),
+                        Toggle::Error => bail!(PublishUnsafeOperationError(format!(
+                            "Attempted to drop function {} however dropping functions is currently disabled",
+                            function.name
+                        ))),
+                        _ => {}
+                    }
+                }
+                Node::Table(table) if dropped_table(&table.name) => {
+                    match publish_profile.generation_options.drop_tables {
+                        Toggle::Allow => {
+                            change_set.push(ChangeInstruction::CheckDataLoss(
+                                DataLoss::Table(table.name.clone()),
+                                publish_profile.generation_options.block_on_possible_data_loss,
+                            ));
+                            change_set.push(ChangeInstruction::DropTable(table.name.to_string()));
+                        }
+                        Toggle::Error => bail!(PublishUnsafeOperationError(format!(
+                            "Attempted to drop table {} however dropping tables is currently disabled",
+                            table.name
+                        ))),
+                        _ => {}
+                    }
+                }
+                Node::Constraint(
+                    table,
+                    TableConstraint::Foreign {
+                        ref name,
+                        ref ref_table,
+                        ..
+                    },
+                ) if drop_tables && table.name.ne(ref_table) && dropped_table(ref_table) => {
+                    // A table that is being kept can only lose the foreign key if dropping them is allowed
+                    if !dropped_table(&table.name)
+                        && !matches!(
+                            publish_profile.generation_options.drop_foreign_key_constraints,
+                            Toggle::Allow
+                        )
+                    {
+                        bail!(PublishUnsafeOperationError(format!(
+                            "Unable to drop table {} as it is referenced by constraint {} on table {} and dropping FKs \
+                             is currently disabled",
+                            ref_table, name, table.name
+                        )));
+                    }
+                    change_set.push(ChangeInstruction::DropConstraint(table.name.clone(), name.to_owned()));
+                    dropped_constraints.push((table.name.clone(), name.to_owned()));
+                }
+                _ => {}
+            }
+        }
+
+        // Anything dropped above no longer exists for the remaining instructions to depend upon
+        for (table_name, name) in dropped_constraints {
+            if let Some(table) = target_package.tables.iter_mut().find(|t| t.name.eq(&table_name)) {
+                table.constraints.retain(|c| c.name() != name);
+            }
+        }
+        if matches!(publish_profile.generation_options.drop_functions, Toggle::Allow) {
+            target_package
+                .functions
+                .retain(|function| package.functions.iter().any(|f| f.name.eq(&function.name)));
+        }
+        if matches!(publish_profile.generation_options.drop_tables, Toggle::Allow) {
+            target_package
+                .tables
+                .retain(|table| package.tables.iter().any(|t| t.name.eq(&table.name)));
+        }
+
+        // Types and schemas are dropped once everything else has been applied since tables and functions may depend
+        // on them until they have been dropped or modified
+        let mut late_drops = Vec::new();
+        for ty in &target_package.types {
+            if !package.types.iter().any(|t| t.name.eq(&ty.name)) {
+                match publish_profile.generation_options.drop_types {
+                    Toggle::Allow => {
+                        // A table that isn't being dropped would prevent the type from being dropped
+                        let tables_dropped = matches!(publish_profile.generation_options.drop_tables, Toggle::Allow);
+                        let dependent = target_package.tables.iter().find(|table| {
+                            !tables_dropped
+                                && !package.tables.iter().any(|t| t.name.eq(&table.name))
+                                && table.columns.iter().any(|c| uses_type(&c.sql_type, &ty.name))
+                        });
+                        if let Some(table) = dependent {
+                            bail!(PublishUnsafeOperationError(format!(
+                                "Unable to drop type {} as it is still used by table {}",
+                                ty.name, table.name
+                            )));
+                        }
+                        // Likewise for a function that isn't being dropped
+                        let dependent = target_package.functions.iter().find(|function| {
+                            !package.functions.iter().any(|f| f.name.eq(&function.name))
+                                && function_uses_type(function, &ty.name)
+                        });
+                        if let Some(function) = dependent {
+                            bail!(PublishUnsafeOperationError(format!(
+                                "Unable to drop type {} as it is still used by function {}",
+                                ty.name, function.name
+                            )));
+                        }
+                        late_drops.push(ChangeInstruction::DropType(ty.name.to_string()));
+                    }
                     Toggle::Error => bail!(PublishUnsafeOperationError(format!(
-                        "Attempted to drop function {} however dropping functions is currently disabled",
-                        function.name
+                        "Attempted to drop type {} however dropping types is currently disabled",
+                        ty.name
                     ))),
                     _ => {}
                 }
             }
         }
-
-        // Drop tables next - first figure out if there are any to drop
-        for table in &target_package.tables {
-            if !package.tables.iter().any(|t| t.name.eq(&table.name)) {
-                match publish_profile.generation_options.drop_tables {
-                    Toggle::Allow => change_set.push(ChangeInstruction::DropTable(table.name.to_string())),
+        for schema in &target_package.schemas {
+            // The public schema is created with every database so is never dropped
+            if schema.name != "public" && !package.schemas.iter().any(|s| s.name.eq(&schema.name)) {
+                match publish_profile.generation_options.drop_schemas {
+                    Toggle::Allow => late_drops.push(ChangeInstruction::DropSchema(schema.name.to_owned())),
                     Toggle::Error => bail!(PublishUnsafeOperationError(format!(
-                        "Attempted to drop table {} however dropping tables is currently disabled",
-                        table.name
+                        "Attempted to drop schema {} however dropping schemas is currently disabled",
+                        schema.name
                     ))),
                     _ => {}
                 }
@@ -757,7 +1790,11 @@ impl<'package> Delta<'package> {
                     build_order.push(DbObject::Table(table));
                 }
                 Node::Column(table, column) => {
-                    build_order.push(DbObject::Column(table, column));
+                    let conversion = package
+                        .conversions
+                        .iter()
+                        .find(|c| c.table.eq(&table.name) && c.column.eq(&column.name));
+                    build_order.push(DbObject::Column(table, column, conversion));
                 }
                 Node::Constraint(table, constraint) => {
                     build_order.push(DbObject::Constraint(table, constraint));
@@ -791,50 +1828,549 @@ impl<'package> Delta<'package> {
                 &log,
             )?;
         }
+        change_set.extend(late_drops);
 
-        Ok(Delta(change_set))
+        Ok(Delta(change_set, Some(package), Some(target_package)))
     }
 
-    pub fn apply(&self, log: &Logger, connection: &Connection) -> PsqlpackResult<()> {
+    pub fn apply(&self, log: &Logger, connection: &Connection, publish_profile: &PublishProfile) -> PsqlpackResult<()> {
         let log = log.new(o!("delta" => "apply"));
+        let options = &publish_profile.generation_options;
+        let started_at = Utc::now();
 
-        let change_set = &self.0;
+        // Each phase is committed before moving onto the next
+        let phases = self.phases();
+        let total = phases.iter().filter(|phase| !matches!(phase, Phase::Connect)).count();
+        let mut current = 0;
+        let mut committed = 0;
 
         // These instructions turn into SQL statements that get executed
         let mut conn = connection.connect_host()?;
+        configure_session(&log, &conn, publish_profile)?;
 
-        for change in change_set.iter() {
-            if let ChangeInstruction::UseDatabase(..) = *change {
+        for phase in &phases {
+            if let Phase::Connect = *phase {
                 conn.finish().chain_err(|| DatabaseConnectionFinishError)?;
                 conn = connection.connect_database()?;
+                configure_session(&log, &conn, publish_profile)?;
                 continue;
             }
+            current += 1;
 
-            // Execute SQL directly
-            trace!(log, "Executing: {}", change);
-            let sql = change.to_sql(&log);
-            conn.batch_execute(&sql).chain_err(|| DatabaseExecuteError(sql))?;
-        }
-
-        // Close the connection
-        conn.finish().chain_err(|| DatabaseConnectionFinishError)?;
-
-        Ok(())
-    }
-
-    pub fn write_report(&self, destination: &Path) -> PsqlpackResult<()> {
-        let change_set = &self.0;
+            // A phase that times out waiting on a lock is retried after any partial changes it left are cleaned up
+            let mut attempt = 0;
+            loop {
+                let result = if attempt == 0 {
+                    phase.execute(&log, &conn)
+                } else {
+                    phase.retry(&log, &conn)
+                };
+                match result {
+                    Ok(()) => break,
+                    Err((_, ref e))
+                        if attempt < options.lock_timeout_retries
+                            && e.code() == Some(&postgres::error::LOCK_NOT_AVAILABLE) =>
+                    {
+                        let delay = options.lock_timeout_retry_delay.saturating_mul(1 << attempt.min(16));
+                        warn!(
+                            log,
+                            "Phase {} of {} timed out waiting for a lock, retrying in {}ms", current, total, delay
+                        );
+                        thread::sleep(Duration::from_millis(delay));
+                        attempt += 1;
+                    }
+                    Err((sql, e)) => {
+                        return Err(e)
+                            .chain_err(|| DatabaseExecuteError(sql))
+                            .chain_err(|| PublishPhaseError(current, total, committed));
+                    }
+                }
+            }
+            committed += phase.len();
+            debug!(log, "Committed phase {} of {}", current, total; "changes" => phase.len());
+        }
+
+        // Once everything has been committed the publish is recorded within the deployment history
+        if let Some(package) = self.1 {
+            let applied_sql = self
+                .0
+                .iter()
+                .filter(|change| {
+                    !matches!(
+                        change,
+                        ChangeInstruction::SuggestRename(..)
+                            | ChangeInstruction::Irreversible(..)
+                            | ChangeInstruction::UseDatabase(..)
+                    )
+                })
+                .map(|change| format!("{};\n", change.to_sql(&log)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            record_deployment(&conn, package, publish_profile, started_at, &applied_sql)?;
+            debug!(log, "Recorded deployment"; "hash" => package.hash());
+        }
+
+        // Close the connection
+        conn.finish().chain_err(|| DatabaseConnectionFinishError)?;
+
+        Ok(())
+    }
+
+    pub fn verify(
+        &self,
+        log: &Logger,
+        connection: &Connection,
+        publish_profile: &PublishProfile,
+    ) -> PsqlpackResult<()> {
+        let log = log.new(o!("delta" => "verify"));
+
+        // Creating or dropping a database can't happen within a transaction, let alone be rolled back
+        if self.0.iter().any(|change| {
+            matches!(
+                change,
+                ChangeInstruction::CreateDatabase(_) | ChangeInstruction::DropDatabase(_)
+            )
+        }) {
+            bail!(PublishError(
+                "Unable to verify changes that create or drop the target database".into()
+            ));
+        }
+
+        let conn = connection.connect_database()?;
+        configure_session(&log, &conn, publish_profile)?;
+
+        // Every change is executed within a single transaction which is always rolled back
+        let transaction = conn.transaction().chain_err(|| DatabaseExecuteError("BEGIN".into()))?;
+        let mut verified = 0;
+        let mut unverifiable = 0;
+        let mut added_values: Vec<&str> = Vec::new();
+        for change in &self.0 {
+            let result = match *change {
+                ChangeInstruction::SuggestRename(..)
+                | ChangeInstruction::Irreversible(..)
+                | ChangeInstruction::UseDatabase(..) => continue,
+                // Prior to PG12 enum values can't be added within a transaction. The savepoint lets us skip these
+                // without aborting the transaction. Either way the value can't be used until it is committed.
+                ChangeInstruction::ModifyType(_, TypeModificationAction::AddEnumValueBefore { ref value, .. })
+                | ChangeInstruction::ModifyType(_, TypeModificationAction::AddEnumValueAfter { ref value, .. }) => {
+                    let savepoint = transaction
+                        .savepoint("psqlpack_verify")
+                        .chain_err(|| DatabaseExecuteError("SAVEPOINT".into()))?;
+                    match execute_change(&log, &savepoint, change) {
+                        Err((_, ref e)) if e.code() == Some(&postgres::error::ACTIVE_SQL_TRANSACTION) => {
+                            warn!(log, "Unable to verify within a transaction, skipping: {}", change);
+                            added_values.push(value);
+                            continue;
+                        }
+                        result => {
+                            added_values.push(value);
+                            result.and_then(|_| savepoint.commit().map_err(|e| ("RELEASE".to_owned(), e)))
+                        }
+                    }
+                }
+                _ if added_values.is_empty() => verify_change(&log, &transaction, change),
+                // Any change using an enum value added above would fail, even though it would succeed once
+                // published. These are reported as unverifiable rather than as a failure.
+                _ => {
+                    let savepoint = transaction
+                        .savepoint("psqlpack_verify")
+                        .chain_err(|| DatabaseExecuteError("SAVEPOINT".into()))?;
+                    match verify_change(&log, &savepoint, change) {
+                        Err((ref sql, _)) if added_values.iter().any(|v| sql.contains(&quote_literal(v))) => {
+                            warn!(
+                                log,
+                                "Unable to verify as it uses an enum value added within the transaction: {}", change
+                            );
+                            unverifiable += 1;
+                            continue;
+                        }
+                        result => result.and_then(|_| savepoint.commit().map_err(|e| ("RELEASE".to_owned(), e))),
+                    }
+                }
+            };
+            if let Err((sql, e)) = result {
+                return Err(e)
+                    .chain_err(|| DatabaseExecuteError(sql))
+                    .chain_err(|| PublishVerifyError(change.to_string()));
+            }
+            verified += 1;
+        }
+
+        transaction.set_rollback();
+        transaction
+            .finish()
+            .chain_err(|| DatabaseExecuteError("ROLLBACK".into()))?;
+        conn.finish().chain_err(|| DatabaseConnectionFinishError)?;
+
+        info!(log, "Verified {} change(s), all of which were rolled back", verified);
+        if unverifiable > 0 {
+            warn!(
+                log,
+                "Unable to verify {} change(s) using enum values added within the transaction", unverifiable
+            );
+        }
+        Ok(())
+    }
+
+    /// Generates the change set that reverts this delta, using the package that was published over as the state to
+    /// return to. Changes that can't be reverted, such as data that has been dropped, are flagged as irreversible.
+    pub fn rollback<'before>(&self, before: Option<&'before Package>) -> Delta<'before>
+    where
+        'package: 'before,
+    {
+        let mut change_set = Vec::new();
+
+        // A database that was dropped can't be brought back. If it was only created then dropping it reverts everything.
+        for change in &self.0 {
+            match *change {
+                ChangeInstruction::DropDatabase(..) => {
+                    change_set.push(ChangeInstruction::Irreversible(change.to_string()))
+                }
+                ChangeInstruction::CreateDatabase(ref db) => {
+                    change_set.push(ChangeInstruction::DropDatabase(db.to_owned()))
+                }
+                _ => {}
+            }
+        }
+        let before = match before {
+            Some(before) if change_set.is_empty() => before,
+            _ => return Delta(change_set, None, None),
+        };
+
+        // Objects that were explicitly dropped are restored individually rather than alongside their table
+        let dropped_constraints: Vec<_> = self
+            .0
+            .iter()
+            .filter_map(|change| match *change {
+                ChangeInstruction::DropConstraint(ref table, ref name) => Some((table, name)),
+                _ => None,
+            })
+            .collect();
+        let dropped_indexes: Vec<_> = self
+            .0
+            .iter()
+            .filter_map(|change| match *change {
+                ChangeInstruction::DropIndex(ref name, _) => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        let find_table = |name: &ObjectName| before.tables.iter().find(|t| t.name.eq(name));
+        let find_column = |table: &ObjectName, name: &str| {
+            find_table(table).and_then(|t| t.columns.iter().find(|c| c.name == name).map(|c| (t, c)))
+        };
+
+        for change in self.0.iter().rev() {
+            let inverse = match *change {
+                ChangeInstruction::UseDatabase(..)
+                | ChangeInstruction::DropDatabase(..)
+                | ChangeInstruction::CreateDatabase(..) => continue,
+
+                // Only ever informational, or undone by reverting the change they accompany
+                ChangeInstruction::SuggestRename(..)
+                | ChangeInstruction::CheckDataLoss(..)
+                | ChangeInstruction::Irreversible(..)
+                | ChangeInstruction::CreateRefactorLog
+                | ChangeInstruction::CreateScriptLog
+                | ChangeInstruction::RecordScript(..)
+                | ChangeInstruction::AddNotNullCheck(..)
+                | ChangeInstruction::ValidateNotNullCheck(..)
+                | ChangeInstruction::DropNotNullCheck(..)
+                | ChangeInstruction::ValidateConstraint(..)
+                | ChangeInstruction::ModifyColumnUniqueConstraint(..)
+                | ChangeInstruction::ModifyColumnPrimaryKeyConstraint(..) => continue,
+
+                // Schema
+                ChangeInstruction::AddSchema(schema) => vec![ChangeInstruction::DropSchema(schema.name.to_owned())],
+                ChangeInstruction::SetSchemaOwner(schema) => before
+                    .schemas
+                    .iter()
+                    .find(|s| s.name == schema.name && s.owner.is_some())
+                    .map(|s| vec![ChangeInstruction::SetSchemaOwner(s)])
+                    .unwrap_or_default(),
+                ChangeInstruction::DropSchema(ref name) => before
+                    .schemas
+                    .iter()
+                    .find(|s| s.name.eq(name))
+                    .map(|s| {
+                        let mut changes = vec![ChangeInstruction::AddSchema(s)];
+                        if s.owner.is_some() {
+                            changes.push(ChangeInstruction::SetSchemaOwner(s));
+                        }
+                        changes
+                    })
+                    .unwrap_or_default(),
+
+                // Types
+                ChangeInstruction::AddType(ty) => vec![ChangeInstruction::DropType(ty.name.to_string())],
+                ChangeInstruction::SetTypeOwner(ty) => before
+                    .types
+                    .iter()
+                    .find(|t| t.name.eq(&ty.name) && t.owner.is_some())
+                    .map(|t| vec![ChangeInstruction::SetTypeOwner(t)])
+                    .unwrap_or_default(),
+                ChangeInstruction::DropType(ref name) => before
+                    .types
+                    .iter()
+                    .find(|t| t.name.to_string().eq(name))
+                    .map(|t| {
+                        let mut changes = vec![ChangeInstruction::AddType(t)];
+                        if t.owner.is_some() {
+                            changes.push(ChangeInstruction::SetTypeOwner(t));
+                        }
+                        changes
+                    })
+                    .unwrap_or_default(),
+
+                // Tables
+                ChangeInstruction::AddTable(table) => vec![ChangeInstruction::DropTable(table.name.to_string())],
+                ChangeInstruction::SetTableOwner(table) => find_table(&table.name)
+                    .filter(|t| t.owner.is_some())
+                    .map(|t| vec![ChangeInstruction::SetTableOwner(t)])
+                    .unwrap_or_default(),
+                ChangeInstruction::DropTable(ref name) => before
+                    .tables
+                    .iter()
+                    .find(|t| t.name.to_string().eq(name))
+                    .map(|t| {
+                        let mut changes = vec![
+                            ChangeInstruction::Irreversible(format!("Data within table {} is not restored", name)),
+                            ChangeInstruction::AddTable(t),
+                        ];
+                        if t.owner.is_some() {
+                            changes.push(ChangeInstruction::SetTableOwner(t));
+                        }
+                        for constraint in &t.constraints {
+                            if !dropped_constraints.contains(&(&t.name, &constraint.name().to_owned())) {
+                                changes.push(ChangeInstruction::AddConstraint(t, constraint));
+                            }
+                        }
+                        for index in before.indexes.iter().filter(|i| i.table.eq(&t.name)) {
+                            if !dropped_indexes.contains(&&index.fully_qualified_name()) {
+                                changes.push(ChangeInstruction::AddIndex(index, false));
+                            }
+                        }
+                        changes
+                    })
+                    .unwrap_or_default(),
+
+                // Columns
+                ChangeInstruction::AddColumn(table, column) | ChangeInstruction::AddNullableColumn(table, column) => {
+                    vec![ChangeInstruction::DropColumn(table, column.name.to_owned())]
+                }
+                ChangeInstruction::ModifyColumnType(table, column, using) => find_column(&table.name, &column.name)
+                    .map(|(t, c)| {
+                        // Reverting the type is a type change of its own, so may lose data or need a conversion
+                        let mut changes = Vec::new();
+                        match classify_type_change(&column.sql_type, &c.sql_type) {
+                            TypeChange::Incompatible => {
+                                return vec![ChangeInstruction::Irreversible(format!(
+                                    "Column {} on table {} can't be converted back from {} to {} without a conversion",
+                                    c.name, t.name, column.sql_type, c.sql_type
+                                ))];
+                            }
+                            TypeChange::Narrowing => changes.push(ChangeInstruction::CheckDataLoss(
+                                DataLoss::Narrowing(
+                                    t.name.clone(),
+                                    c.name.to_owned(),
+                                    column.sql_type.clone(),
+                                    c.sql_type.clone(),
+                                ),
+                                true,
+                            )),
+                            TypeChange::Widening => {}
+                        }
+                        if let Some(using) = using {
+                            changes.push(ChangeInstruction::Irreversible(format!(
+                                "Values of column {} on table {} converted using {} are not restored",
+                                c.name, t.name, using
+                            )));
+                        }
+                        changes.push(ChangeInstruction::ModifyColumnType(t, c, None));
+                        changes
+                    })
+                    .unwrap_or_default(),
+                ChangeInstruction::ModifyColumnNull(table, column) => find_column(&table.name, &column.name)
+                    .map(|(t, c)| vec![ChangeInstruction::ModifyColumnNull(t, c)])
+                    .unwrap_or_default(),
+                ChangeInstruction::ModifyColumnDefault(table, column) => find_column(&table.name, &column.name)
+                    .map(|(t, c)| vec![ChangeInstruction::ModifyColumnDefault(t, c)])
+                    .unwrap_or_default(),
+                ChangeInstruction::DropColumn(table, ref name) => find_column(&table.name, name)
+                    .map(|(t, c)| {
+                        vec![
+                            ChangeInstruction::Irreversible(format!(
+                                "Data within column {} on table {} is not restored",
+                                name, t.name
+                            )),
+                            ChangeInstruction::AddColumn(t, c),
+                        ]
+                    })
+                    .unwrap_or_default(),
+
+                // Constraints
+                ChangeInstruction::AddConstraint(table, constraint)
+                | ChangeInstruction::AddConstraintNotValid(table, constraint) => {
+                    vec![ChangeInstruction::DropConstraint(
+                        table.name.clone(),
+                        constraint.name().to_owned(),
+                    )]
+                }
+                ChangeInstruction::DropConstraint(ref table, ref name) => find_table(table)
+                    .and_then(|t| t.constraints.iter().find(|c| c.name() == name).map(|c| (t, c)))
+                    .map(|(t, c)| vec![ChangeInstruction::AddConstraint(t, c)])
+                    .unwrap_or_default(),
+
+                // Indexes
+                ChangeInstruction::AddIndex(index, concurrently) => {
+                    vec![ChangeInstruction::DropIndex(index.fully_qualified_name(), concurrently)]
+                }
+                ChangeInstruction::DropIndex(ref name, concurrently) => before
+                    .indexes
+                    .iter()
+                    .find(|i| i.fully_qualified_name().eq(name))
+                    .map(|i| vec![ChangeInstruction::AddIndex(i, concurrently)])
+                    .unwrap_or_default(),
+
+                // Functions are always replaced, so only those that didn't exist before are dropped
+                ChangeInstruction::AddFunction(function) | ChangeInstruction::ModifyFunction(function) => {
+                    match before.functions.iter().find(|f| f.name.eq(&function.name)) {
+                        Some(f) => vec![ChangeInstruction::ModifyFunction(f)],
+                        None => vec![ChangeInstruction::DropFunction(function.name.to_string())],
+                    }
+                }
+                ChangeInstruction::SetFunctionOwner(function) => before
+                    .functions
+                    .iter()
+                    .find(|f| f.name.eq(&function.name) && f.owner.is_some())
+                    .map(|f| vec![ChangeInstruction::SetFunctionOwner(f)])
+                    .unwrap_or_default(),
+                ChangeInstruction::DropFunction(ref name) => before
+                    .functions
+                    .iter()
+                    .find(|f| f.name.to_string().eq(name))
+                    .map(|f| {
+                        let mut changes = vec![ChangeInstruction::AddFunction(f)];
+                        if f.owner.is_some() {
+                            changes.push(ChangeInstruction::SetFunctionOwner(f));
+                        }
+                        changes
+                    })
+                    .unwrap_or_default(),
+
+                // Renames are reverted by renaming back. The refactoring is also forgotten by the target so that it is
+                // applied again by the next publish.
+                ChangeInstruction::ApplyRefactor(refactor) => {
+                    vec![ChangeInstruction::RevertRefactor(refactor.operation.inverse())]
+                }
+                ChangeInstruction::AcceptRename(ref suggestion) => {
+                    vec![ChangeInstruction::RevertRefactor(suggestion.operation.inverse())]
+                }
+                ChangeInstruction::RecordRefactor(refactor) => vec![ChangeInstruction::ForgetRefactor(refactor)],
+                ChangeInstruction::RevertRefactor(ref operation) => {
+                    vec![ChangeInstruction::RevertRefactor(operation.inverse())]
+                }
+                ChangeInstruction::ForgetRefactor(..) => continue,
+
+                // Scripts, extensions, backfills and enum changes have no inverse we can generate
+                ChangeInstruction::RunScript(..)
+                | ChangeInstruction::CreateExtension(..)
+                | ChangeInstruction::UpgradeExtension(..)
+                | ChangeInstruction::BackfillColumn(..)
+                | ChangeInstruction::ModifyType(..) => Vec::new(),
+            };
+
+            // Anything we couldn't find an inverse for is flagged
+            if inverse.is_empty() {
+                change_set.push(ChangeInstruction::Irreversible(change.to_string()));
+            } else {
+                change_set.extend(inverse);
+            }
+        }
 
-        File::create(destination)
-            .chain_err(|| GenerationError("Failed to generate report".to_owned()))
-            .and_then(|writer| {
-                serde_json::to_writer_pretty(writer, &change_set)
-                    .chain_err(|| GenerationError("Failed to generate report".to_owned()))
-            })?;
+        // The database needs to be connected to prior to anything else
+        if let Some(ChangeInstruction::UseDatabase(ref db)) =
+            self.0.iter().find(|c| matches!(c, ChangeInstruction::UseDatabase(..)))
+        {
+            change_set.insert(0, ChangeInstruction::UseDatabase(db.to_owned()));
+        }
+        Delta(change_set, None, None)
+    }
+
+    // Groups the instructions into phases. Consecutive instructions are run within a single transaction, however
+    // those that can't be run within a transaction are run on their own.
+    fn phases(&self) -> Vec<Phase<'_, 'package>> {
+        let mut phases = Vec::new();
+        let mut pending = Vec::new();
+        for change in &self.0 {
+            match *change {
+                // Suggestions and irreversible changes are informational only
+                ChangeInstruction::SuggestRename(..) | ChangeInstruction::Irreversible(..) => continue,
+                ChangeInstruction::UseDatabase(..) => {}
+                _ if change.is_transactional() => {
+                    pending.push(change);
+                    continue;
+                }
+                _ => {}
+            }
+            if !pending.is_empty() {
+                phases.push(Phase::Transaction(pending));
+                pending = Vec::new();
+            }
+            phases.push(match *change {
+                ChangeInstruction::UseDatabase(..) => Phase::Connect,
+                _ => Phase::Standalone(change),
+            });
+        }
+        if !pending.is_empty() {
+            phases.push(Phase::Transaction(pending));
+        }
+        phases
+    }
+
+    /// Describes each change for a deployment report, using the package being published over to find the previous
+    /// value of anything that is modified.
+    pub fn report(&self, log: &Logger, before: Option<&Package>) -> Report {
+        Report {
+            version: REPORT_VERSION,
+            changes: self.0.iter().map(|change| change.report_entry(log, before)).collect(),
+        }
+    }
+
+    pub fn write_report(
+        &self,
+        log: &Logger,
+        before: Option<&Package>,
+        format: ReportFormat,
+        destination: &Path,
+    ) -> PsqlpackResult<()> {
+        let report = self.report(log, before);
+
+        let mut writer =
+            File::create(destination).chain_err(|| GenerationError("Failed to generate report".to_owned()))?;
+        match format {
+            ReportFormat::Json => serde_json::to_writer_pretty(writer, &report)
+                .chain_err(|| GenerationError("Failed to generate report".to_owned()))?,
+            ReportFormat::Markdown => writer
+                .write_all(report.to_markdown().as_bytes())
+                .chain_err(|| GenerationError("Failed to generate report".to_owned()))?,
+            ReportFormat::Html => writer
+                .write_all(report.to_html().as_bytes())
+                .chain_err(|| GenerationError("Failed to generate report".to_owned()))?,
+        }
 
         Ok(())
     }
 
+    // Describes the drift of the database this delta was generated against from the package it was generated from.
+    // Each change reverts an out of band change, so the values within the target are the values before the change.
+    pub(crate) fn drift(&self, log: &Logger) -> Vec<DriftEntry> {
+        self.0
+            .iter()
+            .filter_map(|change| change.drift_entry(log, self.2.as_ref()))
+            .collect()
+    }
+
     pub fn write_sql(&self, log: &Logger, destination: &Path) -> PsqlpackResult<()> {
         let change_set = &self.0;
 
@@ -862,6 +2398,103 @@ impl<'package> Delta<'package> {
     }
 }
 
+enum Phase<'a, 'input> {
+    Connect,
+    Standalone(&'a ChangeInstruction<'input>),
+    Transaction(Vec<&'a ChangeInstruction<'input>>),
+}
+
+impl<'a, 'input> Phase<'a, 'input> {
+    fn len(&self) -> usize {
+        match *self {
+            Phase::Connect => 0,
+            Phase::Standalone(_) => 1,
+            Phase::Transaction(ref changes) => changes.len(),
+        }
+    }
+
+    // On failure, returns the SQL that failed alongside the error
+    fn execute(&self, log: &Logger, conn: &PostgresConnection) -> Result<(), (String, postgres::Error)> {
+        let changes = match *self {
+            Phase::Connect => return Ok(()),
+            Phase::Standalone(change) => return execute_change(log, conn, change),
+            Phase::Transaction(ref changes) => changes,
+        };
+        let transaction = conn.transaction().map_err(|e| ("BEGIN".to_owned(), e))?;
+        for change in changes {
+            execute_change(log, &transaction, change)?;
+        }
+        transaction.commit().map_err(|e| ("COMMIT".to_owned(), e))
+    }
+
+    // A transaction is rolled back when it fails, however a concurrent index build that fails leaves an invalid
+    // index behind which has to be dropped before building it again
+    fn retry(&self, log: &Logger, conn: &PostgresConnection) -> Result<(), (String, postgres::Error)> {
+        if let Phase::Standalone(ChangeInstruction::AddIndex(index, true)) = *self {
+            execute_change(
+                log,
+                conn,
+                &ChangeInstruction::DropIndex(index.fully_qualified_name(), true),
+            )?;
+        }
+        self.execute(log, conn)
+    }
+}
+
+fn execute_change<C: GenericConnection>(
+    log: &Logger,
+    conn: &C,
+    change: &ChangeInstruction,
+) -> Result<(), (String, postgres::Error)> {
+    trace!(log, "Executing: {}", change);
+    let sql = change.to_sql(log);
+    conn.batch_execute(&sql).map_err(|e| (sql, e))
+}
+
+// Concurrent index operations can't run within a transaction, so are verified without
+fn verify_change<C: GenericConnection>(
+    log: &Logger,
+    conn: &C,
+    change: &ChangeInstruction,
+) -> Result<(), (String, postgres::Error)> {
+    match *change {
+        ChangeInstruction::AddIndex(index, true) => {
+            execute_change(log, conn, &ChangeInstruction::AddIndex(index, false))
+        }
+        ChangeInstruction::DropIndex(ref name, true) => {
+            execute_change(log, conn, &ChangeInstruction::DropIndex(name.clone(), false))
+        }
+        _ => execute_change(log, conn, change),
+    }
+}
+
+// Session settings persist across transactions, so are applied once per connection
+fn configure_session(log: &Logger, conn: &PostgresConnection, publish_profile: &PublishProfile) -> PsqlpackResult<()> {
+    // Warnings raised by the server, such as possible data loss, are reported through the log
+    let notices = log.clone();
+    conn.set_notice_handler(Box::new(move |notice: DbError| {
+        if notice.severity == "WARNING" {
+            warn!(notices, "{}", notice.message);
+        } else {
+            debug!(notices, "{}", notice.message; "severity" => &notice.severity);
+        }
+    }));
+
+    let options = &publish_profile.generation_options;
+    let mut settings = Vec::new();
+    if let Some(timeout) = options.lock_timeout {
+        settings.push(format!("SET lock_timeout = {}", timeout));
+    }
+    if let Some(timeout) = options.statement_timeout {
+        settings.push(format!("SET statement_timeout = {}", timeout));
+    }
+    if settings.is_empty() {
+        return Ok(());
+    }
+    let sql = settings.join(";\n");
+    conn.batch_execute(&sql).chain_err(|| DatabaseExecuteError(sql))
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub enum ChangeInstruction<'input> {
@@ -876,32 +2509,61 @@ pub enum ChangeInstruction<'input> {
 
     // Schema
     AddSchema(&'input SchemaDefinition),
-    //DropSchema(String),
+    SetSchemaOwner(&'input SchemaDefinition),
+    DropSchema(String),
 
     // Scripts
     RunScript(&'input ScriptDefinition),
+    CreateScriptLog,
+    RecordScript(&'input ScriptDefinition),
+
+    // Refactorings
+    CreateRefactorLog,
+    ApplyRefactor(&'input RefactorDefinition),
+    RecordRefactor(&'input RefactorDefinition),
+    AcceptRename(RenameSuggestion),
+    SuggestRename(RenameSuggestion),
+    RevertRefactor(RefactorOperation),
+    ForgetRefactor(&'input RefactorDefinition),
 
     // Types
     AddType(&'input TypeDefinition),
     ModifyType(&'input TypeDefinition, TypeModificationAction),
+    SetTypeOwner(&'input TypeDefinition),
     DropType(String),
 
     // Tables
     AddTable(&'input TableDefinition),
+    SetTableOwner(&'input TableDefinition),
     DropTable(String),
 
     // Columns
     AddColumn(&'input TableDefinition, &'input ColumnDefinition),
-    ModifyColumnType(&'input TableDefinition, &'input ColumnDefinition),
+    ModifyColumnType(&'input TableDefinition, &'input ColumnDefinition, Option<&'input str>),
     ModifyColumnNull(&'input TableDefinition, &'input ColumnDefinition),
     ModifyColumnDefault(&'input TableDefinition, &'input ColumnDefinition),
     ModifyColumnUniqueConstraint(&'input TableDefinition, &'input ColumnDefinition),
     ModifyColumnPrimaryKeyConstraint(&'input TableDefinition, &'input ColumnDefinition),
     DropColumn(&'input TableDefinition, String),
 
+    // Populating a column before it is made NOT NULL
+    AddNullableColumn(&'input TableDefinition, &'input ColumnDefinition),
+    BackfillColumn(&'input TableDefinition, &'input ColumnDefinition, String),
+    AddNotNullCheck(&'input TableDefinition, &'input ColumnDefinition),
+    ValidateNotNullCheck(&'input TableDefinition, &'input ColumnDefinition),
+    DropNotNullCheck(&'input TableDefinition, &'input ColumnDefinition),
+
+    // Data loss checks, either failing or warning when data would be lost
+    CheckDataLoss(DataLoss, bool),
+
+    // Changes within a rollback that can't be reverted
+    Irreversible(String),
+
     // Constraints
     AddConstraint(&'input TableDefinition, &'input TableConstraint),
-    DropConstraint(&'input TableDefinition, String),
+    AddConstraintNotValid(&'input TableDefinition, &'input TableConstraint),
+    ValidateConstraint(&'input TableDefinition, String),
+    DropConstraint(ObjectName, String),
 
     // Index
     AddIndex(&'input IndexDefinition, bool),
@@ -910,15 +2572,54 @@ pub enum ChangeInstruction<'input> {
     // Functions
     AddFunction(&'input FunctionDefinition),
     ModifyFunction(&'input FunctionDefinition), // This is identical to add however it's for future possible support
+    SetFunctionOwner(&'input FunctionDefinition),
     DropFunction(String),
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub enum TypeModificationAction {
-    AddEnumValueBefore { value: String, before: String },
-    AddEnumValueAfter { value: String, after: String },
-    RemoveEnumValue { value: String },
+    AddEnumValueBefore {
+        value: String,
+        before: String,
+    },
+    AddEnumValueAfter {
+        value: String,
+        after: String,
+    },
+    ReplaceEnumValues {
+        values: Vec<String>,
+        removed: Vec<String>,
+        columns: Vec<DependentColumn>,
+        functions: Vec<FunctionDefinition>,
+    },
+}
+
+/// Data within the target that would be lost by a change
+#[derive(Debug, Serialize)]
+pub enum DataLoss {
+    Table(ObjectName),
+    Column(ObjectName, String),
+    Narrowing(ObjectName, String, SqlType, SqlType),
+}
+
+impl fmt::Display for DataLoss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DataLoss::Table(ref table) => write!(f, "table {}", table),
+            DataLoss::Column(ref table, ref column) => write!(f, "column {} on table {}", column, table),
+            DataLoss::Narrowing(ref table, ref column, ref from, ref to) => {
+                write!(f, "column {} on table {} from {} to {}", column, table, from, to)
+            }
+        }
+    }
+}
+
+/// A column that needs to be moved across when its type is replaced
+#[derive(Debug, Serialize)]
+pub struct DependentColumn {
+    pub table: ObjectName,
+    pub column: ColumnDefinition,
 }
 
 impl<'input> fmt::Display for ChangeInstruction<'input> {
@@ -949,10 +2650,26 @@ impl<'input> fmt::Display for ChangeInstruction<'input> {
 
             // Schema
             AddSchema(schema) => write!(f, "Add schema: {}", schema.name),
-            //DropSchema(String),
+            SetSchemaOwner(schema) => write!(f, "Set owner for schema: {}", schema.name),
+            DropSchema(ref schema_name) => write!(f, "Drop schema: {}", schema_name),
 
             // Scripts
             RunScript(script) => write!(f, "Run script: {}", script.name),
+            CreateScriptLog => write!(f, "Create script log"),
+            RecordScript(script) => write!(f, "Record script: {}", script.name),
+
+            // Refactorings
+            CreateRefactorLog => write!(f, "Create refactor log"),
+            ApplyRefactor(refactor) => write!(f, "Apply refactoring {}: {}", refactor.id, refactor.operation),
+            RecordRefactor(refactor) => write!(f, "Record refactoring: {}", refactor.id),
+            AcceptRename(ref suggestion) => write!(f, "Apply detected rename: {}", suggestion.operation),
+            RevertRefactor(ref operation) => write!(f, "Revert refactoring: {}", operation),
+            ForgetRefactor(refactor) => write!(f, "Forget refactoring: {}", refactor.id),
+            SuggestRename(ref suggestion) => write!(
+                f,
+                "Possible rename ({} confidence): {}",
+                suggestion.confidence, suggestion.operation
+            ),
 
             // Types
             AddType(ty) => write!(f, "Add type: {}", ty.name),
@@ -962,19 +2679,21 @@ impl<'input> fmt::Display for ChangeInstruction<'input> {
                 match *action {
                     TypeModificationAction::AddEnumValueBefore { .. } => "inserting an enum value",
                     TypeModificationAction::AddEnumValueAfter { .. } => "inserting an enum value",
-                    TypeModificationAction::RemoveEnumValue { .. } => "removing enum value",
+                    TypeModificationAction::ReplaceEnumValues { .. } => "replacing enum values",
                 },
                 ty.name
             ),
+            SetTypeOwner(ty) => write!(f, "Set owner for type: {}", ty.name),
             DropType(ref type_name) => write!(f, "Drop type: {}", type_name),
 
             // Tables
             AddTable(table) => write!(f, "Add table: {}", table.name),
+            SetTableOwner(table) => write!(f, "Set owner for table: {}", table.name),
             DropTable(ref table_name) => write!(f, "Drop table: {}", table_name),
 
             // Columns
             AddColumn(table, column) => write!(f, "Add column: {} to table: {}", column.name, table.name),
-            ModifyColumnType(table, column) => {
+            ModifyColumnType(table, column, _) => {
                 write!(f, "Modify type for column: {} on table: {}", column.name, table.name)
             }
             ModifyColumnNull(table, column) => {
@@ -994,12 +2713,50 @@ impl<'input> fmt::Display for ChangeInstruction<'input> {
                 column.name, table.name
             ),
             DropColumn(table, ref column_name) => write!(f, "Drop column: {} on table: {}", column_name, table.name),
+            AddNullableColumn(table, column) => {
+                write!(f, "Add nullable column: {} to table: {}", column.name, table.name)
+            }
+            BackfillColumn(table, column, _) => write!(f, "Backfill column: {} on table: {}", column.name, table.name),
+            AddNotNullCheck(table, column) => {
+                write!(
+                    f,
+                    "Add not null check for column: {} on table: {}",
+                    column.name, table.name
+                )
+            }
+            ValidateNotNullCheck(table, column) => {
+                write!(
+                    f,
+                    "Validate not null check for column: {} on table: {}",
+                    column.name, table.name
+                )
+            }
+            DropNotNullCheck(table, column) => {
+                write!(
+                    f,
+                    "Drop not null check for column: {} on table: {}",
+                    column.name, table.name
+                )
+            }
 
             // Constraints
             AddConstraint(table, constraint) => {
                 write!(f, "Add constraint: {} to table: {}", constraint.name(), table.name)
             }
-            DropConstraint(table, ref name) => write!(f, "Drop constraint: {} to table: {}", name, table.name),
+            AddConstraintNotValid(table, constraint) => {
+                write!(
+                    f,
+                    "Add unvalidated constraint: {} to table: {}",
+                    constraint.name(),
+                    table.name
+                )
+            }
+            ValidateConstraint(table, ref name) => write!(f, "Validate constraint: {} on table: {}", name, table.name),
+            DropConstraint(ref table, ref name) => write!(f, "Drop constraint: {} to table: {}", name, table),
+
+            // Data loss
+            CheckDataLoss(ref loss, _) => write!(f, "Check for data loss: {}", loss),
+            Irreversible(ref reason) => write!(f, "Unable to roll back: {}", reason),
 
             // Indexes
             AddIndex(index, concurrently) => write!(
@@ -1019,22 +2776,485 @@ impl<'input> fmt::Display for ChangeInstruction<'input> {
             AddFunction(function) => write!(f, "Add function: {}", function.name),
             // Modify is identical to add however it's for future possible support
             ModifyFunction(function) => write!(f, "Modify function: {}", function.name),
+            SetFunctionOwner(function) => write!(f, "Set owner for function: {}", function.name),
             DropFunction(ref function_name) => write!(f, "Drop function: {}", function_name),
         }
     }
 }
 
 impl<'input> ChangeInstruction<'input> {
+    // Describes the change as drift, where the live database is what the change would be published over
+    fn drift_entry(&self, log: &Logger, live: Option<&Package>) -> Option<DriftEntry> {
+        let kind = match *self {
+            ChangeInstruction::CreateExtension(..)
+            | ChangeInstruction::AddSchema(..)
+            | ChangeInstruction::AddType(..)
+            | ChangeInstruction::AddTable(..)
+            | ChangeInstruction::AddColumn(..)
+            | ChangeInstruction::AddNullableColumn(..)
+            | ChangeInstruction::AddConstraint(..)
+            | ChangeInstruction::AddConstraintNotValid(..)
+            | ChangeInstruction::AddIndex(..)
+            | ChangeInstruction::AddFunction(..) => DriftKind::Removed,
+            ChangeInstruction::DropSchema(..)
+            | ChangeInstruction::DropType(..)
+            | ChangeInstruction::DropTable(..)
+            | ChangeInstruction::DropColumn(..)
+            | ChangeInstruction::DropConstraint(..)
+            | ChangeInstruction::DropIndex(..)
+            | ChangeInstruction::DropFunction(..) => DriftKind::Added,
+            ChangeInstruction::UpgradeExtension(..)
+            | ChangeInstruction::SetSchemaOwner(..)
+            | ChangeInstruction::ModifyType(..)
+            | ChangeInstruction::SetTypeOwner(..)
+            | ChangeInstruction::SetTableOwner(..)
+            | ChangeInstruction::ModifyColumnType(..)
+            | ChangeInstruction::ModifyColumnNull(..)
+            | ChangeInstruction::ModifyColumnDefault(..)
+            | ChangeInstruction::ModifyColumnUniqueConstraint(..)
+            | ChangeInstruction::ModifyColumnPrimaryKeyConstraint(..)
+            | ChangeInstruction::ModifyFunction(..)
+            | ChangeInstruction::SetFunctionOwner(..) => DriftKind::Modified,
+            // Everything else supports publishing, such as scripts, refactorings and data checks
+            _ => return None,
+        };
+        let entry = self.report_entry(log, live);
+        Some(DriftEntry {
+            kind,
+            object_type: entry.object_type?,
+            name: entry.name?,
+            deployed: entry.after,
+            live: entry.before,
+        })
+    }
+
+    // Describes the change for the deployment report. The package being published over is used to find the
+    // previous value of anything that is modified.
+    fn report_entry(&self, log: &Logger, before: Option<&Package>) -> ReportEntry {
+        let find_table = |name: &ObjectName| before.and_then(|p| p.tables.iter().find(|t| t.name.eq(name)));
+        let find_column =
+            |table: &ObjectName, name: &str| find_table(table).and_then(|t| t.columns.iter().find(|c| c.name == name));
+        let column_name = |table: &ObjectName, column: &str| format!("{}.{}", table, quote_identifier(column));
+        let constraint_name = |table: &ObjectName, name: &str| format!("{}.{}", table, quote_identifier(name));
+        let entry = |operation: Operation, object_type: ObjectType, name: String, risk: Risk| ReportEntry {
+            operation,
+            object_type: Some(object_type),
+            name: Some(name),
+            description: self.to_string(),
+            before: None,
+            after: None,
+            risk,
+            sql: self.to_sql(log),
+        };
+
+        match *self {
+            // Database level
+            ChangeInstruction::UseDatabase(ref db) => {
+                entry(Operation::Connect, ObjectType::Database, db.to_owned(), Risk::None)
+            }
+            ChangeInstruction::CreateDatabase(ref db) => {
+                entry(Operation::Create, ObjectType::Database, db.to_owned(), Risk::Low)
+            }
+            ChangeInstruction::DropDatabase(ref db) => {
+                entry(Operation::Drop, ObjectType::Database, db.to_owned(), Risk::High)
+            }
+
+            // Extensions
+            ChangeInstruction::CreateExtension(ref name, ref version) => ReportEntry {
+                after: version.map(|v| v.to_string()),
+                ..entry(Operation::Create, ObjectType::Extension, name.to_owned(), Risk::Low)
+            },
+            ChangeInstruction::UpgradeExtension(ref name, ref version) => ReportEntry {
+                before: before
+                    .and_then(|p| p.extensions.iter().find(|e| e.name.eq(name)))
+                    .and_then(|e| e.version)
+                    .map(|v| v.to_string()),
+                after: version.map(|v| v.to_string()),
+                ..entry(Operation::Alter, ObjectType::Extension, name.to_owned(), Risk::Medium)
+            },
+
+            // Schemas
+            ChangeInstruction::AddSchema(schema) => {
+                entry(Operation::Create, ObjectType::Schema, schema.name.to_owned(), Risk::Low)
+            }
+            ChangeInstruction::SetSchemaOwner(schema) => ReportEntry {
+                before: before
+                    .and_then(|p| p.schemas.iter().find(|s| s.name == schema.name))
+                    .and_then(|s| s.owner.clone()),
+                after: schema.owner.clone(),
+                ..entry(Operation::Alter, ObjectType::Schema, schema.name.to_owned(), Risk::Low)
+            },
+            ChangeInstruction::DropSchema(ref name) => {
+                entry(Operation::Drop, ObjectType::Schema, name.to_owned(), Risk::Medium)
+            }
+
+            // Scripts
+            ChangeInstruction::RunScript(script) => {
+                entry(Operation::Run, ObjectType::Script, script.name.to_owned(), Risk::Medium)
+            }
+            ChangeInstruction::CreateScriptLog => entry(
+                Operation::Create,
+                ObjectType::ScriptLog,
+                "psqlpack.script_log".to_owned(),
+                Risk::Low,
+            ),
+            ChangeInstruction::RecordScript(script) => ReportEntry {
+                after: Some(script.hash()),
+                ..entry(
+                    Operation::Update,
+                    ObjectType::ScriptLog,
+                    script.name.to_owned(),
+                    Risk::Low,
+                )
+            },
+
+            // Refactorings
+            ChangeInstruction::CreateRefactorLog => entry(
+                Operation::Create,
+                ObjectType::RefactorLog,
+                "psqlpack.refactor_log".to_owned(),
+                Risk::Low,
+            ),
+            ChangeInstruction::RecordRefactor(refactor) | ChangeInstruction::ForgetRefactor(refactor) => entry(
+                Operation::Update,
+                ObjectType::RefactorLog,
+                refactor.id.to_owned(),
+                Risk::Low,
+            ),
+            ChangeInstruction::ApplyRefactor(&RefactorDefinition { ref operation, .. })
+            | ChangeInstruction::AcceptRename(RenameSuggestion { ref operation, .. })
+            | ChangeInstruction::RevertRefactor(ref operation)
+            | ChangeInstruction::SuggestRename(RenameSuggestion { ref operation, .. }) => {
+                let (object_type, name, from, to) = match *operation {
+                    RefactorOperation::RenameTable {
+                        ref table,
+                        ref new_name,
+                    } => (ObjectType::Table, table.to_string(), table.name.to_owned(), new_name),
+                    RefactorOperation::RenameColumn {
+                        ref table,
+                        ref column,
+                        ref new_name,
+                    } => (
+                        ObjectType::Column,
+                        column_name(table, column),
+                        column.to_owned(),
+                        new_name,
+                    ),
+                    RefactorOperation::MoveSchema {
+                        ref table,
+                        ref new_schema,
+                    } => (
+                        ObjectType::Table,
+                        table.to_string(),
+                        table.schema().to_owned(),
+                        new_schema,
+                    ),
+                    RefactorOperation::RenameEnumValue {
+                        ref type_name,
+                        ref value,
+                        ref new_name,
+                    } => (ObjectType::Type, type_name.to_string(), value.to_owned(), new_name),
+                };
+                let (operation, risk) = match *self {
+                    ChangeInstruction::SuggestRename(..) => (Operation::Note, Risk::None),
+                    _ => (Operation::Rename, Risk::Medium),
+                };
+                ReportEntry {
+                    before: Some(from),
+                    after: Some(to.to_owned()),
+                    ..entry(operation, object_type, name, risk)
+                }
+            }
+
+            // Types
+            ChangeInstruction::AddType(ty) => ReportEntry {
+                after: enum_values(ty),
+                ..entry(Operation::Create, ObjectType::Type, ty.name.to_string(), Risk::Low)
+            },
+            ChangeInstruction::ModifyType(ty, ref action) => ReportEntry {
+                before: before
+                    .and_then(|p| p.types.iter().find(|t| t.name.eq(&ty.name)))
+                    .and_then(enum_values),
+                after: enum_values(ty),
+                ..entry(
+                    Operation::Alter,
+                    ObjectType::Type,
+                    ty.name.to_string(),
+                    match *action {
+                        TypeModificationAction::ReplaceEnumValues { ref removed, .. } if !removed.is_empty() => {
+                            Risk::High
+                        }
+                        TypeModificationAction::ReplaceEnumValues { .. } => Risk::Medium,
+                        _ => Risk::Low,
+                    },
+                )
+            },
+            ChangeInstruction::SetTypeOwner(ty) => ReportEntry {
+                before: before
+                    .and_then(|p| p.types.iter().find(|t| t.name.eq(&ty.name)))
+                    .and_then(|t| t.owner.clone()),
+                after: ty.owner.clone(),
+                ..entry(Operation::Alter, ObjectType::Type, ty.name.to_string(), Risk::Low)
+            },
+            ChangeInstruction::DropType(ref name) => {
+                entry(Operation::Drop, ObjectType::Type, name.to_owned(), Risk::Medium)
+            }
+
+            // Tables
+            ChangeInstruction::AddTable(table) => {
+                entry(Operation::Create, ObjectType::Table, table.name.to_string(), Risk::Low)
+            }
+            ChangeInstruction::SetTableOwner(table) => ReportEntry {
+                before: find_table(&table.name).and_then(|t| t.owner.clone()),
+                after: table.owner.clone(),
+                ..entry(Operation::Alter, ObjectType::Table, table.name.to_string(), Risk::Low)
+            },
+            ChangeInstruction::DropTable(ref name) => {
+                entry(Operation::Drop, ObjectType::Table, name.to_owned(), Risk::High)
+            }
+
+            // Columns
+            ChangeInstruction::AddColumn(table, column) => ReportEntry {
+                after: Some(column.sql_type.to_string()),
+                ..entry(
+                    Operation::Create,
+                    ObjectType::Column,
+                    column_name(&table.name, &column.name),
+                    // Existing rows can't satisfy NOT NULL without a default
+                    if column_default(column).is_none() && nullability(column) == "NOT NULL" {
+                        Risk::Medium
+                    } else {
+                        Risk::Low
+                    },
+                )
+            },
+            ChangeInstruction::AddNullableColumn(table, column) => ReportEntry {
+                after: Some(column.sql_type.to_string()),
+                ..entry(
+                    Operation::Create,
+                    ObjectType::Column,
+                    column_name(&table.name, &column.name),
+                    Risk::Low,
+                )
+            },
+            ChangeInstruction::ModifyColumnType(table, column, _) => {
+                let target_column = find_column(&table.name, &column.name);
+                ReportEntry {
+                    before: target_column.map(|c| c.sql_type.to_string()),
+                    after: Some(column.sql_type.to_string()),
+                    ..entry(
+                        Operation::Alter,
+                        ObjectType::Column,
+                        column_name(&table.name, &column.name),
+                        match target_column.map(|c| classify_type_change(&c.sql_type, &column.sql_type)) {
+                            Some(TypeChange::Widening) => Risk::Low,
+                            Some(_) => Risk::High,
+                            None => Risk::Medium,
+                        },
+                    )
+                }
+            }
+            ChangeInstruction::ModifyColumnNull(table, column) => ReportEntry {
+                before: find_column(&table.name, &column.name).map(|c| nullability(c).to_owned()),
+                after: Some(nullability(column).to_owned()),
+                ..entry(
+                    Operation::Alter,
+                    ObjectType::Column,
+                    column_name(&table.name, &column.name),
+                    if nullability(column) == "NOT NULL" {
+                        Risk::Medium
+                    } else {
+                        Risk::Low
+                    },
+                )
+            },
+            ChangeInstruction::ModifyColumnDefault(table, column) => ReportEntry {
+                before: find_column(&table.name, &column.name)
+                    .and_then(column_default)
+                    .map(|expr| expr.to_string()),
+                after: column_default(column).map(|expr| expr.to_string()),
+                ..entry(
+                    Operation::Alter,
+                    ObjectType::Column,
+                    column_name(&table.name, &column.name),
+                    Risk::Low,
+                )
+            },
+            ChangeInstruction::ModifyColumnUniqueConstraint(table, column)
+            | ChangeInstruction::ModifyColumnPrimaryKeyConstraint(table, column) => entry(
+                Operation::Alter,
+                ObjectType::Column,
+                column_name(&table.name, &column.name),
+                Risk::Low,
+            ),
+            ChangeInstruction::DropColumn(table, ref name) => ReportEntry {
+                before: find_column(&table.name, name).map(|c| c.sql_type.to_string()),
+                ..entry(
+                    Operation::Drop,
+                    ObjectType::Column,
+                    column_name(&table.name, name),
+                    Risk::High,
+                )
+            },
+            ChangeInstruction::BackfillColumn(table, column, ref backfill) => ReportEntry {
+                after: Some(backfill.to_owned()),
+                ..entry(
+                    Operation::Update,
+                    ObjectType::Column,
+                    column_name(&table.name, &column.name),
+                    Risk::Medium,
+                )
+            },
+            ChangeInstruction::AddNotNullCheck(table, column) => entry(
+                Operation::Create,
+                ObjectType::Constraint,
+                constraint_name(&table.name, &not_null_check_name(table, column)),
+                Risk::Low,
+            ),
+            ChangeInstruction::ValidateNotNullCheck(table, column) => entry(
+                Operation::Validate,
+                ObjectType::Constraint,
+                constraint_name(&table.name, &not_null_check_name(table, column)),
+                Risk::Low,
+            ),
+            ChangeInstruction::DropNotNullCheck(table, column) => entry(
+                Operation::Drop,
+                ObjectType::Constraint,
+                constraint_name(&table.name, &not_null_check_name(table, column)),
+                Risk::Low,
+            ),
+
+            // Data loss checks
+            ChangeInstruction::CheckDataLoss(ref loss, _) => match *loss {
+                DataLoss::Table(ref table) => entry(Operation::Check, ObjectType::Table, table.to_string(), Risk::None),
+                DataLoss::Column(ref table, ref column) => entry(
+                    Operation::Check,
+                    ObjectType::Column,
+                    column_name(table, column),
+                    Risk::None,
+                ),
+                DataLoss::Narrowing(ref table, ref column, ref from, ref to) => ReportEntry {
+                    before: Some(from.to_string()),
+                    after: Some(to.to_string()),
+                    ..entry(
+                        Operation::Check,
+                        ObjectType::Column,
+                        column_name(table, column),
+                        Risk::None,
+                    )
+                },
+            },
+
+            // Constraints are checked against existing rows unless the table is new, or they are validated later
+            ChangeInstruction::AddConstraint(table, constraint) => entry(
+                Operation::Create,
+                ObjectType::Constraint,
+                constraint_name(&table.name, constraint.name()),
+                if find_table(&table.name).is_some() {
+                    Risk::Medium
+                } else {
+                    Risk::Low
+                },
+            ),
+            ChangeInstruction::AddConstraintNotValid(table, constraint) => entry(
+                Operation::Create,
+                ObjectType::Constraint,
+                constraint_name(&table.name, constraint.name()),
+                Risk::Low,
+            ),
+            ChangeInstruction::ValidateConstraint(table, ref name) => entry(
+                Operation::Validate,
+                ObjectType::Constraint,
+                constraint_name(&table.name, name),
+                Risk::Low,
+            ),
+            ChangeInstruction::DropConstraint(ref table, ref name) => entry(
+                Operation::Drop,
+                ObjectType::Constraint,
+                constraint_name(table, name),
+                Risk::Medium,
+            ),
+
+            // Indexes block writes while being built unless built concurrently
+            ChangeInstruction::AddIndex(index, concurrently) => entry(
+                Operation::Create,
+                ObjectType::Index,
+                index.fully_qualified_name(),
+                if concurrently { Risk::Low } else { Risk::Medium },
+            ),
+            ChangeInstruction::DropIndex(ref name, _) => {
+                entry(Operation::Drop, ObjectType::Index, name.to_owned(), Risk::Low)
+            }
+
+            // Functions
+            ChangeInstruction::AddFunction(function) => entry(
+                Operation::Create,
+                ObjectType::Function,
+                function.name.to_string(),
+                Risk::Low,
+            ),
+            ChangeInstruction::ModifyFunction(function) => entry(
+                Operation::Alter,
+                ObjectType::Function,
+                function.name.to_string(),
+                Risk::Low,
+            ),
+            ChangeInstruction::SetFunctionOwner(function) => ReportEntry {
+                before: before
+                    .and_then(|p| p.functions.iter().find(|f| f.name.eq(&function.name)))
+                    .and_then(|f| f.owner.clone()),
+                after: function.owner.clone(),
+                ..entry(
+                    Operation::Alter,
+                    ObjectType::Function,
+                    function.name.to_string(),
+                    Risk::Low,
+                )
+            },
+            ChangeInstruction::DropFunction(ref name) => {
+                entry(Operation::Drop, ObjectType::Function, name.to_owned(), Risk::Medium)
+            }
+
+            // Flags data that a rollback won't restore
+            ChangeInstruction::Irreversible(_) => ReportEntry {
+                operation: Operation::Note,
+                object_type: None,
+                name: None,
+                description: self.to_string(),
+                before: None,
+                after: None,
+                risk: Risk::High,
+                sql: self.to_sql(log),
+            },
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        match *self {
+            ChangeInstruction::DropDatabase(_) | ChangeInstruction::CreateDatabase(_) => false,
+            // Validating on its own means only a SHARE UPDATE EXCLUSIVE lock is held while the table is scanned
+            ChangeInstruction::ValidateNotNullCheck(..) | ChangeInstruction::ValidateConstraint(..) => false,
+            ChangeInstruction::AddIndex(_, concurrently) | ChangeInstruction::DropIndex(_, concurrently) => {
+                !concurrently
+            }
+            // Prior to PG12 enum values can't be added within a transaction. From PG12 onwards the new value
+            // can't be used until the transaction has been committed, so we always commit it on its own.
+            ChangeInstruction::ModifyType(_, TypeModificationAction::AddEnumValueBefore { .. })
+            | ChangeInstruction::ModifyType(_, TypeModificationAction::AddEnumValueAfter { .. }) => false,
+            _ => true,
+        }
+    }
+
     fn to_sql(&self, log: &Logger) -> String {
         match *self {
             // Database level
-            ChangeInstruction::CreateDatabase(ref db) => format!("CREATE DATABASE {}", db),
+            ChangeInstruction::CreateDatabase(ref db) => format!("CREATE DATABASE {}", quote_identifier(db)),
             ChangeInstruction::DropDatabase(ref db) => {
                 let mut drop = String::new();
                 drop.push_str("SELECT pg_terminate_backend(pg_stat_activity.pid) ");
                 drop.push_str("FROM pg_stat_activity ");
-                drop.push_str(&format!("WHERE pg_stat_activity.datname = '{}';", db));
-                drop.push_str(&format!("DROP DATABASE {}", db));
+                drop.push_str(&format!("WHERE pg_stat_activity.datname = {};", quote_literal(db)));
+                drop.push_str(&format!("DROP DATABASE {}", quote_identifier(db)));
                 drop
             }
             ChangeInstruction::UseDatabase(ref db) => format!("-- Using database `{}`", db),
@@ -1042,27 +3262,69 @@ impl<'input> ChangeInstruction<'input> {
             // ExtensionRequest level
             ChangeInstruction::CreateExtension(ref name, ref version) => {
                 if let Some(ref version) = version {
-                    format!("CREATE EXTENSION {} WITH VERSION \"{}\"", name, version)
+                    format!(
+                        "CREATE EXTENSION {} WITH VERSION {}",
+                        quote_identifier(name),
+                        quote_identifier(&version.to_string())
+                    )
                 } else {
-                    format!("CREATE EXTENSION {}", name)
+                    format!("CREATE EXTENSION {}", quote_identifier(name))
                 }
             }
             ChangeInstruction::UpgradeExtension(ref name, ref version) => {
                 if let Some(ref version) = version {
-                    format!("ALTER EXTENSION {} UPDATE TO \"{}\"", name, version)
+                    format!(
+                        "ALTER EXTENSION {} UPDATE TO {}",
+                        quote_identifier(name),
+                        quote_identifier(&version.to_string())
+                    )
                 } else {
-                    format!("ALTER EXTENSION {} UPDATE", name)
+                    format!("ALTER EXTENSION {} UPDATE", quote_identifier(name))
                 }
             }
 
             // Schema level
             ChangeInstruction::AddSchema(schema) => {
                 if schema.name == "public" {
-                    format!("CREATE SCHEMA IF NOT EXISTS {}", schema.name)
+                    format!("CREATE SCHEMA IF NOT EXISTS {}", quote_identifier(&schema.name))
                 } else {
-                    format!("CREATE SCHEMA {}", schema.name)
+                    format!("CREATE SCHEMA {}", quote_identifier(&schema.name))
                 }
             }
+            ChangeInstruction::SetSchemaOwner(schema) => {
+                format!(
+                    "ALTER SCHEMA {} OWNER TO {}",
+                    quote_identifier(&schema.name),
+                    quote_identifier(schema.owner.as_ref().unwrap())
+                )
+            }
+            ChangeInstruction::DropSchema(ref schema_name) => {
+                format!("DROP SCHEMA IF EXISTS {}", quote_identifier(schema_name))
+            }
+
+            // Refactoring level
+            ChangeInstruction::CreateRefactorLog => "CREATE SCHEMA IF NOT EXISTS psqlpack;\n\
+                 CREATE TABLE IF NOT EXISTS psqlpack.refactor_log (\
+                 id text PRIMARY KEY, \
+                 definition text NOT NULL, \
+                 applied_at timestamp with time zone NOT NULL DEFAULT now())"
+                .to_owned(),
+            ChangeInstruction::ApplyRefactor(refactor) => refactor_sql(&refactor.operation),
+            ChangeInstruction::AcceptRename(ref suggestion) => refactor_sql(&suggestion.operation),
+            ChangeInstruction::RevertRefactor(ref operation) => refactor_sql(operation),
+            ChangeInstruction::SuggestRename(ref suggestion) => format!(
+                "-- Possible rename ({} confidence): {}",
+                suggestion.confidence, suggestion.operation
+            ),
+            ChangeInstruction::RecordRefactor(refactor) => format!(
+                "INSERT INTO psqlpack.refactor_log (id, definition) VALUES ({}, {})",
+                quote_literal(&refactor.id),
+                quote_literal(&serde_json::to_string(refactor).unwrap())
+            ),
+            ChangeInstruction::ForgetRefactor(refactor) => format!(
+                "DELETE FROM psqlpack.refactor_log WHERE id = {}",
+                quote_literal(&refactor.id)
+            ),
 
             // Type level
             ChangeInstruction::AddType(ty) => {
@@ -1078,7 +3340,7 @@ impl<'input> ChangeInstruction<'input> {
                             } else {
                                 enum_comma_required = true;
                             }
-                            def.push_str(&format!("  '{}'", value)[..]);
+                            def.push_str(&format!("  {}", quote_literal(value))[..]);
                         }
                         def.push_str("\n)");
                     }
@@ -1087,70 +3349,44 @@ impl<'input> ChangeInstruction<'input> {
                 def
             }
             ChangeInstruction::ModifyType(ty, ref action) => match *action {
-                TypeModificationAction::AddEnumValueBefore { ref value, ref before } => {
-                    format!("ALTER TYPE {} ADD VALUE '{}' BEFORE '{}'", ty.name, value, before)
-                }
-                TypeModificationAction::AddEnumValueAfter { ref value, ref after } => {
-                    format!("ALTER TYPE {} ADD VALUE '{}' AFTER '{}'", ty.name, value, after)
-                }
-                TypeModificationAction::RemoveEnumValue { ref value } => format!(
-                    "DELETE FROM pg_enum \
-                     WHERE enumlabel='{}' AND \
-                     enumtypid=(SELECT oid FROM pg_type WHERE {})",
-                    value,
-                    if let Some(ref schema) = ty.name.schema {
-                        format!("nspname='{}' AND typname='{}'", schema, ty.name.name)
-                    } else {
-                        format!("typname='{}'", ty.name.name)
-                    },
+                TypeModificationAction::AddEnumValueBefore { ref value, ref before } => format!(
+                    "ALTER TYPE {} ADD VALUE {} BEFORE {}",
+                    ty.name,
+                    quote_literal(value),
+                    quote_literal(before)
+                ),
+                TypeModificationAction::AddEnumValueAfter { ref value, ref after } => format!(
+                    "ALTER TYPE {} ADD VALUE {} AFTER {}",
+                    ty.name,
+                    quote_literal(value),
+                    quote_literal(after)
                 ),
+                TypeModificationAction::ReplaceEnumValues {
+                    ref values,
+                    ref removed,
+                    ref columns,
+                    ref functions,
+                } => replace_enum_sql(ty, values, removed, columns, functions),
             },
+            ChangeInstruction::SetTypeOwner(ty) => {
+                format!(
+                    "ALTER TYPE {} OWNER TO {}",
+                    ty.name,
+                    quote_identifier(ty.owner.as_ref().unwrap())
+                )
+            }
             ChangeInstruction::DropType(ref type_name) => format!("DROP TYPE IF EXISTS {}", type_name),
 
             // Function level
             ChangeInstruction::AddFunction(function) | ChangeInstruction::ModifyFunction(function) => {
-                let mut func = String::new();
-                func.push_str(&format!("CREATE OR REPLACE FUNCTION {} (", function.name)[..]);
-                let mut arg_comma_required = false;
-                for arg in &function.arguments {
-                    if arg_comma_required {
-                        func.push_str(", ");
-                    } else {
-                        arg_comma_required = true;
-                    }
-
-                    func.push_str(&arg.to_string());
-                }
-                func.push_str(")\n");
-                func.push_str("RETURNS ");
-                match function.return_type {
-                    FunctionReturnType::Table(ref columns) => {
-                        func.push_str("TABLE (\n");
-                        let mut column_comma_required = false;
-                        for column in columns {
-                            if column_comma_required {
-                                func.push_str(",\n");
-                            } else {
-                                column_comma_required = true;
-                            }
-                            func.push_str(&format!("  {} {}", column.name, column.sql_type)[..]);
-                        }
-                        func.push_str("\n)\n");
-                    }
-                    FunctionReturnType::SetOf(ref sql_type) => {
-                        func.push_str(&format!("SETOF {}", sql_type)[..]);
-                    }
-                    FunctionReturnType::SqlType(ref sql_type) => {
-                        func.push_str(&format!("{} ", sql_type)[..]);
-                    }
-                }
-                func.push_str("AS $$");
-                func.push_str(&function.body[..]);
-                func.push_str("$$\n");
-                func.push_str("LANGUAGE ");
-                func.push_str(&function.language.to_string());
-                func
+                function_sql(function)
             }
+            ChangeInstruction::SetFunctionOwner(function) => format!(
+                "ALTER FUNCTION {}({}) OWNER TO {}",
+                function.name,
+                function_signature(function),
+                quote_identifier(function.owner.as_ref().unwrap())
+            ),
             ChangeInstruction::DropFunction(ref function_name) => format!("DROP FUNCTION IF EXISTS {}", function_name),
 
             // Table level
@@ -1162,12 +3398,10 @@ impl<'input> ChangeInstruction<'input> {
                         instr.push_str(",");
                     }
                     instr.push_str("\n\t");
-                    instr.push_str(&format!("{} {}", column.name, column.sql_type));
+                    instr.push_str(&format!("{} {}", quote_identifier(&column.name), column.sql_type));
                     for constraint in column.constraints.iter() {
                         match *constraint {
-                            ColumnConstraint::Default(ref any_type) => {
-                                instr.push_str(&format!(" DEFAULT {}", any_type))
-                            }
+                            ColumnConstraint::Default(ref expr) => instr.push_str(&format!(" DEFAULT {}", expr)),
                             ColumnConstraint::NotNull => instr.push_str(" NOT NULL"),
                             ColumnConstraint::Null => instr.push_str(" NULL"),
                             ColumnConstraint::Unique => instr.push_str(" UNIQUE"),
@@ -1179,6 +3413,13 @@ impl<'input> ChangeInstruction<'input> {
                 instr.push_str("\n)");
                 instr
             }
+            ChangeInstruction::SetTableOwner(table) => {
+                format!(
+                    "ALTER TABLE {} OWNER TO {}",
+                    table.name,
+                    quote_identifier(table.owner.as_ref().unwrap())
+                )
+            }
             ChangeInstruction::DropTable(ref table_name) => format!("DROP TABLE IF EXISTS {}", table_name),
 
             // Column level
@@ -1186,11 +3427,13 @@ impl<'input> ChangeInstruction<'input> {
                 let mut instr = String::new();
                 instr.push_str(&format!(
                     "ALTER TABLE {} ADD COLUMN {} {}",
-                    table.name, column.name, column.sql_type
+                    table.name,
+                    quote_identifier(&column.name),
+                    column.sql_type
                 ));
                 for constraint in column.constraints.iter() {
                     match *constraint {
-                        ColumnConstraint::Default(ref any_type) => instr.push_str(&format!(" DEFAULT {}", any_type)),
+                        ColumnConstraint::Default(ref expr) => instr.push_str(&format!(" DEFAULT {}", expr)),
                         ColumnConstraint::NotNull => instr.push_str(" NOT NULL"),
                         ColumnConstraint::Null => instr.push_str(" NULL"),
                         ColumnConstraint::Unique => instr.push_str(" UNIQUE"),
@@ -1199,18 +3442,72 @@ impl<'input> ChangeInstruction<'input> {
                 }
                 instr
             }
-            ChangeInstruction::ModifyColumnType(table, column) => format!(
-                "ALTER TABLE {} ALTER COLUMN {} TYPE {}",
-                table.name, column.name, column.sql_type
+            ChangeInstruction::AddNullableColumn(table, column) => {
+                let mut instr = format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table.name,
+                    quote_identifier(&column.name),
+                    column.sql_type
+                );
+                for constraint in column.constraints.iter() {
+                    match *constraint {
+                        ColumnConstraint::Unique => instr.push_str(" UNIQUE"),
+                        ColumnConstraint::PrimaryKey => instr.push_str(" PRIMARY KEY"),
+                        _ => {}
+                    }
+                }
+                instr
+            }
+            ChangeInstruction::BackfillColumn(table, column, ref backfill) => format!(
+                "UPDATE {} SET {1} = {2} WHERE {1} IS NULL",
+                table.name,
+                quote_identifier(&column.name),
+                backfill
+            ),
+            ChangeInstruction::AddNotNullCheck(table, column) => format!(
+                "ALTER TABLE {} ADD CONSTRAINT {} CHECK ({} IS NOT NULL) NOT VALID",
+                table.name,
+                quote_identifier(&not_null_check_name(table, column)),
+                quote_identifier(&column.name)
             ),
+            ChangeInstruction::ValidateNotNullCheck(table, column) => format!(
+                "ALTER TABLE {} VALIDATE CONSTRAINT {}",
+                table.name,
+                quote_identifier(&not_null_check_name(table, column))
+            ),
+            ChangeInstruction::DropNotNullCheck(table, column) => format!(
+                "ALTER TABLE {} DROP CONSTRAINT {}",
+                table.name,
+                quote_identifier(&not_null_check_name(table, column))
+            ),
+            ChangeInstruction::ModifyColumnType(table, column, using) => {
+                let mut instr = format!(
+                    "ALTER TABLE {} ALTER COLUMN {} TYPE {}",
+                    table.name,
+                    quote_identifier(&column.name),
+                    column.sql_type
+                );
+                if let Some(using) = using {
+                    instr.push_str(&format!(" USING {}", using));
+                }
+                instr
+            }
             ChangeInstruction::ModifyColumnNull(table, column) => {
                 for constraint in column.constraints.iter() {
                     match *constraint {
                         ColumnConstraint::NotNull => {
-                            return format!("ALTER TABLE {} ALTER COLUMN {} SET NOT NULL", table.name, column.name);
+                            return format!(
+                                "ALTER TABLE {} ALTER COLUMN {} SET NOT NULL",
+                                table.name,
+                                quote_identifier(&column.name)
+                            );
                         }
                         ColumnConstraint::Null => {
-                            return format!("ALTER TABLE {} ALTER COLUMN {} DROP NOT NULL", table.name, column.name);
+                            return format!(
+                                "ALTER TABLE {} ALTER COLUMN {} DROP NOT NULL",
+                                table.name,
+                                quote_identifier(&column.name)
+                            );
                         }
                         _ => {}
                     }
@@ -1221,21 +3518,19 @@ impl<'input> ChangeInstruction<'input> {
                 );
                 "".to_owned()
             }
-            ChangeInstruction::ModifyColumnDefault(table, column) => {
-                for constraint in column.constraints.iter() {
-                    if let ColumnConstraint::Default(ref any_type) = *constraint {
-                        return format!(
-                            ";\nALTER TABLE {} ALTER COLUMN {} SET DEFAULT {}",
-                            table.name, column.name, any_type
-                        );
-                    }
-                }
-                error!(
-                    log,
-                    "Expected to modify column default constraint for {}.{}", table.name, column.name
-                );
-                "".to_owned()
-            }
+            ChangeInstruction::ModifyColumnDefault(table, column) => match column_default(column) {
+                Some(expr) => format!(
+                    "ALTER TABLE {} ALTER COLUMN {} SET DEFAULT {}",
+                    table.name,
+                    quote_identifier(&column.name),
+                    expr
+                ),
+                None => format!(
+                    "ALTER TABLE {} ALTER COLUMN {} DROP DEFAULT",
+                    table.name,
+                    quote_identifier(&column.name)
+                ),
+            },
             ChangeInstruction::ModifyColumnUniqueConstraint(table, column) => {
                 for constraint in column.constraints.iter() {
                     if let ColumnConstraint::Unique = *constraint {
@@ -1261,10 +3556,15 @@ impl<'input> ChangeInstruction<'input> {
                 "".to_owned()
             }
             ChangeInstruction::DropColumn(table, ref column_name) => {
-                format!("ALTER TABLE {} DROP COLUMN {}", table.name, column_name)
+                format!(
+                    "ALTER TABLE {} DROP COLUMN {}",
+                    table.name,
+                    quote_identifier(column_name)
+                )
             }
 
-            ChangeInstruction::AddConstraint(table, constraint) => {
+            ChangeInstruction::AddConstraint(table, constraint)
+            | ChangeInstruction::AddConstraintNotValid(table, constraint) => {
                 let mut instr = String::new();
                 instr.push_str(&format!("ALTER TABLE {}\nADD ", table.name));
                 match *constraint {
@@ -1273,7 +3573,11 @@ impl<'input> ChangeInstruction<'input> {
                         ref columns,
                         ref parameters,
                     } => {
-                        instr.push_str(&format!("CONSTRAINT {} PRIMARY KEY ({})", name, columns.join(", ")));
+                        instr.push_str(&format!(
+                            "CONSTRAINT {} PRIMARY KEY ({})",
+                            quote_identifier(name),
+                            quote_identifiers(columns)
+                        ));
 
                         // Do the WITH options too
                         if let Some(ref unwrapped) = *parameters {
@@ -1297,8 +3601,14 @@ impl<'input> ChangeInstruction<'input> {
                         ref match_type,
                         ref events,
                     } => {
-                        instr.push_str(&format!("CONSTRAINT {} FOREIGN KEY ({})", name, columns.join(", "))[..]);
-                        instr.push_str(&format!(" REFERENCES {} ({})", ref_table, ref_columns.join(", "))[..]);
+                        instr.push_str(
+                            &format!(
+                                "CONSTRAINT {} FOREIGN KEY ({})",
+                                quote_identifier(name),
+                                quote_identifiers(columns)
+                            )[..],
+                        );
+                        instr.push_str(&format!(" REFERENCES {} ({})", ref_table, quote_identifiers(ref_columns))[..]);
                         if let Some(ref m) = *match_type {
                             instr.push_str(&format!(" {}", m));
                         }
@@ -1316,13 +3626,26 @@ impl<'input> ChangeInstruction<'input> {
                         }
                     }
                 }
+                if let ChangeInstruction::AddConstraintNotValid(..) = *self {
+                    instr.push_str(" NOT VALID");
+                }
                 instr
             }
+            ChangeInstruction::ValidateConstraint(table, ref name) => {
+                format!(
+                    "ALTER TABLE {}\nVALIDATE CONSTRAINT {}",
+                    table.name,
+                    quote_identifier(name)
+                )
+            }
 
-            ChangeInstruction::DropConstraint(table, ref name) => {
-                format!("ALTER TABLE {}\nDROP CONSTRAINT {}", table.name, name)
+            ChangeInstruction::DropConstraint(ref table, ref name) => {
+                format!("ALTER TABLE {}\nDROP CONSTRAINT {}", table, quote_identifier(name))
             }
 
+            ChangeInstruction::CheckDataLoss(ref loss, block) => data_loss_sql(loss, block),
+            ChangeInstruction::Irreversible(ref reason) => format!("-- Unable to roll back: {}", reason),
+
             // Raw scripts
             ChangeInstruction::RunScript(script) => {
                 let mut instr = String::new();
@@ -1331,6 +3654,18 @@ impl<'input> ChangeInstruction<'input> {
                 instr.push('\n');
                 instr
             }
+            ChangeInstruction::CreateScriptLog => "CREATE SCHEMA IF NOT EXISTS psqlpack;\n\
+                 CREATE TABLE IF NOT EXISTS psqlpack.script_log (\
+                 name text PRIMARY KEY, \
+                 hash text NOT NULL, \
+                 executed_at timestamp with time zone NOT NULL DEFAULT now())"
+                .to_owned(),
+            ChangeInstruction::RecordScript(script) => format!(
+                "INSERT INTO psqlpack.script_log (name, hash) VALUES ({}, {}) \
+                 ON CONFLICT (name) DO UPDATE SET hash = EXCLUDED.hash, executed_at = now()",
+                quote_literal(&script.name),
+                quote_literal(&script.hash())
+            ),
 
             // Indexes
             ChangeInstruction::AddIndex(index, concurrently) => {
@@ -1343,7 +3678,7 @@ impl<'input> ChangeInstruction<'input> {
                 if concurrently {
                     instr.push_str("CONCURRENTLY ");
                 }
-                instr.push_str(&format!("{} ON {}", index.name, index.table));
+                instr.push_str(&format!("{} ON {}", quote_identifier(&index.name), index.table));
                 if let Some(ref method) = index.index_type {
                     instr.push_str(" USING ");
                     instr.push_str(match method {
@@ -1358,7 +3693,7 @@ impl<'input> ChangeInstruction<'input> {
                     if position > 0 {
                         instr.push_str(", ");
                     }
-                    instr.push_str(&col.name);
+                    instr.push_str(&quote_identifier(&col.name));
                     if let Some(ref order) = col.order {
                         instr.push_str(match order {
                             IndexOrder::Ascending => " ASC",
@@ -1404,6 +3739,8 @@ impl<'input> ChangeInstruction<'input> {
 mod tests {
     use super::*;
 
+    use std::collections::BTreeMap;
+
     use crate::errors::PsqlpackError;
     use crate::model::*;
     use crate::sql::ast;
@@ -1423,6 +3760,7 @@ mod tests {
                 name: "colors".to_string(),
             },
             kind: ast::TypeDefinitionKind::Enum(vec!["red".into(), "green".into(), "blue".into()]),
+            owner: None,
         }
     }
 
@@ -1518,6 +3856,7 @@ mod tests {
                 "blue".to_owned(),
                 "black".to_owned(),
             ]),
+            owner: None,
         };
 
         // Create a package with the type already defined
@@ -1580,6 +3919,7 @@ mod tests {
                 "green".to_owned(),
                 "blue".to_owned(),
             ]),
+            owner: None,
         };
 
         // Create a package with the type already defined
@@ -1642,6 +3982,7 @@ mod tests {
                 "black".to_owned(),
                 "blue".to_owned(),
             ]),
+            owner: None,
         };
 
         // Create a package with the type already defined
@@ -1699,6 +4040,7 @@ mod tests {
                 name: "colors".to_string(),
             },
             kind: ast::TypeDefinitionKind::Enum(vec!["black".to_owned(), "green".to_owned(), "blue".to_owned()]),
+            owner: None,
         };
 
         // Create a package with the type already defined
@@ -1722,10 +4064,8 @@ mod tests {
         );
         assert_that!(result).is_ok();
 
-        // We should have a single instruction to modify the enum with an additional value
-        assert_that!(change_set).has_length(2);
-
-        // Removals first
+        // The type needs to be replaced since values are being removed
+        assert_that!(change_set).has_length(1);
         match change_set[0] {
             ChangeInstruction::ModifyType(ty, ref action) => {
                 assert_that!(ty.name).is_equal_to(ast::ObjectName {
@@ -1735,8 +4075,17 @@ mod tests {
 
                 // Also, match the action
                 match *action {
-                    TypeModificationAction::RemoveEnumValue { ref value } => {
-                        assert_that!(*value).is_equal_to("red".to_owned());
+                    TypeModificationAction::ReplaceEnumValues {
+                        ref values,
+                        ref removed,
+                        ..
+                    } => {
+                        assert_that!(*values).is_equal_to(vec![
+                            "black".to_owned(),
+                            "green".to_owned(),
+                            "blue".to_owned(),
+                        ]);
+                        assert_that!(*removed).is_equal_to(vec!["red".to_owned()]);
                     }
                     ref unexpected => panic!("Unexpected enum modification action: {:?}", unexpected),
                 }
@@ -1745,34 +4094,11 @@ mod tests {
         }
         // Check the SQL generation
         assert_that!(change_set[0].to_sql(&log)).is_equal_to(
-            "DELETE FROM pg_enum \
-             WHERE enumlabel='red' AND \
-             enumtypid=(SELECT oid FROM pg_type WHERE nspname='public' AND typname='colors')"
+            "CREATE TYPE public.colors__new AS ENUM ('black', 'green', 'blue');\n\
+             DROP TYPE public.colors;\n\
+             ALTER TYPE public.colors__new RENAME TO colors"
                 .to_owned(),
         );
-
-        // Additions second
-        match change_set[1] {
-            ChangeInstruction::ModifyType(ty, ref action) => {
-                assert_that!(ty.name).is_equal_to(ast::ObjectName {
-                    schema: Some("public".to_string()),
-                    name: "colors".to_string(),
-                });
-
-                // Also, match the action
-                match *action {
-                    TypeModificationAction::AddEnumValueBefore { ref value, ref before } => {
-                        assert_that!(*value).is_equal_to("black".to_owned());
-                        assert_that!(*before).is_equal_to("green".to_owned());
-                    }
-                    ref unexpected => panic!("Unexpected enum modification action: {:?}", unexpected),
-                }
-            }
-            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
-        }
-        // Check the SQL generation
-        assert_that!(change_set[1].to_sql(&log))
-            .is_equal_to("ALTER TYPE public.colors ADD VALUE 'black' BEFORE 'green'".to_owned());
     }
 
     #[test]
@@ -1784,6 +4110,7 @@ mod tests {
                 name: "colors".to_string(),
             },
             kind: ast::TypeDefinitionKind::Enum(vec!["black".to_owned(), "green".to_owned(), "blue".to_owned()]),
+            owner: None,
         };
 
         // Create a package with the type already defined
@@ -1820,6 +4147,7 @@ mod tests {
                 name: "colors".to_string(),
             },
             kind: ast::TypeDefinitionKind::Enum(vec!["green".to_owned(), "blue".to_owned()]),
+            owner: None,
         };
 
         // Create a package with the type already defined
@@ -1843,10 +4171,8 @@ mod tests {
         );
         assert_that!(result).is_ok();
 
-        // We should have a single instruction to modify the enum with an additional value
+        // We should have a single instruction to replace the enum
         assert_that!(change_set).has_length(1);
-
-        // Removals first
         match change_set[0] {
             ChangeInstruction::ModifyType(ty, ref action) => {
                 assert_that!(ty.name).is_equal_to(ast::ObjectName {
@@ -1856,8 +4182,13 @@ mod tests {
 
                 // Also, match the action
                 match *action {
-                    TypeModificationAction::RemoveEnumValue { ref value } => {
-                        assert_that!(*value).is_equal_to("red".to_owned());
+                    TypeModificationAction::ReplaceEnumValues {
+                        ref values,
+                        ref removed,
+                        ..
+                    } => {
+                        assert_that!(*values).is_equal_to(vec!["green".to_owned(), "blue".to_owned()]);
+                        assert_that!(*removed).is_equal_to(vec!["red".to_owned()]);
                     }
                     ref unexpected => panic!("Unexpected enum modification action: {:?}", unexpected),
                 }
@@ -1866,9 +4197,151 @@ mod tests {
         }
         // Check the SQL generation
         assert_that!(change_set[0].to_sql(&log)).is_equal_to(
-            "DELETE FROM pg_enum \
-             WHERE enumlabel='red' AND \
-             enumtypid=(SELECT oid FROM pg_type WHERE nspname='public' AND typname='colors')"
+            "CREATE TYPE public.colors__new AS ENUM ('green', 'blue');\n\
+             DROP TYPE public.colors;\n\
+             ALTER TYPE public.colors__new RENAME TO colors"
+                .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_can_reorder_enum_values_by_replacing_the_type() {
+        let log = empty_logger();
+        let source_type = ast::TypeDefinition {
+            name: ast::ObjectName {
+                schema: Some("public".to_string()),
+                name: "colors".to_string(),
+            },
+            kind: ast::TypeDefinitionKind::Enum(vec!["blue".to_owned(), "green".to_owned(), "red".to_owned()]),
+            owner: None,
+        };
+
+        // Create a package with the type already defined
+        let mut existing_database = Package::new();
+        existing_database.types.push(base_type());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        // Reordering doesn't lose any data so is allowed by default
+        let publish_profile = PublishProfile::default();
+        let mut change_set = Vec::new();
+        let result = (&source_type).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+        assert_that!(change_set).has_length(1);
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+            "CREATE TYPE public.colors__new AS ENUM ('blue', 'green', 'red');\n\
+             DROP TYPE public.colors;\n\
+             ALTER TYPE public.colors__new RENAME TO colors"
+                .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_moves_dependent_columns_and_functions_when_replacing_an_enum() {
+        let log = empty_logger();
+        let source_type = ast::TypeDefinition {
+            name: ast::ObjectName {
+                schema: Some("public".to_string()),
+                name: "colors".to_string(),
+            },
+            kind: ast::TypeDefinitionKind::Enum(vec!["green".to_owned(), "blue".to_owned()]),
+            owner: None,
+        };
+        let colors = SqlType::Custom(base_type().name, None, None);
+
+        // Create a package with the type used by a table and a function
+        let mut existing_database = Package::new();
+        existing_database.types.push(base_type());
+        existing_database.tables.push(ast::TableDefinition {
+            name: ObjectName {
+                schema: Some("public".to_owned()),
+                name: "cars".to_owned(),
+            },
+            columns: vec![
+                ColumnDefinition {
+                    name: "color".to_owned(),
+                    sql_type: colors.clone(),
+                    constraints: vec![ColumnConstraint::Default(Expression::Value(AnyValue::String(
+                        "green".to_owned(),
+                        Some(colors.clone()),
+                    )))],
+                },
+                ColumnDefinition {
+                    name: "trim".to_owned(),
+                    sql_type: SqlType::Custom(base_type().name, None, Some(1)),
+                    constraints: Vec::new(),
+                },
+            ],
+            constraints: Vec::new(),
+            owner: None,
+        });
+        existing_database.functions.push(FunctionDefinition {
+            name: ObjectName {
+                schema: Some("public".to_owned()),
+                name: "is_red".to_owned(),
+            },
+            arguments: vec![FunctionArgument {
+                mode: None,
+                name: Some("c".to_owned()),
+                sql_type: colors,
+                default: None,
+            }],
+            return_type: FunctionReturnType::SqlType(SqlType::Simple(SimpleSqlType::Boolean, None)),
+            body: "SELECT c = 'red'".to_owned(),
+            language: FunctionLanguage::SQL,
+            owner: None,
+        });
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_enum_values = Toggle::Allow;
+
+        let mut change_set = Vec::new();
+        let result = (&source_type).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+        assert_that!(change_set).has_length(1);
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+            "DO $$\n\
+             BEGIN\n  \
+               IF EXISTS (SELECT 1 FROM public.cars WHERE color::text IN ('red')) THEN\n    \
+                 RAISE EXCEPTION USING MESSAGE = 'Unable to remove enum value(s) from public.colors as they are still \
+                 used by public.cars.color';\n  \
+               END IF;\n  \
+               IF EXISTS (SELECT 1 FROM public.cars WHERE trim::text[] && ARRAY['red']) THEN\n    \
+                 RAISE EXCEPTION USING MESSAGE = 'Unable to remove enum value(s) from public.colors as they are still \
+                 used by public.cars.trim';\n  \
+               END IF;\n\
+             END\n\
+             $$;\n\
+             CREATE TYPE public.colors__new AS ENUM ('green', 'blue');\n\
+             DROP FUNCTION IF EXISTS public.is_red(public.colors);\n\
+             ALTER TABLE public.cars ALTER COLUMN color DROP DEFAULT;\n\
+             ALTER TABLE public.cars ALTER COLUMN color TYPE public.colors__new USING color::text::public.colors__new;\n\
+             ALTER TABLE public.cars ALTER COLUMN trim TYPE public.colors__new[] USING trim::text[]::public.colors__new[];\n\
+             DROP TYPE public.colors;\n\
+             ALTER TYPE public.colors__new RENAME TO colors;\n\
+             ALTER TABLE public.cars ALTER COLUMN color SET DEFAULT 'green'::public.colors;\n\
+             CREATE OR REPLACE FUNCTION public.is_red (c public.colors)\n\
+             RETURNS bool AS $$SELECT c = 'red'$$\n\
+             LANGUAGE SQL"
                 .to_owned(),
         );
     }
@@ -1897,16 +4370,1151 @@ mod tests {
                 },
             ],
             constraints: Vec::new(),
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn it_groups_changes_into_transactional_phases() {
+        let enum_type = base_type();
+        let delta = Delta(
+            vec![
+                ChangeInstruction::DropDatabase("db".into()),
+                ChangeInstruction::CreateDatabase("db".into()),
+                ChangeInstruction::UseDatabase("db".into()),
+                ChangeInstruction::DropTable("public.orders".into()),
+                ChangeInstruction::DropSchema("archive".into()),
+                ChangeInstruction::ModifyType(
+                    &enum_type,
+                    TypeModificationAction::AddEnumValueAfter {
+                        value: "purple".into(),
+                        after: "blue".into(),
+                    },
+                ),
+                ChangeInstruction::DropType("public.sizes".into()),
+            ],
+            None,
+            None,
+        );
+
+        let phases = delta.phases();
+        let kinds = phases
+            .iter()
+            .map(|phase| match *phase {
+                Phase::Connect => "connect".to_owned(),
+                Phase::Standalone(_) => "standalone".to_owned(),
+                Phase::Transaction(ref changes) => format!("transaction({})", changes.len()),
+            })
+            .collect::<Vec<_>>();
+        assert_that!(kinds).is_equal_to(vec![
+            "standalone".to_owned(),
+            "standalone".to_owned(),
+            "connect".to_owned(),
+            "transaction(2)".to_owned(),
+            "standalone".to_owned(),
+            "transaction(1)".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_can_add_new_table() {
+        let log = empty_logger();
+        let source_table = base_table();
+
+        // Create an empty database
+        let existing_database = Package::new();
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
+        let result = (&source_table).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // We should have a single instruction to create a new table
+        assert_that!(change_set).has_length(1);
+        match change_set[0] {
+            ChangeInstruction::AddTable(ref table) => {
+                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(table.columns).has_length(3);
+                assert_that!(table.columns[0].name).is_equal_to("id".to_owned());
+                assert_that!(table.columns[1].name).is_equal_to("company_id".to_owned());
+                assert_that!(table.columns[2].name).is_equal_to("first_name".to_owned());
+                assert_that!(table.constraints).is_empty();
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+
+        // Check the SQL generation
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+            "CREATE TABLE my.contacts (\n\
+             \tid serial NOT NULL PRIMARY KEY,\n\
+             \tcompany_id bigint NOT NULL,\n\
+             \tfirst_name varchar(100) NOT NULL\n\
+             )"
+            .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_can_add_column_to_existing_table() {
+        let log = empty_logger();
+        let mut source_table = base_table();
+        source_table.columns.push(ColumnDefinition {
+            name: "last_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+
+        // Create a database with the base table already defined.
+        let mut existing_database = Package::new();
+        existing_database.tables.push(base_table());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
+        // First, check that source table changes do nothing
+        let _ = (&source_table).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(change_set).is_empty();
+
+        // Now we check with a linked column
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns.last().unwrap(),
+            conversion: None,
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // We should have a single instruction to create a new table
+        assert_that!(change_set).has_length(1);
+        match change_set[0] {
+            ChangeInstruction::AddColumn(ref table, ref column) => {
+                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(column.name).is_equal_to("last_name".to_owned());
+                assert_that!(column.sql_type)
+                    .is_equal_to(SqlType::Simple(SimpleSqlType::VariableLengthString(100), None));
+                assert_that!(column.constraints).has_length(1);
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+
+        // Check the SQL generation
+        assert_that!(change_set[0].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts ADD COLUMN last_name varchar(100) NOT NULL".to_owned());
+    }
+
+    #[test]
+    fn it_can_widen_column_on_existing_table() {
+        let log = empty_logger();
+        let mut source_table = base_table();
+        source_table.columns.push(ColumnDefinition {
+            name: "last_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(200), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+
+        // Create a database with the base table already defined.
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns.push(ColumnDefinition {
+            name: "last_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
+        // First, check that source table changes do nothing
+        let _ = (&source_table).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(change_set).is_empty();
+
+        // Now we check with a linked column
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns.last().unwrap(),
+            conversion: None,
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // We should have a single instruction to create a new table
+        assert_that!(change_set).has_length(1);
+        match change_set[0] {
+            ChangeInstruction::ModifyColumnType(ref table, ref column, using) => {
+                assert_that!(using).is_none();
+                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(column.name).is_equal_to("last_name".to_owned());
+                assert_that!(column.sql_type)
+                    .is_equal_to(SqlType::Simple(SimpleSqlType::VariableLengthString(200), None));
+                assert_that!(column.constraints).has_length(1);
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+
+        // Check the SQL generation
+        assert_that!(change_set[0].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts ALTER COLUMN last_name TYPE varchar(200)".to_owned());
+    }
+
+    #[test]
+    fn it_classifies_column_type_changes() {
+        let simple = |sql_type| SqlType::Simple(sql_type, None);
+        let cases = vec![
+            (
+                SimpleSqlType::VariableLengthString(50),
+                SimpleSqlType::Text,
+                TypeChange::Widening,
+            ),
+            (
+                SimpleSqlType::Text,
+                SimpleSqlType::VariableLengthString(50),
+                TypeChange::Narrowing,
+            ),
+            (
+                SimpleSqlType::VariableLengthString(50),
+                SimpleSqlType::VariableLengthString(10),
+                TypeChange::Narrowing,
+            ),
+            (SimpleSqlType::Integer, SimpleSqlType::BigInteger, TypeChange::Widening),
+            (SimpleSqlType::BigInteger, SimpleSqlType::Integer, TypeChange::Narrowing),
+            (
+                SimpleSqlType::Integer,
+                SimpleSqlType::Numeric(Some((12, 2))),
+                TypeChange::Widening,
+            ),
+            (
+                SimpleSqlType::Integer,
+                SimpleSqlType::Numeric(Some((8, 2))),
+                TypeChange::Narrowing,
+            ),
+            (
+                SimpleSqlType::Numeric(Some((10, 4))),
+                SimpleSqlType::Numeric(Some((12, 4))),
+                TypeChange::Widening,
+            ),
+            (
+                SimpleSqlType::Numeric(Some((10, 4))),
+                SimpleSqlType::Numeric(Some((10, 2))),
+                TypeChange::Narrowing,
+            ),
+            (SimpleSqlType::Double, SimpleSqlType::Integer, TypeChange::Narrowing),
+            (
+                SimpleSqlType::DateTime(None),
+                SimpleSqlType::DateTimeWithTimeZone(None),
+                TypeChange::Widening,
+            ),
+            (
+                SimpleSqlType::DateTime(None),
+                SimpleSqlType::DateTime(Some(0)),
+                TypeChange::Narrowing,
+            ),
+            (SimpleSqlType::Integer, SimpleSqlType::Text, TypeChange::Widening),
+            (SimpleSqlType::Text, SimpleSqlType::Integer, TypeChange::Incompatible),
+            (SimpleSqlType::Boolean, SimpleSqlType::Integer, TypeChange::Incompatible),
+        ];
+        for (from, to, expected) in cases {
+            assert_that!(classify_type_change(&simple(from.clone()), &simple(to.clone())))
+                .named(&format!("{} to {}", from, to))
+                .is_equal_to(expected);
+        }
+
+        // Arrays only compare against arrays of the same dimension
+        assert_that!(classify_type_change(
+            &SqlType::Simple(SimpleSqlType::Integer, Some(1)),
+            &SqlType::Simple(SimpleSqlType::BigInteger, Some(1))
+        ))
+        .is_equal_to(TypeChange::Widening);
+        assert_that!(classify_type_change(
+            &SqlType::Simple(SimpleSqlType::Integer, Some(1)),
+            &SqlType::Simple(SimpleSqlType::BigInteger, None)
+        ))
+        .is_equal_to(TypeChange::Incompatible);
+    }
+
+    #[test]
+    fn it_rejects_narrowing_a_column_by_default() {
+        let log = empty_logger();
+        let source_table = base_table();
+
+        // The existing column is wider than in source
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[2].sql_type = SqlType::Simple(SimpleSqlType::VariableLengthString(200), None);
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns[2],
+            conversion: None,
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_err();
+        match result.err().unwrap() {
+            PsqlpackError(PublishUnsafeOperationError(_), _) => {}
+            unexpected => panic!("Expected unsafe operation error however saw {:?}", unexpected),
+        };
+
+        // Once allowed, the column is narrowed
+        publish_profile.generation_options.narrow_column_types = Toggle::Allow;
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns[2],
+            conversion: None,
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+        assert_that!(change_set).has_length(2);
+        assert_that!(change_set[1].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts ALTER COLUMN first_name TYPE varchar(100)".to_owned());
+
+        // Values that change when converted are reported before narrowing, here as a warning
+        publish_profile.generation_options.block_on_possible_data_loss = false;
+        change_set.clear();
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns[2],
+            conversion: None,
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+            "DO $$\nDECLARE\n  lost boolean;\nBEGIN\n  BEGIN\n    \
+             lost := EXISTS (SELECT 1 FROM my.contacts WHERE first_name IS DISTINCT FROM \
+             first_name::varchar(100)::varchar(200));\n  \
+             EXCEPTION WHEN others THEN\n    lost := true;\n  END;\n  IF lost THEN\n    \
+             RAISE WARNING USING MESSAGE = 'Changing column first_name on table my.contacts from varchar(200) to \
+             varchar(100) would lose data as some values can''t be converted';\n  END IF;\nEND\n$$"
+                .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_requires_a_conversion_for_incompatible_column_types() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        let mut source_table = base_table();
+        source_table.columns[1].sql_type = SqlType::Simple(SimpleSqlType::Integer, None);
+        source_package.tables.push(source_table);
+
+        // The existing column holds the values as text
+        let existing_database = || {
+            let mut existing_database = Package::new();
+            let mut existing_table = base_table();
+            existing_table.columns[1].sql_type = SqlType::Simple(SimpleSqlType::Text, None);
+            existing_database.tables.push(existing_table);
+            existing_database
+        };
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_err();
+        match result.err().unwrap() {
+            PsqlpackError(PublishInvalidOperationError(_), _) => {}
+            unexpected => panic!("Expected invalid operation error however saw {:?}", unexpected),
+        };
+
+        // Supplying a conversion allows the type to be changed
+        source_package.push_conversion(ColumnConversion {
+            table: ObjectName {
+                schema: Some("my".to_owned()),
+                name: "contacts".to_owned(),
+            },
+            column: "company_id".to_owned(),
+            using: Some("company_id::integer".to_owned()),
+            backfill: None,
+        });
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+        assert_that!(change_set).has_length(2);
+        assert_that!(change_set[1].to_sql(&log)).is_equal_to(
+            "ALTER TABLE my.contacts ALTER COLUMN company_id TYPE int USING company_id::integer".to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_backfills_a_new_not_null_column_before_setting_not_null() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        let mut source_table = base_table();
+        source_table.columns.push(ColumnDefinition {
+            name: "display_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
+            constraints: vec![
+                ColumnConstraint::NotNull,
+                ColumnConstraint::Default(Expression::Value(AnyValue::String("".into(), None))),
+            ],
+        });
+        source_package.tables.push(source_table);
+        source_package.push_conversion(ColumnConversion {
+            table: ObjectName {
+                schema: Some("my".to_owned()),
+                name: "contacts".to_owned(),
+            },
+            column: "display_name".to_owned(),
+            using: None,
+            backfill: Some("first_name".to_owned()),
+        });
+
+        let mut existing_database = Package::new();
+        existing_database.tables.push(base_table());
+        let capabilities = Capabilities {
+            server_version: Semver::new(12, 0, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let delta = result.unwrap();
+
+        // The column is added as nullable, populated, then validated outside of the transaction
+        let sql = delta.0.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "-- Using database `dbname`".to_owned(),
+            "ALTER TABLE my.contacts ADD COLUMN display_name varchar(100)".to_owned(),
+            "UPDATE my.contacts SET display_name = first_name WHERE display_name IS NULL".to_owned(),
+            "ALTER TABLE my.contacts ALTER COLUMN display_name SET DEFAULT ''".to_owned(),
+            "ALTER TABLE my.contacts ADD CONSTRAINT contacts_display_name_not_null CHECK (display_name IS NOT NULL) \
+             NOT VALID"
+                .to_owned(),
+            "ALTER TABLE my.contacts VALIDATE CONSTRAINT contacts_display_name_not_null".to_owned(),
+            "ALTER TABLE my.contacts ALTER COLUMN display_name SET NOT NULL".to_owned(),
+            "ALTER TABLE my.contacts DROP CONSTRAINT contacts_display_name_not_null".to_owned(),
+        ]);
+        let phases = delta.phases();
+        assert_that!(phases).has_length(4);
+        assert_that!(phases[1].len()).is_equal_to(4);
+        assert_that!(phases[2].len()).is_equal_to(1);
+        assert_that!(phases[3].len()).is_equal_to(2);
+    }
+
+    #[test]
+    fn it_backfills_nulls_from_the_default_when_setting_not_null() {
+        let log = empty_logger();
+        let mut source_table = base_table();
+        source_table.columns[2].constraints = vec![
+            ColumnConstraint::NotNull,
+            ColumnConstraint::Default(Expression::Value(AnyValue::String("unknown".into(), None))),
+        ];
+
+        // The existing column allows nulls
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[2].constraints = vec![ColumnConstraint::Null];
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns[2],
+            conversion: None,
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // Prior to PG12 there is no benefit to validating a CHECK constraint first
+        let sql = change_set.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "ALTER TABLE my.contacts ALTER COLUMN first_name SET DEFAULT 'unknown'".to_owned(),
+            "UPDATE my.contacts SET first_name = 'unknown' WHERE first_name IS NULL".to_owned(),
+            "ALTER TABLE my.contacts ALTER COLUMN first_name SET NOT NULL".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_can_drop_column_on_existing_table() {
+        let log = empty_logger();
+        let source_table = base_table();
+
+        // Create a database with the base table already defined.
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns.push(ColumnDefinition {
+            name: "last_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_columns = Toggle::Allow;
+
+        let mut change_set = Vec::new();
+        let result = (&source_table).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // We should have an instruction to check for data followed by the drop
+        assert_that!(change_set).has_length(2);
+        match change_set[0] {
+            ChangeInstruction::CheckDataLoss(DataLoss::Column(ref table, ref column_name), block) => {
+                assert_that!(table.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(*column_name).is_equal_to("last_name".to_owned());
+                assert_that!(block).is_true();
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        match change_set[1] {
+            ChangeInstruction::DropColumn(ref table, ref column_name) => {
+                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(*column_name).is_equal_to("last_name".to_owned());
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+
+        // Check the SQL generation
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+            "DO $$\nBEGIN\n  IF EXISTS (SELECT 1 FROM my.contacts WHERE last_name IS NOT NULL) THEN\n    \
+             RAISE EXCEPTION USING MESSAGE = 'Dropping column last_name on table my.contacts would lose data as it \
+             contains values';\n  END IF;\nEND\n$$"
+                .to_owned(),
+        );
+        assert_that!(change_set[1].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts DROP COLUMN last_name".to_owned());
+    }
+
+    fn rename_first_name() -> RefactorDefinition {
+        RefactorDefinition {
+            id: "rename-first-name".to_owned(),
+            operation: RefactorOperation::RenameColumn {
+                table: ObjectName {
+                    schema: Some("my".to_owned()),
+                    name: "contacts".to_owned(),
+                },
+                column: "fname".to_owned(),
+                new_name: "first_name".to_owned(),
+            },
+        }
+    }
+
+    #[test]
+    fn it_can_rename_a_column_using_the_refactor_log() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+        source_package.refactors.push(rename_first_name());
+
+        // Create a database with the column under its old name
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[2].name = "fname".to_owned();
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        // Columns can't be dropped by default, so this also confirms the column isn't dropped and re-added
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &PublishProfile::default(),
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // Use database, create the log, rename the column then record it
+        assert_that!(change_set).has_length(4);
+        match change_set[1] {
+            ChangeInstruction::CreateRefactorLog => {}
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        match change_set[2] {
+            ChangeInstruction::ApplyRefactor(refactor) => {
+                assert_that!(refactor.id).is_equal_to("rename-first-name".to_owned());
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        match change_set[3] {
+            ChangeInstruction::RecordRefactor(refactor) => {
+                assert_that!(refactor.id).is_equal_to("rename-first-name".to_owned());
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+
+        // Check the SQL generation
+        assert_that!(change_set[2].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts RENAME COLUMN fname TO first_name".to_owned());
+        assert_that!(change_set[3].to_sql(&log)).starts_with(
+            "INSERT INTO psqlpack.refactor_log (id, definition) VALUES ('rename-first-name', '{\"id\":\"rename-first-name\"",
+        );
+    }
+
+    #[test]
+    fn it_requires_variables_to_be_resolved_before_generating() {
+        let log = empty_logger();
+        let package = || {
+            let mut package = Package::new();
+            let mut table = base_table();
+            table.name.schema = Some("$(Schema)".into());
+            package.tables.push(table);
+            package.push_variable(Variable {
+                name: "Schema".into(),
+                default: Some("tenant".into()),
+            });
+            package
+        };
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        // Placeholders would otherwise be published verbatim
+        let unresolved = package();
+        let result = Delta::generate(
+            &log,
+            &unresolved,
+            Some(Package::new()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        match result {
+            Err(PsqlpackError(UnresolvedVariablesError, _)) => {}
+            unexpected => panic!("Expected unresolved variables error however saw {:?}", unexpected),
+        }
+
+        // The resolved package can be used to generate changes any number of times
+        let resolved = package().resolve_variables(&BTreeMap::new()).unwrap();
+        for _ in 0..2 {
+            let result = Delta::generate(
+                &log,
+                &resolved,
+                Some(Package::new()),
+                "dbname",
+                &capabilities,
+                &publish_profile,
+            );
+            let change_set = match result.unwrap() {
+                Delta(c, ..) => c,
+            };
+            let sql = change_set.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+            assert_that!(sql.iter().any(|s| s.starts_with("CREATE TABLE tenant.contacts"))).is_true();
+        }
+    }
+
+    fn rename_red() -> RefactorDefinition {
+        RefactorDefinition {
+            id: "rename-red".to_owned(),
+            operation: RefactorOperation::RenameEnumValue {
+                type_name: base_type().name,
+                value: "red".to_owned(),
+                new_name: "crimson".to_owned(),
+            },
+        }
+    }
+
+    #[test]
+    fn it_can_rename_an_enum_value_using_the_refactor_log() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        let mut source_type = base_type();
+        source_type.kind = ast::TypeDefinitionKind::Enum(vec!["crimson".into(), "green".into(), "blue".into()]);
+        source_package.types.push(source_type);
+        source_package.refactors.push(rename_red());
+
+        let mut existing_database = Package::new();
+        existing_database.types.push(base_type());
+        let capabilities = Capabilities {
+            server_version: Semver::new(10, 0, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        // Enum values can't be dropped by default, so this also confirms the value isn't replaced
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &PublishProfile::default(),
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // Use database, create the log, rename the value then record it
+        assert_that!(change_set).has_length(4);
+        match change_set[2] {
+            ChangeInstruction::ApplyRefactor(refactor) => {
+                assert_that!(refactor.id).is_equal_to("rename-red".to_owned());
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        assert_that!(change_set[2].to_sql(&log))
+            .is_equal_to("ALTER TYPE public.colors RENAME VALUE 'red' TO 'crimson'".to_owned());
+    }
+
+    #[test]
+    fn it_replaces_enum_values_when_rename_is_unsupported() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        let mut source_type = base_type();
+        source_type.kind = ast::TypeDefinitionKind::Enum(vec!["crimson".into(), "green".into(), "blue".into()]);
+        source_package.types.push(source_type);
+        source_package.refactors.push(rename_red());
+
+        let mut existing_database = Package::new();
+        existing_database.types.push(base_type());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_enum_values = Toggle::Allow;
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // The refactoring is recorded, however the value is replaced rather than renamed
+        assert_that!(change_set).has_length(4);
+        match change_set[2] {
+            ChangeInstruction::RecordRefactor(refactor) => {
+                assert_that!(refactor.id).is_equal_to("rename-red".to_owned());
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        match change_set[3] {
+            ChangeInstruction::ModifyType(_, TypeModificationAction::ReplaceEnumValues { ref removed, .. }) => {
+                assert_that!(*removed).is_equal_to(vec!["red".to_owned()]);
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn it_ignores_refactorings_already_applied_to_the_target() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+        source_package.refactors.push(rename_first_name());
+
+        let mut existing_database = Package::new();
+        existing_database.tables.push(base_table());
+        existing_database.refactors.push(rename_first_name());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &PublishProfile::default(),
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // Only the use database instruction remains
+        assert_that!(change_set).has_length(1);
+    }
+
+    #[test]
+    fn it_runs_scripts_according_to_their_run_policy() {
+        let log = empty_logger();
+        let script = |name: &str, contents: &str, run| ScriptDefinition {
+            name: name.into(),
+            kind: ScriptKind::PostDeployment,
+            order: 0,
+            contents: contents.into(),
+            run,
+        };
+        let mut source_package = Package::new();
+        source_package
+            .scripts
+            .push(script("always.sql", "SELECT 1", ScriptRunPolicy::Always));
+        source_package
+            .scripts
+            .push(script("once.sql", "SELECT 2", ScriptRunPolicy::Once));
+        source_package
+            .scripts
+            .push(script("changed.sql", "SELECT 3", ScriptRunPolicy::OnChange));
+        source_package
+            .scripts
+            .push(script("unchanged.sql", "SELECT 4", ScriptRunPolicy::OnChange));
+        source_package
+            .scripts
+            .push(script("new.sql", "SELECT 5", ScriptRunPolicy::Once));
+
+        // Every tracked script has run before, one of which has since changed
+        let executed = |name: &str, contents: &str| ExecutedScript {
+            name: name.into(),
+            hash: script(name, contents, ScriptRunPolicy::Always).hash(),
+        };
+        let mut existing_database = Package::new();
+        existing_database
+            .executed_scripts
+            .push(executed("once.sql", "SELECT 2"));
+        existing_database
+            .executed_scripts
+            .push(executed("changed.sql", "SELECT 0"));
+        existing_database
+            .executed_scripts
+            .push(executed("unchanged.sql", "SELECT 4"));
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &PublishProfile::default(),
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+        let changes = change_set.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_that!(changes).is_equal_to(vec![
+            "Use database: dbname".to_owned(),
+            "Run script: always.sql".to_owned(),
+            "Create script log".to_owned(),
+            "Run script: changed.sql".to_owned(),
+            "Record script: changed.sql".to_owned(),
+            "Run script: new.sql".to_owned(),
+            "Record script: new.sql".to_owned(),
+        ]);
+        assert_that!(change_set[4].to_sql(&log)).is_equal_to(format!(
+            "INSERT INTO psqlpack.script_log (name, hash) VALUES ('changed.sql', '{}') \
+             ON CONFLICT (name) DO UPDATE SET hash = EXCLUDED.hash, executed_at = now()",
+            source_package.scripts[2].hash()
+        ));
+    }
+
+    #[test]
+    fn it_suggests_a_column_rename_when_detection_is_enabled() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+
+        // The column was renamed without a refactor log entry
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[2].name = "fname".to_owned();
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_columns = Toggle::Allow;
+        publish_profile.generation_options.detect_renames = true;
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // The suggestion is reported, but the column is still dropped and added
+        assert_that!(change_set).has_length(5);
+        match change_set[1] {
+            ChangeInstruction::SuggestRename(ref suggestion) => {
+                assert_that!(suggestion.confidence).is_equal_to(RenameConfidence::High);
+                assert_that!(suggestion.operation.to_string())
+                    .is_equal_to("rename column fname on table my.contacts to first_name".to_owned());
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        match change_set[3] {
+            ChangeInstruction::DropColumn(_, ref column_name) => {
+                assert_that!(*column_name).is_equal_to("fname".to_owned())
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        match change_set[4] {
+            ChangeInstruction::AddColumn(_, column) => assert_that!(column.name).is_equal_to("first_name".to_owned()),
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
+        }
+        assert_that!(change_set[1].to_sql(&log)).is_equal_to(
+            "-- Possible rename (high confidence): rename column fname on table my.contacts to first_name".to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_reports_ambiguous_column_renames_with_low_confidence() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[2].name = "fname".to_owned();
+        existing_table.columns.push(ColumnDefinition {
+            name: "given_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_columns = Toggle::Allow;
+        publish_profile.generation_options.accept_renames = Some(RenameConfidence::Medium);
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // Neither suggestion is confident enough to be accepted
+        let suggestions = change_set
+            .iter()
+            .filter_map(|c| match *c {
+                ChangeInstruction::SuggestRename(ref suggestion) => Some(suggestion.confidence),
+                ChangeInstruction::AcceptRename(_) => panic!("Unexpected rename accepted"),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_that!(suggestions).is_equal_to(vec![RenameConfidence::Low, RenameConfidence::Low]);
+    }
+
+    #[test]
+    fn it_can_accept_a_detected_table_rename() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.name.name = "people".to_owned();
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.accept_renames = Some(RenameConfidence::High);
+
+        // Tables can't be dropped by default so this also confirms the old table isn't dropped
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        assert_that!(change_set).has_length(2);
+        match change_set[1] {
+            ChangeInstruction::AcceptRename(ref suggestion) => {
+                assert_that!(suggestion.confidence).is_equal_to(RenameConfidence::High)
+            }
+            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
         }
+        assert_that!(change_set[1].to_sql(&log)).is_equal_to(
+            "ALTER TABLE my.people RENAME TO contacts;\nALTER INDEX IF EXISTS my.people_pkey RENAME TO contacts_pkey"
+                .to_owned(),
+        );
     }
 
     #[test]
-    fn it_can_add_new_table() {
+    fn it_can_set_an_expression_default_on_an_existing_column() {
         let log = empty_logger();
-        let source_table = base_table();
+        let mut source_table = base_table();
+        source_table.columns.push(ColumnDefinition {
+            name: "created".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::DateTimeWithTimeZone(None), None),
+            constraints: vec![
+                ColumnConstraint::NotNull,
+                ColumnConstraint::Default(Expression::FunctionCall(
+                    ObjectName {
+                        schema: None,
+                        name: "now".to_owned(),
+                    },
+                    Vec::new(),
+                )),
+            ],
+        });
 
-        // Create an empty database
-        let existing_database = Package::new();
+        // Create a database with the column but no default
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns.push(ColumnDefinition {
+            name: "created".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::DateTimeWithTimeZone(None), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+        existing_database.tables.push(existing_table);
         let capabilities = Capabilities {
             server_version: Semver::new(9, 6, None),
             extensions: Vec::new(),
@@ -1915,7 +5523,12 @@ mod tests {
         let publish_profile = PublishProfile::default();
 
         let mut change_set = Vec::new();
-        let result = (&source_table).generate(
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns.last().unwrap(),
+            conversion: None,
+        }
+        .generate(
             &mut change_set,
             &existing_database,
             &capabilities,
@@ -1923,45 +5536,81 @@ mod tests {
             &log,
         );
         assert_that!(result).is_ok();
-
-        // We should have a single instruction to create a new table
         assert_that!(change_set).has_length(1);
         match change_set[0] {
-            ChangeInstruction::AddTable(ref table) => {
+            ChangeInstruction::ModifyColumnDefault(ref table, ref column) => {
                 assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
-                assert_that!(table.columns).has_length(3);
-                assert_that!(table.columns[0].name).is_equal_to("id".to_owned());
-                assert_that!(table.columns[1].name).is_equal_to("company_id".to_owned());
-                assert_that!(table.columns[2].name).is_equal_to("first_name".to_owned());
-                assert_that!(table.constraints).is_empty();
+                assert_that!(column.name).is_equal_to("created".to_owned());
             }
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
         }
+        assert_that!(change_set[0].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts ALTER COLUMN created SET DEFAULT now()".to_owned());
 
-        // Check the SQL generation
-        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
-            "CREATE TABLE my.contacts (\n\
-             \tid serial NOT NULL PRIMARY KEY,\n\
-             \tcompany_id bigint NOT NULL,\n\
-             \tfirst_name varchar(100) NOT NULL\n\
-             )"
-            .to_owned(),
-        );
+        // Removing the default from the source should drop it
+        let mut source_database = Package::new();
+        source_database.tables.push(source_table);
+        let mut change_set = Vec::new();
+        let result = LinkedColumn {
+            table: &existing_database.tables[0],
+            column: &existing_database.tables[0].columns.last().unwrap(),
+            conversion: None,
+        }
+        .generate(&mut change_set, &source_database, &capabilities, &publish_profile, &log);
+        assert_that!(result).is_ok();
+        assert_that!(change_set).has_length(1);
+        assert_that!(change_set[0].to_sql(&log))
+            .is_equal_to("ALTER TABLE my.contacts ALTER COLUMN created DROP DEFAULT".to_owned());
     }
 
     #[test]
-    fn it_can_add_column_to_existing_table() {
+    fn it_ignores_defaults_normalized_by_postgres() {
         let log = empty_logger();
         let mut source_table = base_table();
-        source_table.columns.push(ColumnDefinition {
-            name: "last_name".to_owned(),
-            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
-            constraints: vec![ColumnConstraint::NotNull],
-        });
+        source_table.columns[2]
+            .constraints
+            .push(ColumnConstraint::Default(Expression::BinaryOperation(
+                Box::new(Expression::Value(AnyValue::String("mr".to_owned(), None))),
+                BinaryOperator::Concat,
+                Box::new(Expression::FunctionCall(
+                    ObjectName {
+                        schema: None,
+                        name: "NEXTVAL".to_owned(),
+                    },
+                    vec![Expression::Value(AnyValue::String("my.seq".to_owned(), None))],
+                )),
+            )));
 
-        // Create a database with the base table already defined.
+        // Postgres stores literals with explicit casts
         let mut existing_database = Package::new();
-        existing_database.tables.push(base_table());
+        let mut existing_table = base_table();
+        existing_table.columns[2]
+            .constraints
+            .push(ColumnConstraint::Default(Expression::BinaryOperation(
+                Box::new(Expression::Value(AnyValue::String(
+                    "mr".to_owned(),
+                    Some(SqlType::Simple(SimpleSqlType::Text, None)),
+                ))),
+                BinaryOperator::Concat,
+                Box::new(Expression::FunctionCall(
+                    ObjectName {
+                        schema: None,
+                        name: "nextval".to_owned(),
+                    },
+                    vec![Expression::Value(AnyValue::String(
+                        "my.seq".to_owned(),
+                        Some(SqlType::Custom(
+                            ObjectName {
+                                schema: None,
+                                name: "regclass".to_owned(),
+                            },
+                            None,
+                            None,
+                        )),
+                    ))],
+                )),
+            )));
+        existing_database.tables.push(existing_table);
         let capabilities = Capabilities {
             server_version: Semver::new(9, 6, None),
             extensions: Vec::new(),
@@ -1970,20 +5619,54 @@ mod tests {
         let publish_profile = PublishProfile::default();
 
         let mut change_set = Vec::new();
-        // First, check that source table changes do nothing
-        let _ = (&source_table).generate(
+        let result = LinkedColumn {
+            table: &source_table,
+            column: &source_table.columns[2],
+            conversion: None,
+        }
+        .generate(
             &mut change_set,
             &existing_database,
             &capabilities,
             &publish_profile,
             &log,
         );
+        assert_that!(result).is_ok();
         assert_that!(change_set).is_empty();
+    }
 
-        // Now we check with a linked column
+    #[test]
+    fn it_ignores_negative_defaults_stored_as_strings() {
+        let log = empty_logger();
+        let mut source_table = base_table();
+        source_table.columns[1]
+            .constraints
+            .push(ColumnConstraint::Default(Expression::Value(AnyValue::Integer(
+                -1, None,
+            ))));
+
+        // Postgres stores negative numbers as a quoted literal with a cast
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[1]
+            .constraints
+            .push(ColumnConstraint::Default(Expression::Value(AnyValue::String(
+                "-1".to_owned(),
+                Some(SqlType::Simple(SimpleSqlType::BigInteger, None)),
+            ))));
+        existing_database.tables.push(existing_table);
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
         let result = LinkedColumn {
             table: &source_table,
-            column: &source_table.columns.last().unwrap(),
+            column: &source_table.columns[1],
+            conversion: None,
         }
         .generate(
             &mut change_set,
@@ -1993,44 +5676,57 @@ mod tests {
             &log,
         );
         assert_that!(result).is_ok();
+        assert_that!(change_set).is_empty();
+    }
 
-        // We should have a single instruction to create a new table
-        assert_that!(change_set).has_length(1);
-        match change_set[0] {
-            ChangeInstruction::AddColumn(ref table, ref column) => {
+    #[test]
+    fn it_sets_the_owner_of_a_new_table() {
+        let log = empty_logger();
+        let mut source_table = base_table();
+        source_table.owner = Some("app_owner".to_owned());
+
+        // Create an empty database
+        let existing_database = Package::new();
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let publish_profile = PublishProfile::default();
+
+        let mut change_set = Vec::new();
+        let result = (&source_table).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // We should create the table and then set the owner
+        assert_that!(change_set).has_length(2);
+        match change_set[1] {
+            ChangeInstruction::SetTableOwner(ref table) => {
                 assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
-                assert_that!(column.name).is_equal_to("last_name".to_owned());
-                assert_that!(column.sql_type)
-                    .is_equal_to(SqlType::Simple(SimpleSqlType::VariableLengthString(100), None));
-                assert_that!(column.constraints).has_length(1);
             }
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
         }
 
         // Check the SQL generation
-        assert_that!(change_set[0].to_sql(&log))
-            .is_equal_to("ALTER TABLE my.contacts ADD COLUMN last_name varchar(100) NOT NULL".to_owned());
+        assert_that!(change_set[1].to_sql(&log)).is_equal_to("ALTER TABLE my.contacts OWNER TO app_owner".to_owned());
     }
 
     #[test]
-    fn it_can_widen_column_on_existing_table() {
+    fn it_can_change_the_owner_of_an_existing_table() {
         let log = empty_logger();
         let mut source_table = base_table();
-        source_table.columns.push(ColumnDefinition {
-            name: "last_name".to_owned(),
-            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(200), None),
-            constraints: vec![ColumnConstraint::NotNull],
-        });
+        source_table.owner = Some("app_owner".to_owned());
 
-        // Create a database with the base table already defined.
+        // Create a database with the base table already defined under a different owner
         let mut existing_database = Package::new();
         let mut existing_table = base_table();
-        existing_table.columns.push(ColumnDefinition {
-            name: "last_name".to_owned(),
-            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
-            constraints: vec![ColumnConstraint::NotNull],
-        });
-
+        existing_table.owner = Some("postgres".to_owned());
         existing_database.tables.push(existing_table);
         let capabilities = Capabilities {
             server_version: Semver::new(9, 6, None),
@@ -2040,22 +5736,24 @@ mod tests {
         let publish_profile = PublishProfile::default();
 
         let mut change_set = Vec::new();
-        // First, check that source table changes do nothing
-        let _ = (&source_table).generate(
+        let result = (&source_table).generate(
             &mut change_set,
             &existing_database,
             &capabilities,
             &publish_profile,
             &log,
         );
-        assert_that!(change_set).is_empty();
+        assert_that!(result).is_ok();
+        assert_that!(change_set).has_length(1);
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to("ALTER TABLE my.contacts OWNER TO app_owner".to_owned());
 
-        // Now we check with a linked column
-        let result = LinkedColumn {
-            table: &source_table,
-            column: &source_table.columns.last().unwrap(),
-        }
-        .generate(
+        // If the owner matches, or the source doesn't declare one, then nothing should change
+        let mut change_set = Vec::new();
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.owner = Some("app_owner".to_owned());
+        existing_database.tables.push(existing_table);
+        let result = (&source_table).generate(
             &mut change_set,
             &existing_database,
             &capabilities,
@@ -2063,50 +5761,56 @@ mod tests {
             &log,
         );
         assert_that!(result).is_ok();
-
-        // We should have a single instruction to create a new table
-        assert_that!(change_set).has_length(1);
-        match change_set[0] {
-            ChangeInstruction::ModifyColumnType(ref table, ref column) => {
-                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
-                assert_that!(column.name).is_equal_to("last_name".to_owned());
-                assert_that!(column.sql_type)
-                    .is_equal_to(SqlType::Simple(SimpleSqlType::VariableLengthString(200), None));
-                assert_that!(column.constraints).has_length(1);
-            }
-            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
-        }
-
-        // Check the SQL generation
-        assert_that!(change_set[0].to_sql(&log))
-            .is_equal_to("ALTER TABLE my.contacts ALTER COLUMN last_name TYPE varchar(200)".to_owned());
+        let unowned_table = base_table();
+        let result = (&unowned_table).generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+        assert_that!(change_set).is_empty();
     }
 
     #[test]
-    fn it_can_drop_column_on_existing_table() {
+    fn it_can_change_the_owner_of_a_function() {
         let log = empty_logger();
-        let source_table = base_table();
-
-        // Create a database with the base table already defined.
-        let mut existing_database = Package::new();
-        let mut existing_table = base_table();
-        existing_table.columns.push(ColumnDefinition {
-            name: "last_name".to_owned(),
-            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
-            constraints: vec![ColumnConstraint::NotNull],
-        });
+        let source_function = FunctionDefinition {
+            name: ObjectName {
+                schema: Some("my".to_owned()),
+                name: "fn_contacts".to_owned(),
+            },
+            arguments: vec![
+                FunctionArgument {
+                    mode: None,
+                    name: Some("company_id".to_owned()),
+                    sql_type: SqlType::Simple(SimpleSqlType::BigInteger, None),
+                    default: None,
+                },
+                FunctionArgument {
+                    mode: Some(FunctionArgumentMode::Out),
+                    name: Some("total".to_owned()),
+                    sql_type: SqlType::Simple(SimpleSqlType::Integer, None),
+                    default: None,
+                },
+            ],
+            return_type: FunctionReturnType::SqlType(SqlType::Simple(SimpleSqlType::Integer, None)),
+            body: "SELECT 1".to_owned(),
+            language: FunctionLanguage::SQL,
+            owner: Some("app_owner".to_owned()),
+        };
 
-        existing_database.tables.push(existing_table);
+        let existing_database = Package::new();
         let capabilities = Capabilities {
             server_version: Semver::new(9, 6, None),
             extensions: Vec::new(),
             database_exists: true,
         };
-        let mut publish_profile = PublishProfile::default();
-        publish_profile.generation_options.drop_columns = Toggle::Allow;
+        let publish_profile = PublishProfile::default();
 
         let mut change_set = Vec::new();
-        let result = (&source_table).generate(
+        let result = (&source_function).generate(
             &mut change_set,
             &existing_database,
             &capabilities,
@@ -2115,19 +5819,10 @@ mod tests {
         );
         assert_that!(result).is_ok();
 
-        // We should have a single instruction to create a new table
-        assert_that!(change_set).has_length(1);
-        match change_set[0] {
-            ChangeInstruction::DropColumn(ref table, ref column_name) => {
-                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
-                assert_that!(*column_name).is_equal_to("last_name".to_owned());
-            }
-            ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
-        }
-
-        // Check the SQL generation
-        assert_that!(change_set[0].to_sql(&log))
-            .is_equal_to("ALTER TABLE my.contacts DROP COLUMN last_name".to_owned());
+        // The function is replaced and then the owner is set using the function signature
+        assert_that!(change_set).has_length(2);
+        assert_that!(change_set[1].to_sql(&log))
+            .is_equal_to("ALTER FUNCTION my.fn_contacts(bigint) OWNER TO app_owner".to_owned());
     }
 
     #[test]
@@ -2241,7 +5936,7 @@ mod tests {
         assert_that!(change_set).has_length(1);
         match change_set[0] {
             ChangeInstruction::DropConstraint(ref table, ref name) => {
-                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(table.to_string()).is_equal_to("my.contacts".to_owned());
                 assert_that!(*name).is_equal_to("pk_my_contacts_id".to_owned());
             }
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
@@ -2308,7 +6003,7 @@ mod tests {
         assert_that!(change_set).has_length(2);
         match change_set[0] {
             ChangeInstruction::DropConstraint(ref table, ref name) => {
-                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(table.to_string()).is_equal_to("my.contacts".to_owned());
                 assert_that!(*name).is_equal_to("pk_my_contacts_id".to_owned());
             }
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
@@ -2427,13 +6122,66 @@ mod tests {
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
         }
 
-        // Check the SQL generation
-        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+        // Check the SQL generation
+        assert_that!(change_set[0].to_sql(&log)).is_equal_to(
+            "ALTER TABLE my.contacts\n\
+             ADD CONSTRAINT fk_my_contacts_my_companies FOREIGN KEY (company_id) \
+             REFERENCES my.companies (id) MATCH SIMPLE ON UPDATE CASCADE ON DELETE NO ACTION"
+                .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_can_add_a_foreign_key_online() {
+        let log = empty_logger();
+        let mut source_table = base_table();
+        source_table.constraints.push(TableConstraint::Foreign {
+            name: "fk_my_contacts_my_companies".to_owned(),
+            columns: vec!["company_id".into()],
+            ref_table: ObjectName {
+                schema: Some("my".into()),
+                name: "companies".into(),
+            },
+            ref_columns: vec!["id".into()],
+            match_type: None,
+            events: None,
+        });
+
+        // Create a database with the base table already defined.
+        let mut existing_database = Package::new();
+        existing_database.tables.push(base_table());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.online_constraints = true;
+
+        let mut change_set = Vec::new();
+        let result = LinkedTableConstraint {
+            table: &source_table,
+            constraint: &source_table.constraints[0],
+        }
+        .generate(
+            &mut change_set,
+            &existing_database,
+            &capabilities,
+            &publish_profile,
+            &log,
+        );
+        assert_that!(result).is_ok();
+
+        // The constraint is added without checking existing rows, which are validated separately
+        let sql = change_set.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
             "ALTER TABLE my.contacts\n\
-             ADD CONSTRAINT fk_my_contacts_my_companies FOREIGN KEY (company_id) \
-             REFERENCES my.companies (id) MATCH SIMPLE ON UPDATE CASCADE ON DELETE NO ACTION"
+             ADD CONSTRAINT fk_my_contacts_my_companies FOREIGN KEY (company_id) REFERENCES my.companies (id) NOT VALID"
                 .to_owned(),
-        );
+            "ALTER TABLE my.contacts\nVALIDATE CONSTRAINT fk_my_contacts_my_companies".to_owned(),
+        ]);
+        assert_that!(change_set[0].is_transactional()).is_true();
+        assert_that!(change_set[1].is_transactional()).is_false();
     }
 
     #[test]
@@ -2482,7 +6230,7 @@ mod tests {
         assert_that!(change_set).has_length(1);
         match change_set[0] {
             ChangeInstruction::DropConstraint(ref table, ref name) => {
-                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(table.to_string()).is_equal_to("my.contacts".to_owned());
                 assert_that!(*name).is_equal_to("fk_my_contacts_my_companies".to_owned());
             }
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
@@ -2567,7 +6315,7 @@ mod tests {
         assert_that!(change_set).has_length(2);
         match change_set[0] {
             ChangeInstruction::DropConstraint(ref table, ref name) => {
-                assert_that!(table.name.to_string()).is_equal_to("my.contacts".to_owned());
+                assert_that!(table.to_string()).is_equal_to("my.contacts".to_owned());
                 assert_that!(*name).is_equal_to("fk_my_contacts_my_companies".to_owned());
             }
             ref unexpected => panic!("Unexpected instruction type: {:?}", unexpected),
@@ -2669,6 +6417,290 @@ mod tests {
         );
     }
 
+    fn database_with_palette_schema() -> Package {
+        // A schema containing a type used by a table
+        let mut existing_database = Package::new();
+        existing_database.schemas.push(SchemaDefinition {
+            name: "palette".to_owned(),
+            owner: None,
+        });
+        let mut existing_type = base_type();
+        existing_type.name.schema = Some("palette".to_owned());
+        let mut existing_table = base_table();
+        existing_table.name.schema = Some("palette".to_owned());
+        existing_table.columns.push(ColumnDefinition {
+            name: "favorite_color".to_owned(),
+            sql_type: SqlType::Custom(existing_type.name.clone(), None, None),
+            constraints: Vec::new(),
+        });
+        existing_database.types.push(existing_type);
+        existing_database.tables.push(existing_table);
+        existing_database
+    }
+
+    #[test]
+    fn it_rejects_dropping_a_type_still_used_by_a_function() {
+        let log = empty_logger();
+        let source_package = Package::new();
+        let mut existing_database = database_with_palette_schema();
+        existing_database.functions.push(FunctionDefinition {
+            name: ObjectName {
+                schema: Some("palette".to_owned()),
+                name: "is_red".to_owned(),
+            },
+            arguments: vec![FunctionArgument {
+                mode: None,
+                name: Some("c".to_owned()),
+                sql_type: SqlType::Custom(existing_database.types[0].name.clone(), None, None),
+                default: None,
+            }],
+            return_type: FunctionReturnType::SqlType(SqlType::Simple(SimpleSqlType::Boolean, None)),
+            body: "SELECT c = 'red'".to_owned(),
+            language: FunctionLanguage::SQL,
+            owner: None,
+        });
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        // The function is kept, so still uses the type
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_tables = Toggle::Allow;
+        publish_profile.generation_options.drop_functions = Toggle::Ignore;
+        publish_profile.generation_options.drop_types = Toggle::Allow;
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(existing_database),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_err();
+        match result.err().unwrap() {
+            PsqlpackError(PublishUnsafeOperationError(message), _) => assert_that!(message).is_equal_to(
+                "Unable to drop type palette.colors as it is still used by function palette.is_red".to_owned(),
+            ),
+            unexpected => panic!("Unexpected error: {:?}", unexpected),
+        }
+    }
+
+    fn database_with_company_references() -> Package {
+        let companies = ObjectName {
+            schema: Some("my".into()),
+            name: "companies".into(),
+        };
+        let foreign_key = |name: &str| TableConstraint::Foreign {
+            name: name.to_owned(),
+            columns: vec!["company_id".into()],
+            ref_table: companies.clone(),
+            ref_columns: vec!["id".into()],
+            match_type: None,
+            events: None,
+        };
+        let mut contacts = base_table();
+        contacts.constraints.push(foreign_key("fk_my_contacts_my_companies"));
+        let mut departments = base_table();
+        departments.name.name = "departments".into();
+        departments
+            .constraints
+            .push(foreign_key("fk_my_departments_my_companies"));
+        let mut database = Package::new();
+        database.tables.push(contacts);
+        database.tables.push(departments);
+        database.tables.push(ast::TableDefinition {
+            name: companies.clone(),
+            columns: vec![ColumnDefinition {
+                name: "id".to_owned(),
+                sql_type: SqlType::Simple(SimpleSqlType::Serial, None),
+                constraints: vec![ColumnConstraint::NotNull, ColumnConstraint::PrimaryKey],
+            }],
+            constraints: Vec::new(),
+            owner: None,
+        });
+        database
+    }
+
+    #[test]
+    fn it_drops_referencing_foreign_keys_before_tables() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_tables = Toggle::Allow;
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(database_with_company_references()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // Both foreign keys are dropped before the referenced table, and only once
+        let sql = change_set
+            .iter()
+            .filter(|c| !matches!(c, ChangeInstruction::CheckDataLoss(..)))
+            .map(|c| c.to_sql(&log))
+            .collect::<Vec<_>>();
+        assert_that!(sql).has_length(5);
+        let position = |statement: &str| sql.iter().position(|s| s == statement).unwrap();
+        let companies = position("DROP TABLE IF EXISTS my.companies");
+        let departments = position("DROP TABLE IF EXISTS my.departments");
+        assert_that!(position(
+            "ALTER TABLE my.contacts\nDROP CONSTRAINT fk_my_contacts_my_companies"
+        ))
+        .is_less_than(companies);
+        assert_that!(position(
+            "ALTER TABLE my.departments\nDROP CONSTRAINT fk_my_departments_my_companies"
+        ))
+        .is_less_than(companies.min(departments));
+    }
+
+    #[test]
+    fn it_rejects_dropping_a_referenced_table_when_dropping_foreign_keys_is_disabled() {
+        let log = empty_logger();
+        let mut source_package = Package::new();
+        source_package.tables.push(base_table());
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_tables = Toggle::Allow;
+        publish_profile.generation_options.drop_foreign_key_constraints = Toggle::Error;
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(database_with_company_references()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_err();
+        let err = result.err().unwrap();
+        match err {
+            PsqlpackError(PublishUnsafeOperationError(message), _) => assert_that!(message).is_equal_to(
+                "Unable to drop table my.companies as it is referenced by constraint fk_my_contacts_my_companies \
+                 on table my.contacts and dropping FKs is currently disabled"
+                    .to_owned(),
+            ),
+            unexpected => panic!("Unexpected error: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn it_drops_types_and_schemas_after_dependent_tables() {
+        let log = empty_logger();
+        let source_package = Package::new();
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_tables = Toggle::Allow;
+        publish_profile.generation_options.drop_types = Toggle::Allow;
+        publish_profile.generation_options.drop_schemas = Toggle::Allow;
+
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(database_with_palette_schema()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+
+        // Use database, then the table, type and schema in that order
+        let sql = change_set.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "-- Using database `dbname`".to_owned(),
+            "DO $$\nBEGIN\n  IF EXISTS (SELECT 1 FROM palette.contacts) THEN\n    RAISE EXCEPTION USING MESSAGE = \
+             'Dropping table palette.contacts would lose data as it contains rows';\n  END IF;\nEND\n$$"
+                .to_owned(),
+            "DROP TABLE IF EXISTS palette.contacts".to_owned(),
+            "DROP TYPE IF EXISTS palette.colors".to_owned(),
+            "DROP SCHEMA IF EXISTS palette".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_rejects_dropping_a_type_still_used_by_a_table() {
+        let log = empty_logger();
+        let source_package = Package::new();
+        let capabilities = Capabilities {
+            server_version: Semver::new(9, 6, None),
+            extensions: Vec::new(),
+            database_exists: true,
+        };
+
+        // Types are left alone by default
+        let mut publish_profile = PublishProfile::default();
+        publish_profile.generation_options.drop_tables = Toggle::Ignore;
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(database_with_palette_schema()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_ok();
+        let change_set = match result.unwrap() {
+            Delta(c, ..) => c,
+        };
+        assert_that!(change_set).has_length(1);
+
+        // Though can be configured to error
+        publish_profile.generation_options.drop_types = Toggle::Error;
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(database_with_palette_schema()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_err();
+
+        // Even when allowed, the table that is left behind still uses the type
+        publish_profile.generation_options.drop_types = Toggle::Allow;
+        let result = Delta::generate(
+            &log,
+            &source_package,
+            Some(database_with_palette_schema()),
+            "dbname",
+            &capabilities,
+            &publish_profile,
+        );
+        assert_that!(result).is_err();
+        match result.err().unwrap() {
+            PsqlpackError(PublishUnsafeOperationError(message), _) => assert_that!(message).is_equal_to(
+                "Unable to drop type palette.colors as it is still used by table palette.contacts".to_owned(),
+            ),
+            unexpected => panic!("Unexpected error: {:?}", unexpected),
+        }
+    }
+
     #[test]
     fn it_can_remove_an_existing_index() {
         let log = empty_logger();
@@ -2730,7 +6762,7 @@ mod tests {
         );
         assert_that!(result).is_ok();
         let change_set = match result.unwrap() {
-            Delta(c) => c,
+            Delta(c, ..) => c,
         };
 
         // We should have a single instruction to remove an index (first will be use database)
@@ -3224,4 +7256,258 @@ mod tests {
                 .to_owned();
         assert_that!(format!("{}", err)).is_equal_to(&expect);
     }
+
+    #[test]
+    fn it_can_rollback_column_changes() {
+        let log = empty_logger();
+
+        // The existing table has a last name which is being dropped
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns.push(ColumnDefinition {
+            name: "last_name".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(100), None),
+            constraints: vec![ColumnConstraint::Null],
+        });
+        existing_database.tables.push(existing_table);
+
+        // The source table adds a nickname and widens the first name
+        let mut source_table = base_table();
+        source_table.columns[2].sql_type = SqlType::Simple(SimpleSqlType::VariableLengthString(200), None);
+        source_table.columns.push(ColumnDefinition {
+            name: "nickname".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(50), None),
+            constraints: vec![ColumnConstraint::Null],
+        });
+
+        let delta = Delta(
+            vec![
+                ChangeInstruction::UseDatabase("db".into()),
+                ChangeInstruction::ModifyColumnType(&source_table, &source_table.columns[2], None),
+                ChangeInstruction::AddColumn(&source_table, &source_table.columns[3]),
+                ChangeInstruction::CheckDataLoss(DataLoss::Column(source_table.name.clone(), "last_name".into()), true),
+                ChangeInstruction::DropColumn(&source_table, "last_name".into()),
+            ],
+            None,
+            None,
+        );
+        let rollback = delta.rollback(Some(&existing_database));
+        let sql = rollback.0.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "-- Using database `db`".to_owned(),
+            "-- Unable to roll back: Data within column last_name on table my.contacts is not restored".to_owned(),
+            "ALTER TABLE my.contacts ADD COLUMN last_name varchar(100) NULL".to_owned(),
+            "ALTER TABLE my.contacts DROP COLUMN nickname".to_owned(),
+            "DO $$\nDECLARE\n  lost boolean;\nBEGIN\n  BEGIN\n    \
+             lost := EXISTS (SELECT 1 FROM my.contacts WHERE first_name IS DISTINCT FROM \
+             first_name::varchar(100)::varchar(200));\n  \
+             EXCEPTION WHEN others THEN\n    lost := true;\n  END;\n  IF lost THEN\n    \
+             RAISE EXCEPTION USING MESSAGE = 'Changing column first_name on table my.contacts from varchar(200) to \
+             varchar(100) would lose data as some values can''t be converted';\n  END IF;\nEND\n$$"
+                .to_owned(),
+            "ALTER TABLE my.contacts ALTER COLUMN first_name TYPE varchar(100)".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_flags_column_types_that_cant_be_rolled_back() {
+        let log = empty_logger();
+
+        // The existing table has an age stored as text and a color using a custom type
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns.push(ColumnDefinition {
+            name: "age".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::Text, None),
+            constraints: vec![ColumnConstraint::Null],
+        });
+        existing_table.columns.push(ColumnDefinition {
+            name: "color".to_owned(),
+            sql_type: SqlType::Custom(base_type().name, None, None),
+            constraints: vec![ColumnConstraint::Null],
+        });
+        existing_database.tables.push(existing_table);
+
+        // The source converts the age to an integer and the color to text
+        let mut source_table = base_table();
+        source_table.columns.push(ColumnDefinition {
+            name: "age".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::Integer, None),
+            constraints: vec![ColumnConstraint::Null],
+        });
+        source_table.columns.push(ColumnDefinition {
+            name: "color".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::Text, None),
+            constraints: vec![ColumnConstraint::Null],
+        });
+
+        let delta = Delta(
+            vec![
+                ChangeInstruction::UseDatabase("db".into()),
+                ChangeInstruction::ModifyColumnType(&source_table, &source_table.columns[3], Some("age::integer")),
+                ChangeInstruction::ModifyColumnType(&source_table, &source_table.columns[4], None),
+            ],
+            None,
+            None,
+        );
+        let rollback = delta.rollback(Some(&existing_database));
+        let sql = rollback.0.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "-- Using database `db`".to_owned(),
+            "-- Unable to roll back: Column color on table my.contacts can't be converted back from text to \
+             public.colors without a conversion"
+                .to_owned(),
+            "-- Unable to roll back: Values of column age on table my.contacts converted using age::integer are not \
+             restored"
+                .to_owned(),
+            "ALTER TABLE my.contacts ALTER COLUMN age TYPE text".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_can_rollback_a_table_rename() {
+        let log = empty_logger();
+        let refactor = RefactorDefinition {
+            id: "rename-people".into(),
+            operation: RefactorOperation::RenameTable {
+                table: ast::ObjectName {
+                    schema: Some("my".into()),
+                    name: "people".into(),
+                },
+                new_name: "contacts".into(),
+            },
+        };
+        let existing_database = Package::new();
+
+        // The table is renamed back and the refactoring forgotten so that the next publish applies it again
+        let delta = Delta(
+            vec![
+                ChangeInstruction::UseDatabase("db".into()),
+                ChangeInstruction::CreateRefactorLog,
+                ChangeInstruction::ApplyRefactor(&refactor),
+                ChangeInstruction::RecordRefactor(&refactor),
+            ],
+            None,
+            None,
+        );
+        let rollback = delta.rollback(Some(&existing_database));
+        let sql = rollback.0.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "-- Using database `db`".to_owned(),
+            "DELETE FROM psqlpack.refactor_log WHERE id = 'rename-people'".to_owned(),
+            "ALTER TABLE my.contacts RENAME TO people;\nALTER INDEX IF EXISTS my.contacts_pkey RENAME TO people_pkey"
+                .to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_flags_changes_that_cant_be_rolled_back() {
+        let log = empty_logger();
+        let script = ScriptDefinition {
+            name: "seed".into(),
+            kind: ScriptKind::PostDeployment,
+            order: 0,
+            contents: "INSERT INTO my.contacts (company_id, first_name) VALUES (1, 'Jane')".into(),
+            run: ScriptRunPolicy::Always,
+        };
+        let table = base_table();
+        let existing_database = Package::new();
+
+        // A database that already existed is reverted change by change
+        let delta = Delta(
+            vec![
+                ChangeInstruction::UseDatabase("db".into()),
+                ChangeInstruction::AddTable(&table),
+                ChangeInstruction::RunScript(&script),
+            ],
+            None,
+            None,
+        );
+        let rollback = delta.rollback(Some(&existing_database));
+        let sql = rollback.0.iter().map(|c| c.to_sql(&log)).collect::<Vec<_>>();
+        assert_that!(sql).is_equal_to(vec![
+            "-- Using database `db`".to_owned(),
+            "-- Unable to roll back: Run script: seed".to_owned(),
+            "DROP TABLE IF EXISTS my.contacts".to_owned(),
+        ]);
+
+        // A database that was created is simply dropped, however one that was recreated can't be restored
+        let delta = Delta(
+            vec![
+                ChangeInstruction::DropDatabase("db".into()),
+                ChangeInstruction::CreateDatabase("db".into()),
+                ChangeInstruction::UseDatabase("db".into()),
+                ChangeInstruction::AddTable(&table),
+            ],
+            None,
+            None,
+        );
+        let rollback = delta.rollback(Some(&existing_database));
+        let changes = rollback.0.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_that!(changes).is_equal_to(vec![
+            "Unable to roll back: Drop database: db".to_owned(),
+            "Drop database: db".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn it_generates_a_versioned_report() {
+        let log = empty_logger();
+
+        // The existing table has a longer first name
+        let mut existing_database = Package::new();
+        let mut existing_table = base_table();
+        existing_table.columns[2].sql_type = SqlType::Simple(SimpleSqlType::VariableLengthString(200), None);
+        existing_database.tables.push(existing_table);
+
+        let source_table = base_table();
+        let delta = Delta(
+            vec![
+                ChangeInstruction::ModifyColumnType(&source_table, &source_table.columns[2], None),
+                ChangeInstruction::DropTable("my.orders".into()),
+            ],
+            None,
+            None,
+        );
+        let report = delta.report(&log, Some(&existing_database));
+        assert_that!(report).is_equal_to(Report {
+            version: REPORT_VERSION,
+            changes: vec![
+                ReportEntry {
+                    operation: Operation::Alter,
+                    object_type: Some(ObjectType::Column),
+                    name: Some("my.contacts.first_name".into()),
+                    description: "Modify type for column: first_name on table: my.contacts".into(),
+                    before: Some("varchar(200)".into()),
+                    after: Some("varchar(100)".into()),
+                    risk: Risk::High,
+                    sql: "ALTER TABLE my.contacts ALTER COLUMN first_name TYPE varchar(100)".into(),
+                },
+                ReportEntry {
+                    operation: Operation::Drop,
+                    object_type: Some(ObjectType::Table),
+                    name: Some("my.orders".into()),
+                    description: "Drop table: my.orders".into(),
+                    before: None,
+                    after: None,
+                    risk: Risk::High,
+                    sql: "DROP TABLE IF EXISTS my.orders".into(),
+                },
+            ],
+        });
+
+        // The serialized form is independent of the AST
+        let json = serde_json::to_value(&report).unwrap();
+        assert_that!(json["version"]).is_equal_to(serde_json::json!(1));
+        assert_that!(json["changes"][0]).is_equal_to(serde_json::json!({
+            "operation": "alter",
+            "objectType": "column",
+            "name": "my.contacts.first_name",
+            "description": "Modify type for column: first_name on table: my.contacts",
+            "before": "varchar(200)",
+            "after": "varchar(100)",
+            "risk": "high",
+            "sql": "ALTER TABLE my.contacts ALTER COLUMN first_name TYPE varchar(100)"
+        }));
+    }
 }
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use slog::Logger;
+
+use crate::connection::Connection;
+use crate::errors::PsqlpackErrorKind::*;
+use crate::errors::{PsqlpackResult, PsqlpackResultExt};
+use crate::model::{Capabilities, Delta, Deployment, ObjectType, Package, PublishProfile, Toggle};
+
+/// Changes made to a database outside of psqlpack since the last publish
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Drift {
+    /// The id of the deployment that the database was compared against
+    pub deployment: i32,
+    #[serde(rename = "packageHash")]
+    pub package_hash: String,
+    pub changes: Vec<DriftEntry>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DriftEntry {
+    pub kind: DriftKind,
+    #[serde(rename = "objectType")]
+    pub object_type: ObjectType,
+    /// The qualified name of the object, e.g. `schema.table.column` for a column
+    pub name: String,
+    /// The value of the changed attribute within the published package, such as a column type, if known
+    pub deployed: Option<String>,
+    /// The value of the changed attribute within the database, if known
+    pub live: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DriftKind {
+    /// The object exists within the database but not the published package
+    #[serde(rename = "added")]
+    Added,
+    /// The object exists within the published package but not the database
+    #[serde(rename = "removed")]
+    Removed,
+    #[serde(rename = "modified")]
+    Modified,
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DriftKind::Added => write!(f, "Added"),
+            DriftKind::Removed => write!(f, "Removed"),
+            DriftKind::Modified => write!(f, "Modified"),
+        }
+    }
+}
+
+impl fmt::Display for DriftEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind, self.object_type, self.name)?;
+        if self.kind == DriftKind::Modified {
+            let value = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_owned());
+            write!(f, " (published: {}, now: {})", value(&self.deployed), value(&self.live))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drift {
+    /// Compares the database against the package that was last published to it. Publishing that package again would
+    /// revert every change that is found.
+    pub fn detect(log: &Logger, connection: &Connection) -> PsqlpackResult<Drift> {
+        let log = log.new(o!("drift" => "detect"));
+
+        trace!(log, "Loading last deployment");
+        let deployment = match Deployment::history(&log, connection)?.pop() {
+            Some(deployment) => deployment,
+            None => bail!(DeploymentNotFoundError),
+        };
+        let deployed = deployment.package(&log, connection)?;
+
+        trace!(log, "Loading database");
+        let capabilities = Capabilities::from_connection(&log, connection)?;
+        let live = match Package::from_connection(&log, connection, &capabilities)? {
+            Some(live) => live,
+            None => bail!(DeploymentNotFoundError),
+        };
+
+        // Every difference is generated so that nothing is hidden by the usual safeguards
+        let mut publish_profile = PublishProfile::default();
+        {
+            let options = &mut publish_profile.generation_options;
+            options.drop_enum_values = Toggle::Allow;
+            options.drop_tables = Toggle::Allow;
+            options.drop_columns = Toggle::Allow;
+            options.drop_primary_key_constraints = Toggle::Allow;
+            options.drop_foreign_key_constraints = Toggle::Allow;
+            options.drop_functions = Toggle::Allow;
+            options.drop_indexes = Toggle::Allow;
+            options.drop_types = Toggle::Allow;
+            options.drop_schemas = Toggle::Allow;
+            options.narrow_column_types = Toggle::Allow;
+            options.upgrade_extensions = Toggle::Allow;
+            options.block_on_possible_data_loss = false;
+        }
+        // The delta keeps the snapshot it was generated against, so the drift is described from the same extraction
+        let delta = Delta::generate(
+            &log,
+            &deployed,
+            Some(live),
+            connection.database(),
+            &capabilities,
+            &publish_profile,
+        )?;
+
+        Ok(Drift {
+            deployment: deployment.id,
+            package_hash: deployment.package_hash,
+            changes: delta.drift(&log),
+        })
+    }
+
+    /// Writes the drift as JSON to the destination file
+    pub fn write_to(&self, destination: &Path) -> PsqlpackResult<()> {
+        let writer = File::create(destination).chain_err(|| GenerationError("Failed to write drift".to_owned()))?;
+        serde_json::to_writer_pretty(writer, self).chain_err(|| GenerationError("Failed to write drift".to_owned()))
+    }
+}
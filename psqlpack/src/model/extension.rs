@@ -32,13 +32,18 @@ impl Extension {
 
         let mut package = Package {
             meta,
+            conversions: Vec::new(),
             extensions: Vec::new(),
             functions,
             indexes,
+            refactors: Vec::new(),
             schemas,
             scripts: Vec::new(),
             tables,
             types,
+            variables: Vec::new(),
+            executed_scripts: Vec::new(),
+            type_dependents: Vec::new(),
         };
         package.promote_primary_keys_to_table_constraints();
         Ok(package)
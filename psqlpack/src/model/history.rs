@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::path::Path;
+
+use chrono::prelude::*;
+use postgres::Connection as PostgresConnection;
+use serde_json::{self, Value};
+use slog::Logger;
+
+use crate::connection::Connection;
+use crate::errors::PsqlpackErrorKind::*;
+use crate::errors::{PsqlpackResult, PsqlpackResultExt};
+use crate::model::{Package, PublishProfile};
+
+/// A publish recorded within the deployment history of the target database
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deployment {
+    pub id: i32,
+    /// A hash of the package contents, see `Package::hash`
+    #[serde(rename = "packageHash")]
+    pub package_hash: String,
+    /// The version of psqlpack that generated the package
+    #[serde(rename = "psqlpackVersion")]
+    pub psqlpack_version: String,
+    pub meta: Value,
+    pub profile: Value,
+    #[serde(rename = "startedAt")]
+    pub started_at: DateTime<Utc>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: DateTime<Utc>,
+    /// The database user that published the package
+    #[serde(rename = "executedBy")]
+    pub executed_by: String,
+    /// The SQL that was applied, in the order it was applied
+    #[serde(rename = "appliedSql")]
+    pub applied_sql: String,
+}
+
+impl Deployment {
+    /// Loads the deployment history of the target database, oldest first. A database that has never been published to
+    /// has no history.
+    pub fn history(log: &Logger, connection: &Connection) -> PsqlpackResult<Vec<Deployment>> {
+        let log = log.new(o!("deployment" => "history"));
+
+        trace!(log, "Connecting to database");
+        let conn = connection.connect_database()?;
+        let exists = conn
+            .query(Q_DEPLOYMENTS_EXIST, &[])
+            .chain_err(|| DeploymentHistoryQueryError)?
+            .iter()
+            .any(|row| row.get::<_, bool>(0));
+        let mut deployments = Vec::new();
+        if exists {
+            for row in &conn
+                .query(Q_DEPLOYMENTS, &[])
+                .chain_err(|| DeploymentHistoryQueryError)?
+            {
+                let deployment: Value = row.get(0);
+                deployments.push(serde_json::from_value(deployment).chain_err(|| DeploymentHistoryQueryError)?);
+            }
+        }
+        conn.finish().chain_err(|| DatabaseConnectionFinishError)?;
+        Ok(deployments)
+    }
+
+    /// Loads the package that was published by this deployment
+    pub fn package(&self, log: &Logger, connection: &Connection) -> PsqlpackResult<Package> {
+        let log = log.new(o!("deployment" => "package"));
+
+        trace!(log, "Connecting to database");
+        let conn = connection.connect_database()?;
+        let rows = conn
+            .query(Q_DEPLOYMENT_PACKAGE, &[&self.id])
+            .chain_err(|| DeploymentHistoryQueryError)?;
+        let package = match rows.iter().next() {
+            Some(row) => serde_json::from_value(row.get::<_, Value>(0)).chain_err(|| DeploymentHistoryQueryError)?,
+            None => bail!(DeploymentHistoryQueryError),
+        };
+        conn.finish().chain_err(|| DatabaseConnectionFinishError)?;
+        Ok(package)
+    }
+
+    /// Writes the deployment history as JSON to the destination file
+    pub fn write_history(history: &[Deployment], destination: &Path) -> PsqlpackResult<()> {
+        let writer = File::create(destination).chain_err(|| GenerationError("Failed to write history".to_owned()))?;
+        serde_json::to_writer_pretty(writer, history)
+            .chain_err(|| GenerationError("Failed to write history".to_owned()))
+    }
+}
+
+// Records a successful publish within its own transaction once every change has been committed
+pub(crate) fn record_deployment(
+    conn: &PostgresConnection,
+    package: &Package,
+    publish_profile: &PublishProfile,
+    started_at: DateTime<Utc>,
+    applied_sql: &str,
+) -> PsqlpackResult<()> {
+    let meta = serde_json::to_string(&package.meta).chain_err(|| DeploymentHistoryRecordError)?;
+    let contents = serde_json::to_string(package).chain_err(|| DeploymentHistoryRecordError)?;
+    let profile = serde_json::to_string(publish_profile).chain_err(|| DeploymentHistoryRecordError)?;
+
+    let transaction = conn.transaction().chain_err(|| DeploymentHistoryRecordError)?;
+    transaction
+        .batch_execute(Q_CREATE_DEPLOYMENTS)
+        .chain_err(|| DeploymentHistoryRecordError)?;
+    transaction
+        .execute(
+            Q_RECORD_DEPLOYMENT,
+            &[
+                &package.hash(),
+                &package.meta.version().to_string(),
+                &meta,
+                &profile,
+                &started_at.to_rfc3339(),
+                &applied_sql,
+                &contents,
+            ],
+        )
+        .chain_err(|| DeploymentHistoryRecordError)?;
+    transaction.commit().chain_err(|| DeploymentHistoryRecordError)
+}
+
+static Q_CREATE_DEPLOYMENTS: &'static str = "CREATE SCHEMA IF NOT EXISTS psqlpack;
+    CREATE TABLE IF NOT EXISTS psqlpack.deployments (
+        id serial PRIMARY KEY,
+        package_hash text NOT NULL,
+        psqlpack_version text NOT NULL,
+        meta jsonb NOT NULL,
+        profile jsonb NOT NULL,
+        started_at timestamp with time zone NOT NULL,
+        finished_at timestamp with time zone NOT NULL DEFAULT now(),
+        executed_by text NOT NULL DEFAULT current_user,
+        applied_sql text NOT NULL,
+        package jsonb NOT NULL)";
+static Q_RECORD_DEPLOYMENT: &'static str =
+    "INSERT INTO psqlpack.deployments (package_hash, psqlpack_version, meta, profile, started_at, applied_sql, package)
+     VALUES ($1, $2, $3::text::jsonb, $4::text::jsonb, $5::text::timestamptz, $6, $7::text::jsonb)";
+static Q_DEPLOYMENTS_EXIST: &'static str = "SELECT to_regclass('psqlpack.deployments') IS NOT NULL";
+static Q_DEPLOYMENTS: &'static str = "SELECT json_build_object(
+        'id', id, 'packageHash', package_hash, 'psqlpackVersion', psqlpack_version, 'meta', meta,
+        'profile', profile,
+        'startedAt', started_at, 'finishedAt', finished_at, 'executedBy', executed_by, 'appliedSql', applied_sql)
+    FROM psqlpack.deployments ORDER BY id";
+static Q_DEPLOYMENT_PACKAGE: &'static str = "SELECT package FROM psqlpack.deployments WHERE id = $1";
@@ -9,15 +9,23 @@ macro_rules! dbtry {
 
 mod capabilities;
 mod delta;
+mod drift;
 mod extension;
+mod history;
 mod package;
 mod profiles;
 mod project;
+mod refactor;
+mod report;
 pub mod template;
 
 pub use self::capabilities::{Capabilities, DefinableCatalog};
 pub use self::delta::Delta;
+pub use self::drift::{Drift, DriftEntry, DriftKind};
 pub use self::extension::Extension;
+pub use self::history::Deployment;
 pub use self::package::{MetaInfo, Node, Package, SourceInfo, ValidationKind};
-pub use self::profiles::{GenerationOptions, PublishProfile, Toggle};
-pub use self::project::{Dependency, Project};
+pub use self::profiles::{GenerationOptions, PublishProfile, RenameConfidence, Toggle};
+pub use self::project::{ColumnConversion, Dependency, DeployScript, Project, Variable};
+pub use self::refactor::{RefactorDefinition, RefactorOperation};
+pub use self::report::{ObjectType, Operation, Report, ReportEntry, ReportFormat, Risk, REPORT_VERSION};
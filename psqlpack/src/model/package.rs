@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::{self, File};
 use std::io::{BufReader, Read, Write};
@@ -6,7 +7,9 @@ use std::str::FromStr;
 
 use chrono::prelude::*;
 use petgraph;
-use serde_json;
+use regex::Regex;
+use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
 use slog::Logger;
 use zip::write::FileOptions;
 use zip::{ZipArchive, ZipWriter};
@@ -14,7 +17,10 @@ use zip::{ZipArchive, ZipWriter};
 use crate::connection::Connection;
 use crate::errors::PsqlpackErrorKind::*;
 use crate::errors::{PsqlpackResult, PsqlpackResultExt};
-use crate::model::{Capabilities, DefinableCatalog, Dependency, Project};
+use crate::model::{
+    Capabilities, ColumnConversion, DefinableCatalog, Dependency, Project, RefactorDefinition, RefactorOperation,
+    Variable,
+};
 use crate::semver::Semver;
 use crate::sql::ast::*;
 
@@ -45,16 +51,27 @@ macro_rules! zip_collection {
     }};
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Package {
     pub meta: MetaInfo,
+    pub conversions: Vec<ColumnConversion>,
     pub extensions: Vec<Dependency>,
     pub functions: Vec<FunctionDefinition>,
     pub indexes: Vec<IndexDefinition>,
+    pub refactors: Vec<RefactorDefinition>,
     pub schemas: Vec<SchemaDefinition>,
     pub scripts: Vec<ScriptDefinition>,
     pub tables: Vec<TableDefinition>,
     pub types: Vec<TypeDefinition>,
+    /// Variables that may be used as `$(Name)` placeholders, see `Package::resolve_variables`
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+    /// Scripts already run against the database this package was loaded from, see `ScriptRunPolicy`
+    #[serde(skip)]
+    pub executed_scripts: Vec<ExecutedScript>,
+    /// Objects using a type within the database this package was loaded from that aren't modelled
+    #[serde(skip)]
+    pub type_dependents: Vec<TypeDependent>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,6 +91,51 @@ impl MetaInfo {
             publishable: source != SourceInfo::Extension,
         }
     }
+
+    /// The version of psqlpack that generated the package
+    pub fn version(&self) -> &Semver {
+        &self.version
+    }
+}
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new("\\$\\(([a-zA-Z_][a-zA-Z0-9_]*)\\)").unwrap();
+}
+
+// Replaces placeholders within every string of the serialized package, leaving any that don't resolve as they are
+fn resolve_placeholders(
+    json: &mut Value,
+    resolve: &dyn Fn(&str) -> PsqlpackResult<Option<String>>,
+) -> PsqlpackResult<()> {
+    match *json {
+        Value::String(ref mut text) if PLACEHOLDER.is_match(text) => {
+            let mut resolved = String::new();
+            let mut last = 0;
+            for captures in PLACEHOLDER.captures_iter(text) {
+                let placeholder = captures.get(0).unwrap();
+                resolved.push_str(&text[last..placeholder.start()]);
+                match resolve(&captures[1])? {
+                    Some(value) => resolved.push_str(&value),
+                    None => resolved.push_str(placeholder.as_str()),
+                }
+                last = placeholder.end();
+            }
+            resolved.push_str(&text[last..]);
+            *text = resolved;
+        }
+        Value::Array(ref mut values) => {
+            for value in values {
+                resolve_placeholders(value, resolve)?;
+            }
+        }
+        Value::Object(ref mut map) => {
+            for value in map.values_mut() {
+                resolve_placeholders(value, resolve)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
 }
 
 fn crate_version() -> Semver {
@@ -137,13 +199,16 @@ impl Package {
             .and_then(|file| ZipArchive::new(file).chain_err(|| PackageUnarchiveError(source_path.to_path_buf())))?;
 
         let mut meta: Option<MetaInfo> = None;
+        let mut conversions = Vec::new();
         let mut extensions = Vec::new();
         let mut functions = Vec::new();
         let mut indexes = Vec::new();
+        let mut refactors = Vec::new();
         let mut schemas = Vec::new();
         let mut scripts = Vec::new();
         let mut tables = Vec::new();
         let mut types = Vec::new();
+        let mut variables = Vec::new();
 
         for i in 0..archive.len() {
             let file = archive.by_index(i).unwrap();
@@ -157,12 +222,16 @@ impl Package {
                 }
                 let m = serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?;
                 meta = Some(m);
+            } else if name == "conversions.json" {
+                conversions = serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?;
             } else if name.starts_with("extensions/") {
                 extensions.push(serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?);
             } else if name.starts_with("functions/") {
                 functions.push(serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?);
             } else if name.starts_with("indexes") {
                 indexes.push(serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?);
+            } else if name == "refactorlog.json" {
+                refactors = serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?;
             } else if name.starts_with("schemas/") {
                 schemas.push(serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?);
             } else if name.starts_with("scripts/") {
@@ -171,6 +240,8 @@ impl Package {
                 tables.push(serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?);
             } else if name.starts_with("types/") {
                 types.push(serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?);
+            } else if name == "variables.json" {
+                variables = serde_json::from_reader(file).chain_err(|| PackageInternalReadError(name))?;
             }
         }
 
@@ -181,13 +252,18 @@ impl Package {
                 // For now, it assumes a standard project
                 None => MetaInfo::new(SourceInfo::Project),
             },
+            conversions,
             extensions,
             functions,
             indexes,
+            refactors,
             schemas,
             scripts,
             tables,
             types,
+            variables,
+            executed_scripts: Vec::new(),
+            type_dependents: Vec::new(),
         };
         package.promote_primary_keys_to_table_constraints();
         Ok(package)
@@ -225,19 +301,27 @@ impl Package {
         let functions = capabilities.functions(&db_conn)?;
         let tables = capabilities.tables(&db_conn)?;
         let indexes = capabilities.indexes(&db_conn)?;
+        let refactors = capabilities.refactors(&db_conn)?;
+        let executed_scripts = capabilities.executed_scripts(&db_conn)?;
+        let type_dependents = capabilities.type_dependents(&db_conn)?;
 
         // Close the connection
         dbtry!(db_conn.finish());
 
         let mut package = Package {
             meta: MetaInfo::new(SourceInfo::Database),
+            conversions: Vec::new(), // Conversions only apply to a source
             extensions,
             functions,
             indexes,
+            refactors,
             schemas,
-            scripts: Vec::new(), // Scripts can't be known from a connection
+            scripts: Vec::new(), // Scripts can't be known from a connection, only whether they've run
             tables,
             types,
+            variables: Vec::new(),
+            executed_scripts,
+            type_dependents,
         };
         package.promote_primary_keys_to_table_constraints();
 
@@ -263,13 +347,37 @@ impl Package {
                     Err(e) => bail!(GenerationError(format!("Failed to write package: {}", e))),
                 };
                 ztry!(zip.write_all(json.as_bytes()));
+                if !self.conversions.is_empty() {
+                    ztry!(zip.start_file("conversions.json", FileOptions::default()));
+                    let json = match serde_json::to_string_pretty(&self.conversions) {
+                        Ok(j) => j,
+                        Err(e) => bail!(GenerationError(format!("Failed to write package: {}", e))),
+                    };
+                    ztry!(zip.write_all(json.as_bytes()));
+                }
                 zip_collection!(zip, self, extensions);
                 zip_collection!(zip, self, functions);
                 zip_collection!(zip, self, indexes);
+                if !self.refactors.is_empty() {
+                    ztry!(zip.start_file("refactorlog.json", FileOptions::default()));
+                    let json = match serde_json::to_string_pretty(&self.refactors) {
+                        Ok(j) => j,
+                        Err(e) => bail!(GenerationError(format!("Failed to write package: {}", e))),
+                    };
+                    ztry!(zip.write_all(json.as_bytes()));
+                }
                 zip_collection!(zip, self, schemas);
                 zip_collection!(zip, self, scripts);
                 zip_collection!(zip, self, tables);
                 zip_collection!(zip, self, types);
+                if !self.variables.is_empty() {
+                    ztry!(zip.start_file("variables.json", FileOptions::default()));
+                    let json = match serde_json::to_string_pretty(&self.variables) {
+                        Ok(j) => j,
+                        Err(e) => bail!(GenerationError(format!("Failed to write package: {}", e))),
+                    };
+                    ztry!(zip.write_all(json.as_bytes()));
+                }
 
                 ztry!(zip.finish());
 
@@ -277,20 +385,93 @@ impl Package {
             })
     }
 
+    /// A SHA-256 hash of the package contents, excluding the meta information so that rebuilding an unchanged
+    /// project results in the same hash.
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        let mut add = |json: serde_json::Result<Vec<u8>>| {
+            // Serializing the package model to JSON can't fail
+            hasher.input(json.unwrap());
+            hasher.input(b"\n");
+        };
+        add(serde_json::to_vec(&self.conversions));
+        add(serde_json::to_vec(&self.extensions));
+        add(serde_json::to_vec(&self.functions));
+        add(serde_json::to_vec(&self.indexes));
+        add(serde_json::to_vec(&self.refactors));
+        add(serde_json::to_vec(&self.schemas));
+        add(serde_json::to_vec(&self.scripts));
+        add(serde_json::to_vec(&self.tables));
+        add(serde_json::to_vec(&self.types));
+        add(serde_json::to_vec(&self.variables));
+        hasher.result().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Substitutes `$(Name)` placeholders for declared variables within every object and script with the value of the
+    /// variable, returning a package that is ready to be published. Values override the defaults declared within the
+    /// project, and any variable that is used must end up with a value. Placeholders that don't name a declared
+    /// variable are left as they are.
+    pub fn resolve_variables(self, values: &BTreeMap<String, String>) -> PsqlpackResult<Package> {
+        // Values must be for declared variables so that misspelt names aren't silently ignored
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.variables.iter().any(|v| v.name.eq(*name)))
+        {
+            bail!(UnknownVariableError(name.to_owned()));
+        }
+        if self.variables.is_empty() {
+            return Ok(self);
+        }
+
+        let mut json =
+            serde_json::to_value(&self).chain_err(|| GenerationError("Failed to resolve variables".into()))?;
+        resolve_placeholders(
+            &mut json,
+            &|name| match self.variables.iter().find(|v| v.name == name) {
+                Some(variable) => match values.get(name).or(variable.default.as_ref()) {
+                    Some(value) => Ok(Some(value.to_owned())),
+                    None => Err(MissingVariableError(name.to_owned()).into()),
+                },
+                None => Ok(None),
+            },
+        )?;
+        let package: Package =
+            serde_json::from_value(json).chain_err(|| GenerationError("Failed to resolve variables".into()))?;
+        Ok(Package {
+            variables: Vec::new(),
+            executed_scripts: self.executed_scripts,
+            type_dependents: self.type_dependents,
+            ..package
+        })
+    }
+
     pub fn new() -> Self {
         Package {
             // By default, our source is a project file
             meta: MetaInfo::new(SourceInfo::Project),
+            conversions: Vec::new(),
             extensions: Vec::new(),
             functions: Vec::new(),
             indexes: Vec::new(),
+            refactors: Vec::new(),
             schemas: Vec::new(),
             scripts: Vec::new(),
             tables: Vec::new(),
             types: Vec::new(),
+            variables: Vec::new(),
+            executed_scripts: Vec::new(),
+            type_dependents: Vec::new(),
         }
     }
 
+    pub fn push_variable(&mut self, variable: Variable) {
+        self.variables.push(variable);
+    }
+
+    pub fn push_conversion(&mut self, conversion: ColumnConversion) {
+        self.conversions.push(conversion);
+    }
+
     pub fn push_extension(&mut self, extension: Dependency) {
         self.extensions.push(extension);
     }
@@ -303,6 +484,10 @@ impl Package {
         self.indexes.push(index);
     }
 
+    pub fn push_refactor(&mut self, refactor: RefactorDefinition) {
+        self.refactors.push(refactor);
+    }
+
     pub fn push_script(&mut self, script: ScriptDefinition) {
         self.scripts.push(script);
     }
@@ -331,6 +516,7 @@ impl Package {
         if !has_public {
             self.schemas.push(SchemaDefinition {
                 name: project.default_schema.to_owned(),
+                owner: None,
             });
         }
         for typ in &mut self.types {
@@ -425,10 +611,248 @@ impl Package {
             }
         }
 
+        // Column conversions may also omit the schema
+        for conversion in &mut self.conversions {
+            if conversion.table.schema.is_none() {
+                conversion.table.schema = Some(project.default_schema.clone());
+            }
+        }
+
+        // Refactorings may also omit the schema
+        for refactor in &mut self.refactors {
+            let name = refactor.operation.object_name_mut();
+            if name.schema.is_none() {
+                name.schema = Some(project.default_schema.clone());
+            }
+        }
+
         // We also do the promotion here
         self.promote_primary_keys_to_table_constraints();
     }
 
+    pub fn set_owners(&mut self, owners: Vec<OwnerDefinition>, project: &Project) -> PsqlpackResult<()> {
+        // Names are compared with the default schema applied, since functions don't have it set explicitly
+        let default_schema = &project.default_schema[..];
+        let same_name = |a: &ObjectName, b: &ObjectName| {
+            a.name.eq(&b.name)
+                && a.schema.as_ref().map_or(default_schema, |s| &s[..])
+                    == b.schema.as_ref().map_or(default_schema, |s| &s[..])
+        };
+
+        let mut errors = Vec::new();
+        for definition in owners {
+            let owner = match definition.kind {
+                OwnedObjectKind::Function => {
+                    // Overloads share a name, so the argument list is needed to tell them apart
+                    let mut functions = self.functions.iter_mut().filter(|f| {
+                        same_name(&f.name, &definition.name)
+                            && match definition.arguments {
+                                Some(ref arguments) => input_types(&f.arguments) == input_types(arguments),
+                                None => true,
+                            }
+                    });
+                    let function = functions.next();
+                    if function.is_some() && functions.next().is_some() {
+                        errors.push(ValidationKind::OwnedFunctionAmbiguous {
+                            name: definition.name.to_string(),
+                        });
+                        continue;
+                    }
+                    function.map(|f| &mut f.owner)
+                }
+                OwnedObjectKind::Schema => self
+                    .schemas
+                    .iter_mut()
+                    .find(|s| s.name.eq(&definition.name.name))
+                    .map(|s| &mut s.owner),
+                OwnedObjectKind::Table => self
+                    .tables
+                    .iter_mut()
+                    .find(|t| same_name(&t.name, &definition.name))
+                    .map(|t| &mut t.owner),
+                OwnedObjectKind::Type => self
+                    .types
+                    .iter_mut()
+                    .find(|t| same_name(&t.name, &definition.name))
+                    .map(|t| &mut t.owner),
+            };
+            match owner {
+                Some(owner) => *owner = Some(definition.owner),
+                None => errors.push(ValidationKind::OwnedObjectMissing {
+                    kind: definition.kind,
+                    name: match definition.arguments {
+                        Some(ref arguments) => format!(
+                            "{}({})",
+                            definition.name,
+                            input_types(arguments)
+                                .iter()
+                                .map(|t| t.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                        None => definition.name.to_string(),
+                    },
+                }),
+            }
+        }
+        if !errors.is_empty() {
+            bail!(ValidationError(errors))
+        }
+
+        // Anything left without an owner picks up the project default (if any)
+        if let Some(ref default_owner) = project.default_owner {
+            let unowned = self
+                .schemas
+                .iter_mut()
+                .map(|s| &mut s.owner)
+                .chain(self.tables.iter_mut().map(|t| &mut t.owner))
+                .chain(self.types.iter_mut().map(|t| &mut t.owner))
+                .chain(self.functions.iter_mut().map(|f| &mut f.owner))
+                .filter(|owner| owner.is_none());
+            for owner in unowned {
+                *owner = Some(default_owner.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a refactoring to this package, updating any references to the affected table or column.
+    /// Returns `false` if the object being refactored doesn't exist, or if the new name is already taken.
+    pub fn apply_refactor(&mut self, operation: &RefactorOperation) -> bool {
+        let (table_name, new_table_name) = match *operation {
+            RefactorOperation::RenameEnumValue {
+                ref type_name,
+                ref value,
+                ref new_name,
+            } => {
+                let values = match self.types.iter_mut().find(|t| t.name.eq(type_name)) {
+                    Some(TypeDefinition {
+                        kind: TypeDefinitionKind::Enum(ref mut values),
+                        ..
+                    }) => values,
+                    _ => return false,
+                };
+                if values.contains(new_name) {
+                    return false;
+                }
+                return match values.iter_mut().find(|v| v == &value) {
+                    Some(target_value) => {
+                        *target_value = new_name.to_owned();
+                        true
+                    }
+                    None => false,
+                };
+            }
+            RefactorOperation::RenameTable {
+                ref table,
+                ref new_name,
+            } => (
+                table,
+                ObjectName {
+                    schema: table.schema.clone(),
+                    name: new_name.to_owned(),
+                },
+            ),
+            RefactorOperation::MoveSchema {
+                ref table,
+                ref new_schema,
+            } => (
+                table,
+                ObjectName {
+                    schema: Some(new_schema.to_owned()),
+                    name: table.name.to_owned(),
+                },
+            ),
+            RefactorOperation::RenameColumn {
+                ref table,
+                ref column,
+                ref new_name,
+            } => {
+                let target = match self.tables.iter_mut().find(|t| t.name.eq(table)) {
+                    Some(target) => target,
+                    None => return false,
+                };
+                if target.columns.iter().any(|c| c.name.eq(new_name)) {
+                    return false;
+                }
+                match target.columns.iter_mut().find(|c| c.name.eq(column)) {
+                    Some(target_column) => target_column.name = new_name.to_owned(),
+                    None => return false,
+                }
+
+                let rename = |columns: &mut Vec<String>| {
+                    for c in columns.iter_mut().filter(|c| c == &column) {
+                        *c = new_name.to_owned();
+                    }
+                };
+                for t in &mut self.tables {
+                    let is_table = t.name.eq(table);
+                    for constraint in &mut t.constraints {
+                        match *constraint {
+                            TableConstraint::Primary { ref mut columns, .. } => {
+                                if is_table {
+                                    rename(columns);
+                                }
+                            }
+                            TableConstraint::Foreign {
+                                ref mut columns,
+                                ref ref_table,
+                                ref mut ref_columns,
+                                ..
+                            } => {
+                                if is_table {
+                                    rename(columns);
+                                }
+                                if ref_table.eq(table) {
+                                    rename(ref_columns);
+                                }
+                            }
+                        }
+                    }
+                }
+                for index in self.indexes.iter_mut().filter(|i| i.table.eq(table)) {
+                    for c in index.columns.iter_mut().filter(|c| c.name.eq(column)) {
+                        c.name = new_name.to_owned();
+                    }
+                }
+                return true;
+            }
+        };
+
+        // Table renames and schema moves both change the table name, along with anything that references it
+        if self.tables.iter().any(|t| t.name.eq(&new_table_name)) {
+            return false;
+        }
+        match self.tables.iter_mut().find(|t| t.name.eq(table_name)) {
+            Some(target) => {
+                // Primary keys named by convention follow the table name (see `RenameTable` SQL generation)
+                let implicit_pkey = format!("{}_pkey", table_name.name);
+                for constraint in &mut target.constraints {
+                    if let TableConstraint::Primary { ref mut name, .. } = *constraint {
+                        if *name == implicit_pkey {
+                            *name = format!("{}_pkey", new_table_name.name);
+                        }
+                    }
+                }
+                target.name = new_table_name.clone();
+            }
+            None => return false,
+        }
+        for t in &mut self.tables {
+            for constraint in &mut t.constraints {
+                if let TableConstraint::Foreign { ref mut ref_table, .. } = *constraint {
+                    if *ref_table == *table_name {
+                        *ref_table = new_table_name.clone();
+                    }
+                }
+            }
+        }
+        for index in self.indexes.iter_mut().filter(|i| i.table.eq(table_name)) {
+            index.table = new_table_name.clone();
+        }
+        true
+    }
+
     pub fn promote_primary_keys_to_table_constraints(&mut self) {
         // Set default schema's as well as marking primary key columns as not null
         for table in &mut self.tables {
@@ -669,6 +1093,18 @@ impl Package {
                 }),
         );
 
+        // 6. Validate refactorings are uniquely identified, otherwise we can't tell which have been applied
+        let mut refactor_ids = Vec::new();
+        for refactor in &self.refactors {
+            if refactor_ids.contains(&&refactor.id) {
+                errors.push(ValidationKind::DuplicateRefactorId {
+                    id: refactor.id.to_owned(),
+                });
+            } else {
+                refactor_ids.push(&refactor.id);
+            }
+        }
+
         // If there are no errors then we're "ok"
         if errors.is_empty() {
             Ok(())
@@ -678,6 +1114,15 @@ impl Package {
     }
 }
 
+// Functions are identified by their input argument types. OUT arguments are ignored by PG.
+fn input_types(arguments: &[FunctionArgument]) -> Vec<&SqlType> {
+    arguments
+        .iter()
+        .filter(|arg| arg.mode != Some(FunctionArgumentMode::Out))
+        .map(|arg| &arg.sql_type)
+        .collect()
+}
+
 impl Default for Package {
     fn default() -> Self {
         Self::new()
@@ -720,6 +1165,16 @@ pub enum ValidationKind {
         language: FunctionLanguage,
         name: ObjectName,
     },
+    OwnedObjectMissing {
+        kind: OwnedObjectKind,
+        name: String,
+    },
+    OwnedFunctionAmbiguous {
+        name: String,
+    },
+    DuplicateRefactorId {
+        id: String,
+    },
 }
 
 impl fmt::Display for ValidationKind {
@@ -778,6 +1233,17 @@ impl fmt::Display for ValidationKind {
                 "Unsupported function language `{}` used on function `{}`",
                 language, name,
             ),
+            ValidationKind::OwnedObjectMissing { ref kind, ref name } => {
+                write!(f, "Owner declared for unknown {} `{}`", kind, name)
+            }
+            ValidationKind::OwnedFunctionAmbiguous { ref name } => write!(
+                f,
+                "Owner declared for overloaded function `{}` without an argument list",
+                name
+            ),
+            ValidationKind::DuplicateRefactorId { ref id } => {
+                write!(f, "Refactoring `{}` is declared more than once in the refactor log", id)
+            }
         }
     }
 }
@@ -961,6 +1427,7 @@ mod tests {
 
     use slog::{Discard, Drain, Logger};
     use spectral::prelude::*;
+    use std::collections::BTreeMap;
 
     fn package_sql(sql: &str) -> Package {
         let tokens = match lexer::tokenize_stmt(sql) {
@@ -968,6 +1435,7 @@ mod tests {
             Err(e) => panic!("Syntax error: {}", e.line),
         };
         let mut package = Package::new();
+        let mut owners = Vec::new();
         match StatementListParser::new().parse(tokens) {
             Ok(statement_list) => {
                 for statement in statement_list {
@@ -978,11 +1446,15 @@ mod tests {
                         ast::Statement::Schema(schema_definition) => package.push_schema(schema_definition),
                         ast::Statement::Table(table_definition) => package.push_table(table_definition),
                         ast::Statement::Type(type_definition) => package.push_type(type_definition),
+                        ast::Statement::Owner(owner_definition) => owners.push(owner_definition),
                     }
                 }
             }
             Err(err) => panic!("Failed to parse sql: {:?}", err),
         }
+        if let Err(err) = package.set_owners(owners, &Project::default()) {
+            panic!("Failed to set owners: {:?}", err);
+        }
         package
     }
 
@@ -1047,6 +1519,88 @@ mod tests {
         };
     }
 
+    #[test]
+    fn it_hashes_the_package_contents() {
+        let sql = "CREATE TABLE my.items(id int NOT NULL);";
+        let package = package_sql(sql);
+        let mut other = package_sql(sql);
+        other.meta = MetaInfo::new(SourceInfo::Database);
+
+        // Meta information doesn't contribute to the hash
+        let hash = package.hash();
+        assert_that!(hash.len()).is_equal_to(64);
+        assert_that!(other.hash()).is_equal_to(&hash);
+
+        // Though any change to the contents does
+        other.push_schema(ast::SchemaDefinition {
+            name: "my".into(),
+            owner: None,
+        });
+        assert_that!(other.hash()).is_not_equal_to(&hash);
+    }
+
+    #[test]
+    fn it_resolves_variables_from_values_and_defaults() {
+        let package = || {
+            let mut package = package_sql(
+                "CREATE TABLE $(TenantSchema).settings(name varchar(50) NOT NULL DEFAULT '$(Region)-$(Other)');",
+            );
+            package.push_variable(Variable {
+                name: "TenantSchema".into(),
+                default: None,
+            });
+            package.push_variable(Variable {
+                name: "Region".into(),
+                default: Some("eu".into()),
+            });
+            package
+        };
+
+        // A value must be given for a variable without a default
+        match package().resolve_variables(&BTreeMap::new()) {
+            Err(PsqlpackError(MissingVariableError(name), _)) => {
+                assert_that!(name).is_equal_to("TenantSchema".to_owned())
+            }
+            unexpected => panic!("Expected missing variable error however saw {:?}", unexpected),
+        }
+
+        // Values may only be given for declared variables
+        let mut values = BTreeMap::new();
+        values.insert("Tenant".to_owned(), "acme".to_owned());
+        match package().resolve_variables(&values) {
+            Err(PsqlpackError(UnknownVariableError(name), _)) => assert_that!(name).is_equal_to("Tenant".to_owned()),
+            unexpected => panic!("Expected unknown variable error however saw {:?}", unexpected),
+        }
+
+        let mut values = BTreeMap::new();
+        values.insert("TenantSchema".to_owned(), "acme".to_owned());
+        let resolved = match package().resolve_variables(&values) {
+            Ok(resolved) => resolved,
+            Err(e) => panic!("Failed to resolve variables: {}", e),
+        };
+        assert_that!(resolved.variables).is_empty();
+        assert_that!(resolved.tables).has_length(1);
+        let table = &resolved.tables[0];
+        assert_that!(table.name.to_string()).is_equal_to("acme.settings".to_owned());
+
+        // Placeholders that aren't declared are left as they are
+        assert_that!(table.columns[0].constraints).contains(ast::ColumnConstraint::Default(ast::Expression::Value(
+            ast::AnyValue::String("eu-$(Other)".into(), None),
+        )));
+    }
+
+    #[test]
+    fn it_leaves_placeholders_alone_without_variables() {
+        let package = package_sql("CREATE TABLE my.settings(name varchar(50) NOT NULL DEFAULT '$(Region)');");
+        let hash = package.hash();
+
+        let resolved = match package.resolve_variables(&BTreeMap::new()) {
+            Ok(resolved) => resolved,
+            Err(e) => panic!("Failed to resolve variables: {}", e),
+        };
+        assert_that!(resolved.hash()).is_equal_to(hash);
+    }
+
     #[test]
     fn it_sets_table_defaults() {
         let mut package = package_sql("CREATE TABLE hello_world(id int);");
@@ -1209,7 +1763,10 @@ mod tests {
         }
 
         // Add the schema and try again
-        package.schemas.push(ast::SchemaDefinition { name: "my".to_owned() });
+        package.schemas.push(ast::SchemaDefinition {
+            name: "my".to_owned(),
+            owner: None,
+        });
         assert_that!(package.validate()).is_ok();
     }
 
@@ -1248,6 +1805,7 @@ mod tests {
                 name: "mytype".to_string(),
             },
             kind: ast::TypeDefinitionKind::Enum(Vec::new()),
+            owner: None,
         });
         assert_that!(package.validate()).is_ok();
     }
@@ -1293,6 +1851,7 @@ mod tests {
                 constraints: Vec::new(),
             }],
             constraints: Vec::new(),
+            owner: None,
         });
         assert_that!(package.validate()).is_ok();
     }
@@ -1428,6 +1987,7 @@ mod tests {
                 },
             ],
             constraints: Vec::new(),
+            owner: None,
         });
         assert_that!(package.validate()).is_ok();
     }
@@ -1473,4 +2033,115 @@ mod tests {
         }
         assert_that!(package.validate()).is_ok();
     }
+
+    #[test]
+    fn it_applies_declared_and_default_owners() {
+        let mut package = package_sql(
+            "CREATE SCHEMA my;
+             CREATE TABLE my.contacts(id int);
+             CREATE TABLE my.companies(id int);
+             ALTER TABLE my.contacts OWNER TO contacts_owner;",
+        );
+        let mut project = Project::default();
+        project.default_owner = Some("app_owner".to_owned());
+        assert_that!(package.set_owners(Vec::new(), &project)).is_ok();
+
+        let owner_of = |name: &str| {
+            package
+                .tables
+                .iter()
+                .find(|t| t.name.name.eq(name))
+                .and_then(|t| t.owner.clone())
+        };
+        assert_that!(owner_of("contacts")).is_equal_to(Some("contacts_owner".to_owned()));
+        assert_that!(owner_of("companies")).is_equal_to(Some("app_owner".to_owned()));
+        assert_that!(package.schemas[0].owner).is_equal_to(Some("app_owner".to_owned()));
+    }
+
+    #[test]
+    fn it_rejects_owners_for_unknown_objects() {
+        let mut package = package_sql("CREATE SCHEMA my;");
+        let result = package.set_owners(
+            vec![ast::OwnerDefinition {
+                kind: ast::OwnedObjectKind::Table,
+                name: ast::ObjectName {
+                    schema: Some("my".to_owned()),
+                    name: "contacts".to_owned(),
+                },
+                arguments: None,
+                owner: "app_owner".to_owned(),
+            }],
+            &Project::default(),
+        );
+
+        assert_that!(result).is_err();
+        let validation_errors = match result.err().unwrap() {
+            PsqlpackError(ValidationError(errors), _) => errors,
+            unexpected => panic!("Expected validation error however saw {:?}", unexpected),
+        };
+        assert_that!(validation_errors).has_length(1);
+        match validation_errors[0] {
+            ValidationKind::OwnedObjectMissing { ref kind, ref name } => {
+                assert_that!(*kind).is_equal_to(ast::OwnedObjectKind::Table);
+                assert_that!(*name).is_equal_to("my.contacts".to_owned());
+            }
+            ref unexpected => panic!("Unexpected validation type: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn it_matches_function_owners_on_argument_types() {
+        let package = package_sql(
+            "CREATE SCHEMA my;
+             CREATE FUNCTION my.describe(value int) RETURNS text AS $$ SELECT 'int' $$ LANGUAGE SQL;
+             CREATE FUNCTION my.describe(value text) RETURNS text AS $$ SELECT 'text' $$ LANGUAGE SQL;
+             ALTER FUNCTION my.describe(text) OWNER TO text_owner;
+             ALTER FUNCTION my.describe(integer) OWNER TO int_owner;",
+        );
+
+        let owners = package
+            .functions
+            .iter()
+            .map(|f| (f.arguments[0].sql_type.to_string(), f.owner.clone()))
+            .collect::<Vec<_>>();
+        assert_that!(owners).is_equal_to(vec![
+            ("int".to_owned(), Some("int_owner".to_owned())),
+            ("text".to_owned(), Some("text_owner".to_owned())),
+        ]);
+    }
+
+    #[test]
+    fn it_rejects_function_owners_that_match_more_than_one_overload() {
+        let mut package = package_sql(
+            "CREATE SCHEMA my;
+             CREATE FUNCTION my.describe(value int) RETURNS text AS $$ SELECT 'int' $$ LANGUAGE SQL;
+             CREATE FUNCTION my.describe(value text) RETURNS text AS $$ SELECT 'text' $$ LANGUAGE SQL;",
+        );
+        let result = package.set_owners(
+            vec![ast::OwnerDefinition {
+                kind: ast::OwnedObjectKind::Function,
+                name: ast::ObjectName {
+                    schema: Some("my".to_owned()),
+                    name: "describe".to_owned(),
+                },
+                arguments: None,
+                owner: "app_owner".to_owned(),
+            }],
+            &Project::default(),
+        );
+
+        assert_that!(result).is_err();
+        let validation_errors = match result.err().unwrap() {
+            PsqlpackError(ValidationError(errors), _) => errors,
+            unexpected => panic!("Expected validation error however saw {:?}", unexpected),
+        };
+        assert_that!(validation_errors).has_length(1);
+        match validation_errors[0] {
+            ValidationKind::OwnedFunctionAmbiguous { ref name } => {
+                assert_that!(*name).is_equal_to("my.describe".to_owned());
+            }
+            ref unexpected => panic!("Unexpected validation type: {:?}", unexpected),
+        }
+        assert_that!(package.functions.iter().all(|f| f.owner.is_none())).is_true();
+    }
 }
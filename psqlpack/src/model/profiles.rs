@@ -3,7 +3,9 @@
 //! For instance, a `PublishProfile` might determine how unknown entities in the
 //! target are handled when performing a `publish` operation.
 
+use std::collections::BTreeMap;
 use std::default::Default;
+use std::fmt;
 use std::fs::File;
 use std::path::Path;
 
@@ -18,6 +20,9 @@ pub struct PublishProfile {
     pub version: Semver,
     #[serde(rename = "generationOptions")]
     pub generation_options: GenerationOptions,
+    /// Values for the variables declared within the project, overriding their defaults
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -41,6 +46,24 @@ impl Toggle {
     }
 }
 
+/// How likely a detected rename is to have been intended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum RenameConfidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for RenameConfidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenameConfidence::Low => write!(f, "low"),
+            RenameConfidence::Medium => write!(f, "medium"),
+            RenameConfidence::High => write!(f, "high"),
+        }
+    }
+}
+
 struct Bool;
 impl Bool {
     fn t() -> bool {
@@ -48,6 +71,13 @@ impl Bool {
     }
 }
 
+struct Millis;
+impl Millis {
+    fn one_second() -> u64 {
+        1000
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct GenerationOptions {
     /// If set to true, the database will always be recereated
@@ -82,6 +112,26 @@ pub struct GenerationOptions {
     /// Default: Allow
     #[serde(rename = "dropIndexes", default = "Toggle::allow")]
     pub drop_indexes: Toggle,
+    /// Types may still be in use by objects outside of the project. If set to Allow, psqlpack will drop the type.
+    /// Default: Ignore
+    #[serde(rename = "dropTypes", default = "Toggle::ignore")]
+    pub drop_types: Toggle,
+    /// Schemas may contain objects that aren't managed by psqlpack. If set to Allow, psqlpack will drop the schema
+    /// provided it is empty. The `public` schema is never dropped.
+    /// Default: Ignore
+    #[serde(rename = "dropSchemas", default = "Toggle::ignore")]
+    pub drop_schemas: Toggle,
+    /// Changing a column to a narrower type (e.g. varchar(50) to varchar(10)) may truncate or round existing values.
+    /// If set to Allow, psqlpack will narrow the column.
+    /// Default: Error
+    #[serde(rename = "narrowColumnTypes", default = "Toggle::error")]
+    pub narrow_column_types: Toggle,
+
+    /// Before dropping a table or column, or narrowing a column's type, the target is checked for data that would be
+    /// lost. If set to true the publish is stopped when data would be lost, otherwise a warning is reported.
+    /// Default: true
+    #[serde(rename = "blockOnPossibleDataLoss", default = "Bool::t")]
+    pub block_on_possible_data_loss: bool,
 
     /// Extensions may not be intended to be upgraded automatically. If set to Allow, psqlpack will upgrade the extension as necessary.
     /// Default: Ignore
@@ -92,6 +142,39 @@ pub struct GenerationOptions {
     /// Default: true
     #[serde(rename = "forceConcurrentIndexes", default = "Bool::t")]
     pub force_concurrent_indexes: bool,
+    /// Foreign keys added to existing tables are created as `NOT VALID` and validated separately, so that locks on
+    /// both tables aren't held while existing rows are checked.
+    /// Default: false
+    #[serde(rename = "onlineConstraints", default)]
+    pub online_constraints: bool,
+
+    /// The number of milliseconds a statement waits to acquire a lock before it fails. This stops a publish from
+    /// queueing other writers behind a statement waiting on a long running query.
+    /// Default: None
+    #[serde(rename = "lockTimeout", default, skip_serializing_if = "Option::is_none")]
+    pub lock_timeout: Option<u64>,
+    /// The number of milliseconds a statement may run before it is cancelled.
+    /// Default: None
+    #[serde(rename = "statementTimeout", default, skip_serializing_if = "Option::is_none")]
+    pub statement_timeout: Option<u64>,
+    /// The number of times a phase that failed due to `lockTimeout` is retried.
+    /// Default: 0
+    #[serde(rename = "lockTimeoutRetries", default)]
+    pub lock_timeout_retries: u32,
+    /// The number of milliseconds to wait before the first retry. This doubles with each subsequent retry.
+    /// Default: 1000
+    #[serde(rename = "lockTimeoutRetryDelay", default = "Millis::one_second")]
+    pub lock_timeout_retry_delay: u64,
+
+    /// Dropped and added tables or columns that look alike are reported as possible renames.
+    /// Default: false
+    #[serde(rename = "detectRenames", default)]
+    pub detect_renames: bool,
+    /// Detected renames with at least this confidence (Low, Medium or High) are applied as renames instead of
+    /// dropping and adding the object. Implies `detectRenames`.
+    /// Default: None
+    #[serde(rename = "acceptRenames", default, skip_serializing_if = "Option::is_none")]
+    pub accept_renames: Option<RenameConfidence>,
 }
 
 impl Default for PublishProfile {
@@ -108,11 +191,26 @@ impl Default for PublishProfile {
                 drop_foreign_key_constraints: Toggle::Allow,
                 drop_functions: Toggle::Error,
                 drop_indexes: Toggle::Allow,
+                drop_types: Toggle::Ignore,
+                drop_schemas: Toggle::Ignore,
+                narrow_column_types: Toggle::Error,
+
+                block_on_possible_data_loss: true,
 
                 upgrade_extensions: Toggle::Ignore,
 
                 force_concurrent_indexes: true,
+                online_constraints: false,
+
+                lock_timeout: None,
+                statement_timeout: None,
+                lock_timeout_retries: 0,
+                lock_timeout_retry_delay: 1000,
+
+                detect_renames: false,
+                accept_renames: None,
             },
+            variables: BTreeMap::new(),
         }
     }
 }
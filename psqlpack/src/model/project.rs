@@ -9,6 +9,7 @@ use slog::Logger;
 
 use crate::errors::PsqlpackErrorKind::*;
 use crate::errors::{PsqlpackError, PsqlpackResult, PsqlpackResultExt};
+use crate::model::refactor::load_refactor_log;
 use crate::model::Package;
 use crate::sql::ast::*;
 use crate::sql::lexer;
@@ -41,13 +42,29 @@ pub struct Project {
     #[serde(rename = "defaultSchema")]
     pub default_schema: String,
 
+    /// The default owner for schemas, tables, types and functions that don't declare one explicitly
+    #[serde(rename = "defaultOwner", skip_serializing_if = "Option::is_none")]
+    pub default_owner: Option<String>,
+
     /// An array of scripts to run before anything is deployed
     #[serde(rename = "preDeployScripts")]
-    pub pre_deploy_scripts: Vec<String>,
+    pub pre_deploy_scripts: Vec<DeployScript>,
 
     /// An array of scripts to run after everything has been deployed
     #[serde(rename = "postDeployScripts")]
-    pub post_deploy_scripts: Vec<String>,
+    pub post_deploy_scripts: Vec<DeployScript>,
+
+    /// A file recording table and column renames so that they can be applied without losing data
+    #[serde(rename = "refactorLog", skip_serializing_if = "Option::is_none")]
+    pub refactor_log: Option<String>,
+
+    /// An array of expressions used to convert existing column values when a column's type is changed
+    #[serde(rename = "columnConversions", skip_serializing_if = "Option::is_none")]
+    pub column_conversions: Option<Vec<ColumnConversion>>,
+
+    /// An array of variables that may be used as `$(Name)` placeholders within scripts and objects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Vec<Variable>>,
 
     /// An array of extensions to include within this project
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -62,6 +79,44 @@ pub struct Project {
     pub exclude_globs: Option<Vec<String>>,
 }
 
+/// A variable substituted for `$(Name)` placeholders when publishing, e.g. a role name that differs per environment
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Variable {
+    pub name: String,
+    /// The value used unless the publish profile or command line provides one. Without a default a value must be
+    /// provided whenever the variable is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// A deployment script, given either as a relative path which is run on every publish or as an object declaring how
+/// often it is run, e.g. `{ "path": "scripts/fix_emails.sql", "run": "once" }`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeployScript {
+    Path(String),
+    Script {
+        path: String,
+        #[serde(default)]
+        run: ScriptRunPolicy,
+    },
+}
+
+impl DeployScript {
+    pub fn path(&self) -> &str {
+        match *self {
+            DeployScript::Path(ref path) | DeployScript::Script { ref path, .. } => path,
+        }
+    }
+
+    pub fn run(&self) -> ScriptRunPolicy {
+        match *self {
+            DeployScript::Path(_) => ScriptRunPolicy::Always,
+            DeployScript::Script { run, .. } => run,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Dependency {
     pub name: String,
@@ -69,14 +124,32 @@ pub struct Dependency {
     pub version: Option<Semver>,
 }
 
+/// Expressions used to convert the existing values of a column, either when changing its type to one Postgres can't
+/// convert the values to implicitly, or to populate the column when it is added or made `NOT NULL`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnConversion {
+    pub table: ObjectName,
+    pub column: String,
+    /// The expression given to `ALTER COLUMN ... TYPE ... USING`, e.g. `age::integer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub using: Option<String>,
+    /// The expression used to populate existing rows where the column is null, e.g. `lower(email)`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backfill: Option<String>,
+}
+
 impl Default for Project {
     fn default() -> Self {
         Project {
             project_file_path: None,
             version: "1.0".into(),
             default_schema: "public".into(),
+            default_owner: None,
             pre_deploy_scripts: Vec::new(),
             post_deploy_scripts: Vec::new(),
+            refactor_log: None,
+            column_conversions: None,
+            variables: None,
             extensions: None,
             include_globs: None,
             exclude_globs: None,
@@ -99,10 +172,26 @@ impl Project {
                 if project.default_schema.is_empty() {
                     project.default_schema = "public".into();
                 }
+                project.validate()?;
                 Ok(project)
             })
     }
 
+    fn validate(&self) -> PsqlpackResult<()> {
+        // A conversion without any expressions would otherwise be silently ignored
+        if let Some(ref conversions) = self.column_conversions {
+            for conversion in conversions {
+                if conversion.using.is_none() && conversion.backfill.is_none() {
+                    bail!(ProjectError(format!(
+                        "Column conversion for {} on table {} requires either using or backfill",
+                        conversion.column, conversion.table
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn build_package(&self, log: &Logger) -> PsqlpackResult<Package> {
         let log = log.new(o!("project" => "build_package"));
 
@@ -117,23 +206,33 @@ impl Project {
                 .canonicalize()
                 .chain_err(|| InvalidScriptPath(script.to_owned()))
         };
+        // Scripts are named by their path within the project so that scripts sharing a file name are kept apart
+        let script_name = |path: &Path, script: &str| match path.strip_prefix(&parent) {
+            Ok(relative) => relative
+                .components()
+                .map(|c| c.as_os_str().to_str().unwrap())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => script.to_owned(),
+        };
 
         trace!(log, "Canonicalizing predeploy paths");
         let mut predeploy_paths = Vec::new();
         for script in &self.pre_deploy_scripts {
-            predeploy_paths.push(make_path(script)?);
+            predeploy_paths.push(make_path(script.path())?);
         }
         trace!(log, "Done predeploy paths"; "count" => predeploy_paths.len());
 
         trace!(log, "Canonicalizing postdeploy paths");
         let mut postdeploy_paths = Vec::new();
         for script in &self.post_deploy_scripts {
-            postdeploy_paths.push(make_path(script)?);
+            postdeploy_paths.push(make_path(script.path())?);
         }
         trace!(log, "Done postdeploy paths"; "count" => postdeploy_paths.len());
 
         // Start the package
         let mut package = Package::new();
+        let mut owners = Vec::new();
         let mut errors: Vec<PsqlpackError> = Vec::new();
 
         // Add extensions into package
@@ -146,6 +245,28 @@ impl Project {
             }
         }
 
+        // Load the refactor log, if any
+        if let Some(ref refactor_log) = self.refactor_log {
+            trace!(log, "Loading refactor log");
+            for refactor in load_refactor_log(&parent.join(Path::new(refactor_log)))? {
+                package.push_refactor(refactor);
+            }
+        }
+
+        // Add variables into package
+        if let Some(ref variables) = self.variables {
+            for variable in variables {
+                package.push_variable(variable.clone());
+            }
+        }
+
+        // Add column conversions into package
+        if let Some(ref conversions) = self.column_conversions {
+            for conversion in conversions {
+                package.push_conversion(conversion.clone());
+            }
+        }
+
         // Enumerate the glob paths
         for path in self.walk_files(&parent)? {
             let log = log.new(o!("file" => path.to_str().unwrap().to_owned()));
@@ -162,18 +283,20 @@ impl Project {
             if let Some(pos) = predeploy_paths.iter().position(|x| real_path.eq(x)) {
                 trace!(log, "Found predeploy script");
                 package.push_script(ScriptDefinition {
-                    name: path.file_name().unwrap().to_str().unwrap().to_owned(),
+                    name: script_name(&real_path, self.pre_deploy_scripts[pos].path()),
                     kind: ScriptKind::PreDeployment,
                     order: pos,
                     contents,
+                    run: self.pre_deploy_scripts[pos].run(),
                 });
             } else if let Some(pos) = postdeploy_paths.iter().position(|x| real_path.eq(x)) {
                 trace!(log, "Found postdeploy script");
                 package.push_script(ScriptDefinition {
-                    name: path.file_name().unwrap().to_str().unwrap().to_owned(),
+                    name: script_name(&real_path, self.post_deploy_scripts[pos].path()),
                     kind: ScriptKind::PostDeployment,
                     order: pos,
                     contents,
+                    run: self.post_deploy_scripts[pos].run(),
                 });
             } else {
                 trace!(log, "Tokenizing file");
@@ -212,6 +335,7 @@ impl Project {
                                 Statement::Schema(schema_definition) => package.push_schema(schema_definition),
                                 Statement::Table(table_definition) => package.push_table(table_definition),
                                 Statement::Type(type_definition) => package.push_type(type_definition),
+                                Statement::Owner(owner_definition) => owners.push(owner_definition),
                             }
                         }
                     }
@@ -230,6 +354,8 @@ impl Project {
 
         // Update any missing defaults, then try to validate the project
         package.set_defaults(self);
+        // Ownership statements may reference objects declared in any file so these are applied last
+        package.set_owners(owners, self)?;
         trace!(log, "Validating package");
         package.validate()?;
 
@@ -278,7 +404,10 @@ impl Project {
 #[cfg(test)]
 mod tests {
 
-    use super::Project;
+    use super::{DeployScript, Project};
+    use crate::sql::ast::ScriptRunPolicy;
+    use serde_json;
+    use slog::{Discard, Drain, Logger};
     use spectral::prelude::*;
     use std::path::Path;
 
@@ -309,8 +438,12 @@ mod tests {
             project_file_path: None,
             version: "1.0".into(),
             default_schema: "public".into(),
+            default_owner: None,
             pre_deploy_scripts: Vec::new(),
             post_deploy_scripts: Vec::new(),
+            refactor_log: None,
+            column_conversions: None,
+            variables: None,
             extensions: None,
             include_globs: None,
             exclude_globs: Some(vec!["**/*org*".into()]),
@@ -337,8 +470,12 @@ mod tests {
             project_file_path: None,
             version: "1.0".into(),
             default_schema: "public".into(),
+            default_owner: None,
             pre_deploy_scripts: Vec::new(),
             post_deploy_scripts: Vec::new(),
+            refactor_log: None,
+            column_conversions: None,
+            variables: None,
             extensions: None,
             include_globs: None,
             exclude_globs: Some(vec!["**/geo/**/*.sql".into(), "**/geo.*".into()]),
@@ -363,8 +500,12 @@ mod tests {
             project_file_path: None,
             version: "1.0".into(),
             default_schema: "public".into(),
+            default_owner: None,
             pre_deploy_scripts: Vec::new(),
             post_deploy_scripts: Vec::new(),
+            refactor_log: None,
+            column_conversions: None,
+            variables: None,
             extensions: None,
             include_globs: Some(vec!["**/*org*.sql".into()]),
             exclude_globs: None,
@@ -378,4 +519,72 @@ mod tests {
         assert_that!(result).contains_all_of(&vec![&"../samples/simple/public/tables/public.organisation.sql"]);
     }
 
+    #[test]
+    fn it_can_parse_deploy_scripts_with_a_run_policy() {
+        let project: Project = serde_json::from_str(
+            r#"{
+                "version": "1.0",
+                "defaultSchema": "public",
+                "preDeployScripts": ["scripts/always.sql", { "path": "scripts/default.sql" }],
+                "postDeployScripts": [
+                    { "path": "scripts/once.sql", "run": "once" },
+                    { "path": "scripts/seed.sql", "run": "onChange" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let scripts = |scripts: &[DeployScript]| {
+            scripts
+                .iter()
+                .map(|script| (script.path().to_owned(), script.run()))
+                .collect::<Vec<_>>()
+        };
+        assert_that!(scripts(&project.pre_deploy_scripts)).is_equal_to(vec![
+            ("scripts/always.sql".to_owned(), ScriptRunPolicy::Always),
+            ("scripts/default.sql".to_owned(), ScriptRunPolicy::Always),
+        ]);
+        assert_that!(scripts(&project.post_deploy_scripts)).is_equal_to(vec![
+            ("scripts/once.sql".to_owned(), ScriptRunPolicy::Once),
+            ("scripts/seed.sql".to_owned(), ScriptRunPolicy::OnChange),
+        ]);
+    }
+
+    #[test]
+    fn it_rejects_column_conversions_without_an_expression() {
+        let project: Project = serde_json::from_str(
+            r#"{
+                "version": "1.0",
+                "defaultSchema": "public",
+                "preDeployScripts": [],
+                "postDeployScripts": [],
+                "columnConversions": [
+                    { "table": { "schema": "public", "name": "contacts" }, "column": "age", "using": "age::integer" },
+                    { "table": { "schema": "public", "name": "contacts" }, "column": "email" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let result = project.validate();
+        assert_that!(result).is_err();
+        assert_that!(result.unwrap_err().to_string()).is_equal_to(
+            "Project format error: Column conversion for email on table public.contacts requires either using or \
+             backfill"
+                .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_names_deploy_scripts_by_their_path_within_the_project() {
+        // This test relies on the `complex` samples directory
+        let log = Logger::root(Discard.fuse(), o!());
+        let project = Project::from_project_file(&log, Path::new("../samples/complex/complex.psqlproj")).unwrap();
+        let package = project.build_package(&log).unwrap();
+
+        let names = package.scripts.iter().map(|s| &s.name[..]).collect::<Vec<_>>();
+        assert_that!(names).has_length(9);
+        assert_that!(names).contains("scripts/seed/data.idents.sql");
+        assert_that!(names).contains("scripts/seed/reference_data.states.sql");
+    }
 }
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use serde_json;
+
+use crate::errors::PsqlpackErrorKind::*;
+use crate::errors::{PsqlpackResult, PsqlpackResultExt};
+use crate::sql::ast::ObjectName;
+
+/// A refactoring recorded within the project's refactor log. Each refactoring is applied to a target database
+/// at most once, after which it is recorded within the target so that it is not applied again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RefactorDefinition {
+    /// A unique identifier for this refactoring
+    pub id: String,
+    #[serde(flatten)]
+    pub operation: RefactorOperation,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation")]
+pub enum RefactorOperation {
+    #[serde(rename = "renameTable")]
+    RenameTable {
+        table: ObjectName,
+        #[serde(rename = "newName")]
+        new_name: String,
+    },
+    #[serde(rename = "renameColumn")]
+    RenameColumn {
+        table: ObjectName,
+        column: String,
+        #[serde(rename = "newName")]
+        new_name: String,
+    },
+    #[serde(rename = "moveSchema")]
+    MoveSchema {
+        table: ObjectName,
+        #[serde(rename = "newSchema")]
+        new_schema: String,
+    },
+    /// Renames a value of an enum type, which requires PG10 or later. Prior to this the refactoring is recorded
+    /// without being applied, leaving the value to be replaced.
+    #[serde(rename = "renameEnumValue")]
+    RenameEnumValue {
+        #[serde(rename = "type")]
+        type_name: ObjectName,
+        value: String,
+        #[serde(rename = "newName")]
+        new_name: String,
+    },
+}
+
+impl RefactorOperation {
+    pub(crate) fn object_name_mut(&mut self) -> &mut ObjectName {
+        match *self {
+            RefactorOperation::RenameTable { ref mut table, .. }
+            | RefactorOperation::RenameColumn { ref mut table, .. }
+            | RefactorOperation::MoveSchema { ref mut table, .. } => table,
+            RefactorOperation::RenameEnumValue { ref mut type_name, .. } => type_name,
+        }
+    }
+
+    /// The operation that undoes this one, returning the object to its original name
+    pub(crate) fn inverse(&self) -> RefactorOperation {
+        match *self {
+            RefactorOperation::RenameTable {
+                ref table,
+                ref new_name,
+            } => RefactorOperation::RenameTable {
+                table: ObjectName {
+                    schema: table.schema.clone(),
+                    name: new_name.to_owned(),
+                },
+                new_name: table.name.to_owned(),
+            },
+            RefactorOperation::RenameColumn {
+                ref table,
+                ref column,
+                ref new_name,
+            } => RefactorOperation::RenameColumn {
+                table: table.clone(),
+                column: new_name.to_owned(),
+                new_name: column.to_owned(),
+            },
+            RefactorOperation::MoveSchema {
+                ref table,
+                ref new_schema,
+            } => RefactorOperation::MoveSchema {
+                table: ObjectName {
+                    schema: Some(new_schema.to_owned()),
+                    name: table.name.to_owned(),
+                },
+                new_schema: table.schema().to_owned(),
+            },
+            RefactorOperation::RenameEnumValue {
+                ref type_name,
+                ref value,
+                ref new_name,
+            } => RefactorOperation::RenameEnumValue {
+                type_name: type_name.clone(),
+                value: new_name.to_owned(),
+                new_name: value.to_owned(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RefactorOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RefactorOperation::RenameTable {
+                ref table,
+                ref new_name,
+            } => write!(f, "rename table {} to {}", table, new_name),
+            RefactorOperation::RenameColumn {
+                ref table,
+                ref column,
+                ref new_name,
+            } => write!(f, "rename column {} on table {} to {}", column, table, new_name),
+            RefactorOperation::MoveSchema {
+                ref table,
+                ref new_schema,
+            } => write!(f, "move table {} to schema {}", table, new_schema),
+            RefactorOperation::RenameEnumValue {
+                ref type_name,
+                ref value,
+                ref new_name,
+            } => write!(f, "rename value {} of type {} to {}", value, type_name, new_name),
+        }
+    }
+}
+
+pub(crate) fn load_refactor_log(path: &Path) -> PsqlpackResult<Vec<RefactorDefinition>> {
+    File::open(path)
+        .chain_err(|| RefactorLogReadError(path.to_path_buf()))
+        .and_then(|file| serde_json::from_reader(file).chain_err(|| RefactorLogParseError(path.to_path_buf())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    #[test]
+    fn it_can_parse_a_refactor_log() {
+        let json = r#"[
+            { "id": "1", "operation": "renameTable", "table": { "schema": null, "name": "contacts" }, "newName": "people" },
+            { "id": "2", "operation": "renameColumn", "table": { "schema": "public", "name": "people" },
+              "column": "fname", "newName": "first_name" },
+            { "id": "3", "operation": "moveSchema", "table": { "schema": "public", "name": "people" },
+              "newSchema": "crm" },
+            { "id": "4", "operation": "renameEnumValue", "type": { "schema": "public", "name": "colors" },
+              "value": "red", "newName": "crimson" }
+        ]"#;
+        let refactors: Result<Vec<RefactorDefinition>, _> = serde_json::from_str(json);
+        assert_that!(refactors).is_ok().has_length(4);
+        let refactors = refactors.unwrap();
+        assert_that!(refactors[1]).is_equal_to(RefactorDefinition {
+            id: "2".into(),
+            operation: RefactorOperation::RenameColumn {
+                table: ObjectName {
+                    schema: Some("public".into()),
+                    name: "people".into(),
+                },
+                column: "fname".into(),
+                new_name: "first_name".into(),
+            },
+        });
+        assert_that!(refactors[2].operation.to_string())
+            .is_equal_to("move table public.people to schema crm".to_owned());
+        assert_that!(refactors[3].operation.to_string())
+            .is_equal_to("rename value red of type public.colors to crimson".to_owned());
+    }
+}
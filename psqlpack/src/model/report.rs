@@ -0,0 +1,441 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The version of the report format. This is incremented whenever an existing field is changed or removed, so that
+/// consumers can detect a report they don't understand. New fields may be added without changing the version.
+pub const REPORT_VERSION: u32 = 1;
+
+/// A deployment report describing each change that would be made to the target database, in the order it would be
+/// made. Unlike the instructions used to generate it, the shape of the report is independent of the AST.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub version: u32,
+    pub changes: Vec<ReportEntry>,
+}
+
+impl Report {
+    /// Renders a grouped summary suitable for a pull request comment
+    pub fn to_markdown(&self) -> String {
+        let summary = Summary::new(self);
+        let mut out = String::new();
+        out.push_str("# Deployment report\n\n");
+        out.push_str(&format!("{}\n", summary));
+
+        if !summary.data_loss.is_empty() || !summary.locking.is_empty() {
+            out.push_str("\n## Warnings\n");
+            for &(title, entries) in &[
+                ("Possible data loss", &summary.data_loss),
+                ("Locking and other risks", &summary.locking),
+            ] {
+                if entries.is_empty() {
+                    continue;
+                }
+                out.push_str(&format!("\n### {}\n\n", title));
+                for entry in entries.iter() {
+                    out.push_str(&format!("- {}\n", markdown_escape(&entry.description)));
+                }
+            }
+        }
+
+        for &(title, entries) in &[
+            ("Created", &summary.created),
+            ("Altered", &summary.altered),
+            ("Dropped", &summary.dropped),
+        ] {
+            if entries.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\n## {}\n\n", title));
+            out.push_str("| Type | Name | Before | After |\n");
+            out.push_str("|------|------|--------|-------|\n");
+            for entry in entries.iter() {
+                out.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    entry.object_type.map(|t| t.to_string()).unwrap_or_default(),
+                    markdown_code(entry.name.as_ref()),
+                    markdown_code(entry.before.as_ref()),
+                    markdown_code(entry.after.as_ref()),
+                ));
+            }
+        }
+
+        if !summary.notes.is_empty() {
+            out.push_str("\n## Notes\n\n");
+            for entry in &summary.notes {
+                out.push_str(&format!("- {}\n", markdown_escape(&entry.description)));
+            }
+        }
+        out
+    }
+
+    /// Renders a grouped summary as a self-contained HTML document
+    pub fn to_html(&self) -> String {
+        let summary = Summary::new(self);
+        let mut out = String::new();
+        out.push_str(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Deployment report</title>\n<style>\n\
+             body { font-family: sans-serif; margin: 2em; }\n\
+             table { border-collapse: collapse; margin-bottom: 1em; }\n\
+             th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+             code { font-family: monospace; }\n\
+             .high { color: #b00020; }\n\
+             .medium { color: #a35c00; }\n\
+             </style>\n</head>\n<body>\n<h1>Deployment report</h1>\n",
+        );
+        out.push_str(&format!("<p>{}</p>\n", html_escape(&summary.to_string())));
+
+        if !summary.data_loss.is_empty() || !summary.locking.is_empty() {
+            out.push_str("<h2>Warnings</h2>\n");
+            for &(title, class, entries) in &[
+                ("Possible data loss", "high", &summary.data_loss),
+                ("Locking and other risks", "medium", &summary.locking),
+            ] {
+                if entries.is_empty() {
+                    continue;
+                }
+                out.push_str(&format!("<h3 class=\"{}\">{}</h3>\n<ul>\n", class, title));
+                for entry in entries.iter() {
+                    out.push_str(&format!("<li>{}</li>\n", html_escape(&entry.description)));
+                }
+                out.push_str("</ul>\n");
+            }
+        }
+
+        for &(title, entries) in &[
+            ("Created", &summary.created),
+            ("Altered", &summary.altered),
+            ("Dropped", &summary.dropped),
+        ] {
+            if entries.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("<h2>{}</h2>\n<table>\n", title));
+            out.push_str("<tr><th>Type</th><th>Name</th><th>Before</th><th>After</th></tr>\n");
+            for entry in entries.iter() {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    entry.object_type.map(|t| t.to_string()).unwrap_or_default(),
+                    html_code(entry.name.as_ref()),
+                    html_code(entry.before.as_ref()),
+                    html_code(entry.after.as_ref()),
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+
+        if !summary.notes.is_empty() {
+            out.push_str("<h2>Notes</h2>\n<ul>\n");
+            for entry in &summary.notes {
+                out.push_str(&format!("<li>{}</li>\n", html_escape(&entry.description)));
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+// Groups the changes within a report by what they do to the target
+struct Summary<'a> {
+    created: Vec<&'a ReportEntry>,
+    altered: Vec<&'a ReportEntry>,
+    dropped: Vec<&'a ReportEntry>,
+    notes: Vec<&'a ReportEntry>,
+    data_loss: Vec<&'a ReportEntry>,
+    locking: Vec<&'a ReportEntry>,
+}
+
+impl<'a> Summary<'a> {
+    fn new(report: &'a Report) -> Summary<'a> {
+        let changes = |filter: &dyn Fn(&ReportEntry) -> bool| report.changes.iter().filter(|e| filter(e)).collect();
+        Summary {
+            created: changes(&|e| e.operation == Operation::Create),
+            altered: changes(&|e| matches!(e.operation, Operation::Alter | Operation::Rename | Operation::Update)),
+            dropped: changes(&|e| e.operation == Operation::Drop),
+            notes: changes(&|e| e.operation == Operation::Note),
+            data_loss: changes(&|e| e.risk == Risk::High),
+            locking: changes(&|e| e.risk == Risk::Medium),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Summary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} object(s) created, {} altered and {} dropped.",
+            self.created.len(),
+            self.altered.len(),
+            self.dropped.len()
+        )?;
+        if !self.data_loss.is_empty() {
+            write!(f, " {} change(s) may lose data.", self.data_loss.len())?;
+        }
+        Ok(())
+    }
+}
+
+fn markdown_escape(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+fn markdown_code(value: Option<&String>) -> String {
+    match value {
+        Some(value) => format!("`{}`", markdown_escape(value).replace('`', "'")),
+        None => String::new(),
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_code(value: Option<&String>) -> String {
+    match value {
+        Some(value) => format!("<code>{}</code>", html_escape(value)),
+        None => String::new(),
+    }
+}
+
+/// The format a deployment report is written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Markdown,
+    Html,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(format!(
+                "Unknown report format: {}. Expected one of json, markdown or html",
+                format
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReportEntry {
+    pub operation: Operation,
+    /// The kind of object being changed. This is empty for notes that don't relate to a single object.
+    #[serde(rename = "objectType")]
+    pub object_type: Option<ObjectType>,
+    /// The qualified name of the object being changed, e.g. `schema.table.column` for a column
+    pub name: Option<String>,
+    /// A human readable description of the change
+    pub description: String,
+    /// The value of the changed attribute within the target, such as a column type, if known
+    pub before: Option<String>,
+    /// The value of the changed attribute once published, if applicable
+    pub after: Option<String>,
+    pub risk: Risk,
+    /// The SQL that is executed for this change
+    pub sql: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    #[serde(rename = "connect")]
+    Connect,
+    #[serde(rename = "create")]
+    Create,
+    #[serde(rename = "alter")]
+    Alter,
+    #[serde(rename = "rename")]
+    Rename,
+    #[serde(rename = "update")]
+    Update,
+    #[serde(rename = "validate")]
+    Validate,
+    #[serde(rename = "drop")]
+    Drop,
+    #[serde(rename = "check")]
+    Check,
+    #[serde(rename = "run")]
+    Run,
+    #[serde(rename = "note")]
+    Note,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ObjectType {
+    #[serde(rename = "database")]
+    Database,
+    #[serde(rename = "extension")]
+    Extension,
+    #[serde(rename = "schema")]
+    Schema,
+    #[serde(rename = "script")]
+    Script,
+    #[serde(rename = "refactorLog")]
+    RefactorLog,
+    #[serde(rename = "scriptLog")]
+    ScriptLog,
+    #[serde(rename = "type")]
+    Type,
+    #[serde(rename = "table")]
+    Table,
+    #[serde(rename = "column")]
+    Column,
+    #[serde(rename = "constraint")]
+    Constraint,
+    #[serde(rename = "index")]
+    Index,
+    #[serde(rename = "function")]
+    Function,
+}
+
+/// How likely a change is to lose data or disrupt the target while it is applied
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum Risk {
+    /// Informational only, nothing is changed
+    #[serde(rename = "none")]
+    None,
+    /// Additive or metadata only changes
+    #[serde(rename = "low")]
+    Low,
+    /// Changes that hold locks while existing rows are scanned or rewritten, may fail against existing data, or run
+    /// arbitrary SQL
+    #[serde(rename = "medium")]
+    Medium,
+    /// Changes that may lose data
+    #[serde(rename = "high")]
+    High,
+}
+
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ObjectType::Database => "database",
+            ObjectType::Extension => "extension",
+            ObjectType::Schema => "schema",
+            ObjectType::Script => "script",
+            ObjectType::RefactorLog => "refactor log",
+            ObjectType::ScriptLog => "script log",
+            ObjectType::Type => "type",
+            ObjectType::Table => "table",
+            ObjectType::Column => "column",
+            ObjectType::Constraint => "constraint",
+            ObjectType::Index => "index",
+            ObjectType::Function => "function",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    fn sample_report() -> Report {
+        let entry = |operation, object_type, name: &str, before: Option<&str>, after: Option<&str>, risk| ReportEntry {
+            operation,
+            object_type: Some(object_type),
+            name: Some(name.to_owned()),
+            description: format!("Change {}", name),
+            before: before.map(|b| b.to_owned()),
+            after: after.map(|a| a.to_owned()),
+            risk,
+            sql: String::new(),
+        };
+        Report {
+            version: REPORT_VERSION,
+            changes: vec![
+                entry(Operation::Connect, ObjectType::Database, "db", None, None, Risk::None),
+                entry(
+                    Operation::Create,
+                    ObjectType::Table,
+                    "public.orders",
+                    None,
+                    None,
+                    Risk::Low,
+                ),
+                entry(
+                    Operation::Alter,
+                    ObjectType::Column,
+                    "public.contacts.name",
+                    Some("varchar(200)"),
+                    Some("varchar(100)"),
+                    Risk::High,
+                ),
+                entry(
+                    Operation::Create,
+                    ObjectType::Index,
+                    "public.idx_<name>",
+                    None,
+                    None,
+                    Risk::Medium,
+                ),
+                entry(
+                    Operation::Drop,
+                    ObjectType::Table,
+                    "public.archive",
+                    None,
+                    None,
+                    Risk::High,
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn it_can_render_a_markdown_report() {
+        assert_that!(sample_report().to_markdown()).is_equal_to(
+            "# Deployment report\n\n\
+             2 object(s) created, 1 altered and 1 dropped. 2 change(s) may lose data.\n\n\
+             ## Warnings\n\n\
+             ### Possible data loss\n\n\
+             - Change public.contacts.name\n\
+             - Change public.archive\n\n\
+             ### Locking and other risks\n\n\
+             - Change public.idx_<name>\n\n\
+             ## Created\n\n\
+             | Type | Name | Before | After |\n\
+             |------|------|--------|-------|\n\
+             | table | `public.orders` |  |  |\n\
+             | index | `public.idx_<name>` |  |  |\n\n\
+             ## Altered\n\n\
+             | Type | Name | Before | After |\n\
+             |------|------|--------|-------|\n\
+             | column | `public.contacts.name` | `varchar(200)` | `varchar(100)` |\n\n\
+             ## Dropped\n\n\
+             | Type | Name | Before | After |\n\
+             |------|------|--------|-------|\n\
+             | table | `public.archive` |  |  |\n"
+                .to_owned(),
+        );
+    }
+
+    #[test]
+    fn it_escapes_an_html_report() {
+        let html = sample_report().to_html();
+        assert_that!(html).starts_with("<!DOCTYPE html>");
+        assert_that!(html)
+            .contains("<h3 class=\"high\">Possible data loss</h3>\n<ul>\n<li>Change public.contacts.name</li>");
+        assert_that!(html).contains(
+            "<tr><td>column</td><td><code>public.contacts.name</code></td>\
+             <td><code>varchar(200)</code></td><td><code>varchar(100)</code></td></tr>",
+        );
+        assert_that!(html).contains("<code>public.idx_&lt;name&gt;</code>");
+        assert_that!(html.contains("idx_<name>")).is_false();
+    }
+
+    #[test]
+    fn it_can_parse_a_report_format() {
+        assert_that!("markdown".parse::<ReportFormat>()).is_ok_containing(ReportFormat::Markdown);
+        assert_that!("HTML".parse::<ReportFormat>()).is_ok_containing(ReportFormat::Html);
+        assert_that!("xml".parse::<ReportFormat>()).is_err();
+    }
+}
@@ -1,7 +1,10 @@
 use rust_decimal::Decimal;
+use sha2::{Digest, Sha256};
 
 use std::fmt;
 
+use crate::sql::quote::{quote_expression_identifier, quote_identifier, quote_literal};
+
 #[derive(Debug, PartialEq)]
 pub enum ErrorKind {
     ExtensionNotSupported(String),
@@ -27,6 +30,7 @@ pub enum Statement {
     Schema(SchemaDefinition),
     Table(TableDefinition),
     Type(TypeDefinition),
+    Owner(OwnerDefinition),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
@@ -60,18 +64,51 @@ pub enum SimpleSqlType {
 
     Boolean, // bool
 
-    Date,                 // date
-    DateTime,             // timestamp without time zone
-    DateTimeWithTimeZone, // timestamp with time zone
-    Time,                 // time
-    TimeWithTimeZone,     // time with time zone
+    Date,                                         // date
+    DateTime(Option<u32>),                        // timestamp(p) without time zone
+    DateTimeWithTimeZone(Option<u32>),            // timestamp(p) with time zone
+    Time(Option<u32>),                            // time(p)
+    TimeWithTimeZone(Option<u32>),                // time(p) with time zone
+    Interval(Option<IntervalField>, Option<u32>), // interval fields(p)
+
+    Binary, // bytea
 
     Uuid, // uuid
+
+    Json,  // json
+    Jsonb, // jsonb
+    Xml,   // xml
+
+    Inet,       // inet
+    Cidr,       // cidr
+    MacAddress, // macaddr
+
+    TsVector, // tsvector
+    TsQuery,  // tsquery
+
+    Point, // point
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub enum IntervalField {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    YearToMonth,
+    DayToHour,
+    DayToMinute,
+    DayToSecond,
+    HourToMinute,
+    HourToSecond,
+    MinuteToSecond,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub enum ColumnConstraint {
-    Default(AnyValue),
+    Default(Expression),
     NotNull,
     Null,
     Unique,
@@ -84,11 +121,29 @@ pub enum AnyValue {
     Array(Vec<AnyValue>, Option<SqlType>),
     Boolean(bool, Option<SqlType>),
     Decimal(Decimal, Option<SqlType>),
-    Integer(i32, Option<SqlType>),
+    Integer(i64, Option<SqlType>),
     String(String, Option<SqlType>),
     Null(Option<SqlType>),
 }
 
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub enum Expression {
+    Value(AnyValue),
+    Identifier(String),
+    FunctionCall(ObjectName, Vec<Expression>),
+    BinaryOperation(Box<Expression>, BinaryOperator, Box<Expression>),
+    Cast(Box<Expression>, SqlType),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Concat,
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub enum IndexParameter {
     FillFactor(u32),
@@ -162,9 +217,11 @@ pub struct TableDefinition {
     pub name: ObjectName,
     pub columns: Vec<ColumnDefinition>,
     pub constraints: Vec<TableConstraint>,
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub struct ColumnDefinition {
     pub name: String,
     pub sql_type: SqlType,
@@ -174,12 +231,16 @@ pub struct ColumnDefinition {
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct SchemaDefinition {
     pub name: String,
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct TypeDefinition {
     pub name: ObjectName,
     pub kind: TypeDefinitionKind,
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -196,6 +257,18 @@ pub struct ScriptDefinition {
     pub kind: ScriptKind,
     pub order: usize,
     pub contents: String,
+    #[serde(default)]
+    pub run: ScriptRunPolicy,
+}
+
+impl ScriptDefinition {
+    /// A SHA-256 hash of the script contents, used to detect when a script has changed
+    pub fn hash(&self) -> String {
+        Sha256::digest(self.contents.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -204,16 +277,54 @@ pub enum ScriptKind {
     PostDeployment,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+/// When a deployment script is run. Scripts that aren't always run are recorded within the target database.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScriptRunPolicy {
+    /// The script is run on every publish
+    #[serde(rename = "always")]
+    Always,
+    /// The script is run on the first publish only
+    #[serde(rename = "once")]
+    Once,
+    /// The script is run on the first publish and again whenever its contents change
+    #[serde(rename = "onChange")]
+    OnChange,
+}
+
+impl Default for ScriptRunPolicy {
+    fn default() -> Self {
+        ScriptRunPolicy::Always
+    }
+}
+
+/// A script recorded as having been run against a database
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExecutedScript {
+    pub name: String,
+    /// The hash of the script contents when it was last run
+    pub hash: String,
+}
+
+/// An object that uses a type however isn't modelled, such as a view or CHECK constraint
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TypeDependent {
+    pub type_name: ObjectName,
+    /// The object as described by Postgres, e.g. `view public.active_contacts`
+    pub description: String,
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub struct FunctionDefinition {
     pub name: ObjectName,
     pub arguments: Vec<FunctionArgument>,
     pub return_type: FunctionReturnType,
     pub body: String,
     pub language: FunctionLanguage,
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub struct FunctionArgument {
     pub mode: Option<FunctionArgumentMode>,
     pub name: Option<String>,
@@ -221,7 +332,7 @@ pub struct FunctionArgument {
     pub default: Option<AnyValue>,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub enum FunctionArgumentMode {
     In,
     InOut,
@@ -229,7 +340,7 @@ pub enum FunctionArgumentMode {
     Variadic,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub enum FunctionReturnType {
     Table(Vec<ColumnDefinition>),
     SetOf(SqlType),
@@ -245,6 +356,22 @@ pub enum FunctionLanguage {
     Custom(String),
 }
 
+#[derive(Debug, PartialEq)]
+pub struct OwnerDefinition {
+    pub kind: OwnedObjectKind,
+    pub name: ObjectName,
+    pub arguments: Option<Vec<FunctionArgument>>, // Only set for functions declared with an argument list
+    pub owner: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OwnedObjectKind {
+    Function,
+    Schema,
+    Table,
+    Type,
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct IndexDefinition {
     pub name: String,
@@ -258,7 +385,7 @@ pub struct IndexDefinition {
 
 impl IndexDefinition {
     pub fn fully_qualified_name(&self) -> String {
-        format!("{}.{}", self.schema(), self.name)
+        format!("{}.{}", quote_identifier(self.schema()), quote_identifier(&self.name))
     }
 
     pub fn is_same_index(&self, other: &IndexDefinition) -> bool {
@@ -327,7 +454,7 @@ impl fmt::Display for AnyValue {
                 sql_type
             }
             AnyValue::String(ref s, ref sql_type) => {
-                write!(f, "'{}'", s)?;
+                write!(f, "{}", quote_literal(s))?;
                 sql_type
             }
             AnyValue::Null(ref sql_type) => {
@@ -342,6 +469,42 @@ impl fmt::Display for AnyValue {
     }
 }
 
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Value(ref value) => write!(f, "{}", value),
+            Expression::Identifier(ref ident) => write!(f, "{}", quote_expression_identifier(ident)),
+            Expression::FunctionCall(ref name, ref args) => {
+                write!(f, "{}(", name)?;
+                let mut comma = false;
+                for arg in args {
+                    if comma {
+                        write!(f, ", ")?;
+                    } else {
+                        comma = true;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expression::BinaryOperation(ref left, ref op, ref right) => write!(f, "({} {} {})", left, op, right),
+            Expression::Cast(ref expr, ref sql_type) => write!(f, "{}::{}", expr, sql_type),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BinaryOperator::Add => write!(f, "+"),
+            BinaryOperator::Subtract => write!(f, "-"),
+            BinaryOperator::Multiply => write!(f, "*"),
+            BinaryOperator::Divide => write!(f, "/"),
+            BinaryOperator::Concat => write!(f, "||"),
+        }
+    }
+}
+
 impl fmt::Display for ForeignConstraintMatchType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -367,15 +530,15 @@ impl fmt::Display for ForeignConstraintAction {
 impl fmt::Display for FunctionArgument {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some(ref mode) = self.mode {
-            write!(f, "{}", mode)?;
+            write!(f, "{} ", mode)?;
         }
         if let Some(ref name) = self.name {
-            write!(f, "{} {}", name, self.sql_type)?;
+            write!(f, "{} {}", quote_identifier(name), self.sql_type)?;
         } else {
             write!(f, "{}", self.sql_type)?;
         }
         if let Some(ref default) = self.default {
-            write!(f, "{}", default)?;
+            write!(f, " DEFAULT {}", default)?;
         }
         Ok(())
     }
@@ -407,8 +570,8 @@ impl fmt::Display for FunctionLanguage {
 impl fmt::Display for ObjectName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.schema {
-            Some(ref s) => write!(f, "{}.{}", s, self.name),
-            None => write!(f, "{}", self.name),
+            Some(ref s) => write!(f, "{}.{}", quote_identifier(s), quote_identifier(&self.name)),
+            None => write!(f, "{}", quote_identifier(&self.name)),
         }
     }
 }
@@ -435,6 +598,17 @@ impl fmt::Display for SqlType {
     }
 }
 
+impl fmt::Display for OwnedObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OwnedObjectKind::Function => write!(f, "FUNCTION"),
+            OwnedObjectKind::Schema => write!(f, "SCHEMA"),
+            OwnedObjectKind::Table => write!(f, "TABLE"),
+            OwnedObjectKind::Type => write!(f, "TYPE"),
+        }
+    }
+}
+
 impl fmt::Display for TypeDefinitionKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -474,12 +648,61 @@ impl fmt::Display for SimpleSqlType {
             SimpleSqlType::Boolean => write!(f, "bool"),
 
             SimpleSqlType::Date => write!(f, "date"),
-            SimpleSqlType::DateTime => write!(f, "timestamp without time zone"),
-            SimpleSqlType::DateTimeWithTimeZone => write!(f, "timestamp with time zone"),
-            SimpleSqlType::Time => write!(f, "time"),
-            SimpleSqlType::TimeWithTimeZone => write!(f, "time with time zone"),
+            SimpleSqlType::DateTime(None) => write!(f, "timestamp without time zone"),
+            SimpleSqlType::DateTime(Some(p)) => write!(f, "timestamp({}) without time zone", p),
+            SimpleSqlType::DateTimeWithTimeZone(None) => write!(f, "timestamp with time zone"),
+            SimpleSqlType::DateTimeWithTimeZone(Some(p)) => write!(f, "timestamp({}) with time zone", p),
+            SimpleSqlType::Time(None) => write!(f, "time"),
+            SimpleSqlType::Time(Some(p)) => write!(f, "time({})", p),
+            SimpleSqlType::TimeWithTimeZone(None) => write!(f, "time with time zone"),
+            SimpleSqlType::TimeWithTimeZone(Some(p)) => write!(f, "time({}) with time zone", p),
+            SimpleSqlType::Interval(ref fields, precision) => {
+                write!(f, "interval")?;
+                if let Some(ref fields) = *fields {
+                    write!(f, " {}", fields)?;
+                }
+                if let Some(p) = precision {
+                    write!(f, "({})", p)?;
+                }
+                Ok(())
+            }
+
+            SimpleSqlType::Binary => write!(f, "bytea"),
 
             SimpleSqlType::Uuid => write!(f, "uuid"),
+
+            SimpleSqlType::Json => write!(f, "json"),
+            SimpleSqlType::Jsonb => write!(f, "jsonb"),
+            SimpleSqlType::Xml => write!(f, "xml"),
+
+            SimpleSqlType::Inet => write!(f, "inet"),
+            SimpleSqlType::Cidr => write!(f, "cidr"),
+            SimpleSqlType::MacAddress => write!(f, "macaddr"),
+
+            SimpleSqlType::TsVector => write!(f, "tsvector"),
+            SimpleSqlType::TsQuery => write!(f, "tsquery"),
+
+            SimpleSqlType::Point => write!(f, "point"),
+        }
+    }
+}
+
+impl fmt::Display for IntervalField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntervalField::Year => write!(f, "year"),
+            IntervalField::Month => write!(f, "month"),
+            IntervalField::Day => write!(f, "day"),
+            IntervalField::Hour => write!(f, "hour"),
+            IntervalField::Minute => write!(f, "minute"),
+            IntervalField::Second => write!(f, "second"),
+            IntervalField::YearToMonth => write!(f, "year to month"),
+            IntervalField::DayToHour => write!(f, "day to hour"),
+            IntervalField::DayToMinute => write!(f, "day to minute"),
+            IntervalField::DayToSecond => write!(f, "day to second"),
+            IntervalField::HourToMinute => write!(f, "hour to minute"),
+            IntervalField::HourToSecond => write!(f, "hour to second"),
+            IntervalField::MinuteToSecond => write!(f, "minute to second"),
         }
     }
 }
@@ -36,6 +36,8 @@ mod context {
         LiteralStart,
         LiteralEnd,
         LiteralBody,
+
+        Variable,
     }
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -101,6 +103,7 @@ mod context {
                         LexerState::LiteralStart => "LiteralBegin",
                         LexerState::LiteralBody => "Literal",
                         LexerState::LiteralEnd => "LiteralEnd",
+                        LexerState::Variable => "Variable",
                     })
                     .collect::<Vec<_>>()
                     .join(" -> "),
@@ -134,16 +137,20 @@ mod context {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Token {
     ACTION,
+    ALTER,
     ARRAY,
     AS,
     ASC,
+    AUTHORIZATION,
     BIGINT,
     BIGSERIAL,
     BIT,
     BOOL,
     BOOLEAN,
     BTREE,
+    BYTEA,
     CASCADE,
+    CIDR,
     CONSTRAINT,
     CHAR,
     CHARACTER,
@@ -165,15 +172,20 @@ pub enum Token {
     HASH,
     IN,
     INDEX,
+    INET,
     INOUT,
     INT,
     INT2,
     INT4,
     INT8,
     INTEGER,
+    INTERVAL,
+    JSON,
+    JSONB,
     KEY,
     LANGUAGE,
     LAST,
+    MACADDR,
     MATCH,
     MONEY,
     NO,
@@ -184,7 +196,9 @@ pub enum Token {
     ON,
     OR,
     OUT,
+    OWNER,
     PARTIAL,
+    POINT,
     PRECISION,
     PRIMARY,
     REAL,
@@ -208,6 +222,9 @@ pub enum Token {
     TIMESTAMP,
     TIMESTAMPTZ,
     TIMETZ,
+    TO,
+    TSQUERY,
+    TSVECTOR,
     TYPE,
     UNIQUE,
     UPDATE,
@@ -219,10 +236,11 @@ pub enum Token {
     VARYING,
     WITH,
     WITHOUT,
+    XML,
     ZONE,
 
     Identifier(String),
-    Digit(i32),
+    Digit(i64),
     Decimal(Decimal),
     Boolean(bool),
     StringValue(String),
@@ -238,22 +256,32 @@ pub enum Token {
     Period,
     Semicolon,
     Equals,
+
+    Plus,
+    Minus,
+    Asterisk,
+    Slash,
+    Concat,
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Token::ACTION => write!(f, "ACTION"),
+            Token::ALTER => write!(f, "ALTER"),
             Token::ARRAY => write!(f, "ARRAY"),
             Token::AS => write!(f, "AS"),
             Token::ASC => write!(f, "ASC"),
+            Token::AUTHORIZATION => write!(f, "AUTHORIZATION"),
             Token::BIGINT => write!(f, "BIGINT"),
             Token::BIGSERIAL => write!(f, "BIGSERIAL"),
             Token::BIT => write!(f, "BIT"),
             Token::BOOL => write!(f, "BOOL"),
             Token::BOOLEAN => write!(f, "BOOLEAN"),
             Token::BTREE => write!(f, "BTREE"),
+            Token::BYTEA => write!(f, "BYTEA"),
             Token::CASCADE => write!(f, "CASCADE"),
+            Token::CIDR => write!(f, "CIDR"),
             Token::CONSTRAINT => write!(f, "CONSTRAINT"),
             Token::CHAR => write!(f, "CHAR"),
             Token::CHARACTER => write!(f, "CHARACTER"),
@@ -275,15 +303,20 @@ impl fmt::Display for Token {
             Token::HASH => write!(f, "HASH"),
             Token::IN => write!(f, "IN"),
             Token::INDEX => write!(f, "INDEX"),
+            Token::INET => write!(f, "INET"),
             Token::INOUT => write!(f, "INOUT"),
             Token::INT => write!(f, "INT"),
             Token::INT2 => write!(f, "INT2"),
             Token::INT4 => write!(f, "INT4"),
             Token::INT8 => write!(f, "INT8"),
             Token::INTEGER => write!(f, "INTEGER"),
+            Token::INTERVAL => write!(f, "INTERVAL"),
+            Token::JSON => write!(f, "JSON"),
+            Token::JSONB => write!(f, "JSONB"),
             Token::KEY => write!(f, "KEY"),
             Token::LANGUAGE => write!(f, "LANGUAGE"),
             Token::LAST => write!(f, "LAST"),
+            Token::MACADDR => write!(f, "MACADDR"),
             Token::MATCH => write!(f, "MATCH"),
             Token::MONEY => write!(f, "MONEY"),
             Token::NO => write!(f, "NO"),
@@ -294,7 +327,9 @@ impl fmt::Display for Token {
             Token::ON => write!(f, "ON"),
             Token::OR => write!(f, "OR"),
             Token::OUT => write!(f, "OUT"),
+            Token::OWNER => write!(f, "OWNER"),
             Token::PARTIAL => write!(f, "PARTIAL"),
+            Token::POINT => write!(f, "POINT"),
             Token::PRECISION => write!(f, "PRECISION"),
             Token::PRIMARY => write!(f, "PRIMARY"),
             Token::REAL => write!(f, "REAL"),
@@ -318,6 +353,9 @@ impl fmt::Display for Token {
             Token::TIMESTAMP => write!(f, "TIMESTAMP"),
             Token::TIMESTAMPTZ => write!(f, "TIMESTAMPTZ"),
             Token::TIMETZ => write!(f, "TIMETZ"),
+            Token::TO => write!(f, "TO"),
+            Token::TSQUERY => write!(f, "TSQUERY"),
+            Token::TSVECTOR => write!(f, "TSVECTOR"),
             Token::TYPE => write!(f, "TYPE"),
             Token::UNIQUE => write!(f, "UNIQUE"),
             Token::UPDATE => write!(f, "UPDATE"),
@@ -329,6 +367,7 @@ impl fmt::Display for Token {
             Token::VARYING => write!(f, "VARYING"),
             Token::WITH => write!(f, "WITH"),
             Token::WITHOUT => write!(f, "WITHOUT"),
+            Token::XML => write!(f, "XML"),
             Token::ZONE => write!(f, "ZONE"),
 
             Token::Identifier(ref ident) => write!(f, "Ident({})", ident),
@@ -348,20 +387,28 @@ impl fmt::Display for Token {
             Token::Period => write!(f, "."),
             Token::Semicolon => write!(f, ";"),
             Token::Equals => write!(f, "="),
+
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Asterisk => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Concat => write!(f, "||"),
         }
     }
 }
 
 lazy_static! {
     static ref IDENTIFIER: Regex = Regex::new("^[a-zA-Z][a-zA-Z0-9_]*$").unwrap();
-    static ref DECIMAL: Regex = Regex::new("^\\d+\\.\\d+$").unwrap();
-    static ref DIGIT: Regex = Regex::new("^\\d+$").unwrap();
+    static ref DECIMAL: Regex = Regex::new("^-?\\d+\\.\\d+$").unwrap();
+    static ref DIGIT: Regex = Regex::new("^-?\\d+$").unwrap();
+    static ref SCIENTIFIC: Regex = Regex::new("^-?\\d+(\\.\\d+)?[eE][+-]?\\d+$").unwrap();
+    static ref EXPONENT_START: Regex = Regex::new("^-?\\d+(\\.\\d+)?[eE]$").unwrap();
 }
 
 macro_rules! tokenize_normal_buffer {
     ($context:ident, $line:ident, $tokens:ident) => {{
         if $context.buffer.len() > 0 {
-            let token = match self::create_normal_token(&mut $context) {
+            let token = match self::create_normal_token(&mut $context, &$tokens) {
                 Some(t) => t,
                 None => return Err($context.create_error($line, "unexpected token")),
             };
@@ -397,7 +444,7 @@ macro_rules! push_token {
     };
 }
 
-fn create_normal_token(context: &mut Context) -> Option<Token> {
+fn create_normal_token(context: &mut Context, tokens: &[Token]) -> Option<Token> {
     let variant = if let LexerState::Normal(variant) = context.peek_state() {
         variant
     } else {
@@ -405,6 +452,12 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
     };
 
     let value = String::from_iter(context.buffer.clone());
+    match &value[..] {
+        "-" => return Some(Token::Minus),
+        "/" => return Some(Token::Slash),
+        "||" => return Some(Token::Concat),
+        _ => {}
+    }
     if "true".eq_ignore_ascii_case(&value[..]) {
         return Some(Token::Boolean(true));
     }
@@ -414,6 +467,7 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
 
     // Keywords - this is very naive and should be generated.
     if let NormalVariant::Any = variant {
+        match_keyword!(value, ALTER);
         match_keyword!(value, CREATE);
         match_keyword!(value, OR);
         match_keyword!(value, REPLACE);
@@ -426,6 +480,21 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
         match_keyword_replace_state!(context, NormalVariant::Body, value, TABLE);
     }
 
+    // Ownership keywords are only reserved within the statements that use them so that they can still name columns
+    let statement = match tokens.iter().rposition(|t| *t == Token::Semicolon) {
+        Some(position) => &tokens[position + 1..],
+        None => tokens,
+    };
+    match statement.first() {
+        Some(Token::ALTER) => match_keyword!(value, OWNER),
+        Some(Token::CREATE) if statement.get(1) == Some(&Token::SCHEMA) => match_keyword!(value, AUTHORIZATION),
+        _ => {}
+    }
+    match *tokens {
+        [.., Token::OWNER] | [.., Token::INTERVAL, Token::Identifier(_)] => match_keyword!(value, TO),
+        _ => {}
+    }
+
     match_keyword!(value, ACTION);
     match_keyword!(value, ARRAY);
     match_keyword!(value, AS);
@@ -436,7 +505,9 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
     match_keyword!(value, BOOL);
     match_keyword!(value, BOOLEAN);
     match_keyword!(value, BTREE);
+    match_keyword!(value, BYTEA);
     match_keyword!(value, CASCADE);
+    match_keyword!(value, CIDR);
     match_keyword!(value, CONSTRAINT);
     match_keyword!(value, CHAR);
     match_keyword!(value, CHARACTER);
@@ -454,15 +525,20 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
     match_keyword!(value, GIST);
     match_keyword!(value, HASH);
     match_keyword!(value, IN);
+    match_keyword!(value, INET);
     match_keyword!(value, INOUT);
     match_keyword!(value, INT);
     match_keyword!(value, INT2);
     match_keyword!(value, INT4);
     match_keyword!(value, INT8);
     match_keyword!(value, INTEGER);
+    match_keyword!(value, INTERVAL);
+    match_keyword!(value, JSON);
+    match_keyword!(value, JSONB);
     match_keyword!(value, KEY);
     match_keyword!(value, LANGUAGE);
     match_keyword!(value, LAST);
+    match_keyword!(value, MACADDR);
     match_keyword!(value, MATCH);
     match_keyword!(value, MONEY);
     match_keyword!(value, NO);
@@ -474,6 +550,7 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
     match_keyword!(value, OR);
     match_keyword!(value, OUT);
     match_keyword!(value, PARTIAL);
+    match_keyword!(value, POINT);
     match_keyword!(value, PRECISION);
     match_keyword!(value, PRIMARY);
     match_keyword!(value, REAL);
@@ -495,6 +572,8 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
     match_keyword!(value, TIMESTAMP);
     match_keyword!(value, TIMESTAMPTZ);
     match_keyword!(value, TIMETZ);
+    match_keyword!(value, TSQUERY);
+    match_keyword!(value, TSVECTOR);
     match_keyword!(value, TYPE);
     match_keyword!(value, UNIQUE);
     match_keyword!(value, UPDATE);
@@ -506,23 +585,56 @@ fn create_normal_token(context: &mut Context) -> Option<Token> {
     match_keyword!(value, VARYING);
     match_keyword!(value, WITH);
     match_keyword!(value, WITHOUT);
+    match_keyword!(value, XML);
     match_keyword!(value, ZONE);
 
     // Regex
     if IDENTIFIER.is_match(&value[..]) {
-        return Some(Token::Identifier(value));
+        // Unquoted identifiers are case insensitive so we fold them the same way Postgres does
+        return Some(Token::Identifier(value.to_lowercase()));
     }
     if DECIMAL.is_match(&value[..]) {
         return Some(Token::Decimal(value.parse::<Decimal>().unwrap()));
     }
     if DIGIT.is_match(&value[..]) {
-        return Some(Token::Digit(value.parse::<i32>().unwrap()));
+        // Anything too large for a 64 bit integer falls back to a decimal
+        return match value.parse::<i64>() {
+            Ok(i) => Some(Token::Digit(i)),
+            Err(_) => value.parse::<Decimal>().ok().map(Token::Decimal),
+        };
+    }
+    if SCIENTIFIC.is_match(&value[..]) {
+        return Decimal::from_scientific(&value.to_lowercase()).ok().map(Token::Decimal);
     }
 
     // Error
     None
 }
 
+fn is_operator(buffer: &[char]) -> bool {
+    matches!(*buffer, ['-'] | ['/'] | ['|', '|'])
+}
+
+// A minus sign is only part of a numeric literal if it couldn't otherwise be a subtraction
+fn starts_operand(previous: Option<&Token>) -> bool {
+    match previous {
+        None => true,
+        Some(token) => matches!(
+            *token,
+            Token::DEFAULT
+                | Token::LeftBracket
+                | Token::LeftSquare
+                | Token::Comma
+                | Token::Equals
+                | Token::Plus
+                | Token::Minus
+                | Token::Asterisk
+                | Token::Slash
+                | Token::Concat
+        ),
+    }
+}
+
 pub fn tokenize_body(text: &str) -> Result<Vec<Token>, LexicalError> {
     tokenize(text, NormalVariant::Body)
 }
@@ -557,14 +669,34 @@ fn tokenize(text: &str, start: NormalVariant) -> Result<Vec<Token>, LexicalError
                         tokenize_normal_buffer!(context, line, tokens);
                         context.push_state(LexerState::Comment2);
                     } else if c == '\'' {
-                        if context.buffer.is_empty() {
+                        if is_operator(&context.buffer) {
+                            tokenize_normal_buffer!(context, line, tokens);
+                        }
+                        if context.buffer.is_empty() && context.last_char == '\'' {
+                            // An escaped quote, so we continue the previous string
+                            if let Some(Token::StringValue(value)) = tokens.pop() {
+                                context.buffer.extend(value.chars());
+                                context.buffer.push(c);
+                            }
+                            context.push_state(LexerState::String);
+                        } else if context.buffer.is_empty() {
                             context.push_state(LexerState::String);
                         } else {
                             // Invalid state - must be something like xx'dd
                             return Err(context.create_error(line, "' was unexpected"));
                         }
                     } else if c == '"' {
-                        if context.buffer.is_empty() {
+                        if is_operator(&context.buffer) {
+                            tokenize_normal_buffer!(context, line, tokens);
+                        }
+                        if context.buffer.is_empty() && context.last_char == '"' {
+                            // An escaped quote, so we continue the previous identifier
+                            if let Some(Token::Identifier(value)) = tokens.pop() {
+                                context.buffer.extend(value.chars());
+                                context.buffer.push(c);
+                            }
+                            context.push_state(LexerState::QuotedIdentifier);
+                        } else if context.buffer.is_empty() {
                             context.push_state(LexerState::QuotedIdentifier);
                         } else {
                             // Invalid state - Must be something like xx"dd
@@ -610,7 +742,11 @@ fn tokenize(text: &str, start: NormalVariant) -> Result<Vec<Token>, LexicalError
                             }
                             '.' => {
                                 // If it is just a plain digit in the buffer, then allow it to continue.
-                                if context.buffer.iter().all(|c: &char| c.is_digit(10)) {
+                                let digits = match context.buffer.first() {
+                                    Some('-') => &context.buffer[1..],
+                                    _ => &context.buffer[..],
+                                };
+                                if !digits.is_empty() && digits.iter().all(|c: &char| c.is_ascii_digit()) {
                                     context.buffer.push(c);
                                 } else {
                                     tokenize_normal_buffer!(context, line, tokens);
@@ -625,7 +761,38 @@ fn tokenize(text: &str, start: NormalVariant) -> Result<Vec<Token>, LexicalError
                                 tokenize_normal_buffer!(context, line, tokens);
                                 push_token!(tokens, Token::RightSquare);
                             }
-                            _ => context.buffer.push(c),
+                            '+' | '-' if EXPONENT_START.is_match(&String::from_iter(context.buffer.clone())) => {
+                                // The sign of an exponent in scientific notation
+                                context.buffer.push(c);
+                            }
+                            '+' => {
+                                tokenize_normal_buffer!(context, line, tokens);
+                                push_token!(tokens, Token::Plus);
+                            }
+                            '*' => {
+                                tokenize_normal_buffer!(context, line, tokens);
+                                push_token!(tokens, Token::Asterisk);
+                            }
+                            '-' | '/' => {
+                                // These may also start a comment so we leave them in the buffer until we know more
+                                tokenize_normal_buffer!(context, line, tokens);
+                                context.buffer.push(c);
+                            }
+                            '|' => {
+                                if context.buffer != ['|'] {
+                                    tokenize_normal_buffer!(context, line, tokens);
+                                }
+                                context.buffer.push(c);
+                            }
+                            _ => {
+                                // Operators left in the buffer are complete once something else follows
+                                let negative_literal =
+                                    context.buffer == ['-'] && c.is_ascii_digit() && starts_operand(tokens.last());
+                                if is_operator(&context.buffer) && !negative_literal {
+                                    tokenize_normal_buffer!(context, line, tokens);
+                                }
+                                context.buffer.push(c)
+                            }
                         }
                     }
                 }
@@ -657,12 +824,25 @@ fn tokenize(text: &str, start: NormalVariant) -> Result<Vec<Token>, LexicalError
                     }
                 }
                 LexerState::LiteralStart => {
-                    if c == '$' {
+                    if c == '(' && context.literal.is_empty() {
+                        // A $(Name) variable placeholder rather than a literal
+                        context.buffer.extend(&['$', '(']);
+                        context.replace_state(LexerState::Variable);
+                    } else if c == '$' {
                         context.replace_state(LexerState::LiteralBody);
                     } else {
                         context.literal.push(c);
                     }
                 }
+                LexerState::Variable => {
+                    // Placeholders are kept verbatim until variables are resolved
+                    context.buffer.push(c);
+                    if c == ')' {
+                        push_token!(tokens, Token::Identifier(String::from_iter(context.buffer.clone())));
+                        context.buffer.clear();
+                        context.pop_state();
+                    }
+                }
                 LexerState::LiteralEnd => {
                     if c == '$' {
                         if context.literal.is_empty() {
@@ -740,7 +920,11 @@ fn tokenize(text: &str, start: NormalVariant) -> Result<Vec<Token>, LexicalError
             LexerState::Comment2 => {
                 // Do nothing at the end of a line - it's a multi-line comment
             }
-            LexerState::String | LexerState::QuotedIdentifier | LexerState::LiteralStart | LexerState::LiteralEnd => {
+            LexerState::String
+            | LexerState::QuotedIdentifier
+            | LexerState::LiteralStart
+            | LexerState::LiteralEnd
+            | LexerState::Variable => {
                 // If we're in these states at the end of a line it's an error
                 // (e.g. at the moment we don't support multi-line strings)
                 return Err(context.create_error(line, "end of line was unexpected"));
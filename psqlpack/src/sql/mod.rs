@@ -2,6 +2,7 @@ pub mod ast;
 pub mod lexer;
 #[cfg_attr(rustfmt, rustfmt_skip)]
 pub mod parser;
+pub mod quote;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+
+// Keywords that cannot be used as an identifier without quoting. This includes keywords that are
+// reserved but may still be used as a function or type name.
+static RESERVED_KEYWORDS: &[&str] = &[
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "authorization",
+    "binary",
+    "both",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "collation",
+    "column",
+    "concurrently",
+    "constraint",
+    "create",
+    "cross",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_schema",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "freeze",
+    "from",
+    "full",
+    "grant",
+    "group",
+    "having",
+    "ilike",
+    "in",
+    "initially",
+    "inner",
+    "intersect",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "lateral",
+    "leading",
+    "left",
+    "like",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "natural",
+    "not",
+    "notnull",
+    "null",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "outer",
+    "overlaps",
+    "placing",
+    "primary",
+    "references",
+    "returning",
+    "right",
+    "select",
+    "session_user",
+    "similar",
+    "some",
+    "symmetric",
+    "table",
+    "tablesample",
+    "then",
+    "to",
+    "trailing",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "variadic",
+    "verbose",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+// Reserved keywords that are evaluated as a function when used within an expression, e.g. `DEFAULT CURRENT_TIMESTAMP`
+static VALUE_FUNCTIONS: &[&str] = &[
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_schema",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "localtime",
+    "localtimestamp",
+    "session_user",
+    "user",
+];
+
+/// Quotes an identifier if it would otherwise be case folded or interpreted as a keyword by Postgres.
+pub fn quote_identifier(ident: &str) -> Cow<'_, str> {
+    let mut chars = ident.chars();
+    let simple = match chars.next() {
+        Some(c) => {
+            (c.is_ascii_lowercase() || c == '_')
+                && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '$')
+        }
+        None => false,
+    };
+    if simple && RESERVED_KEYWORDS.binary_search(&ident).is_err() {
+        Cow::Borrowed(ident)
+    } else {
+        Cow::Owned(format!("\"{}\"", ident.replace('"', "\"\"")))
+    }
+}
+
+/// Quotes a string so that it can be used as a literal value.
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Quotes an identifier used within an expression, leaving keywords such as `current_timestamp` that Postgres evaluates
+/// as a function.
+pub fn quote_expression_identifier(ident: &str) -> Cow<'_, str> {
+    if VALUE_FUNCTIONS.contains(&ident) {
+        Cow::Borrowed(ident)
+    } else {
+        quote_identifier(ident)
+    }
+}
@@ -1,6 +1,9 @@
 use crate::sql::ast::*;
 use crate::sql::lexer;
-use crate::sql::parser::{FunctionArgumentListParser, StatementListParser};
+use crate::sql::parser::{ExpressionParser, FunctionArgumentListParser, SqlTypeParser, StatementListParser};
+use crate::sql::quote::{quote_identifier, quote_literal};
+
+use rust_decimal::Decimal;
 
 use spectral::prelude::*;
 
@@ -37,6 +40,7 @@ fn it_can_parse_a_basic_function_definition() {
         return_type: FunctionReturnType::SqlType(SqlType::Simple(SimpleSqlType::Integer, None)),
         body: "SELECT index".into(),
         language: FunctionLanguage::SQL,
+        owner: None,
     }));
 }
 
@@ -68,6 +72,7 @@ fn it_can_parse_a_function_definition_with_simple_literals() {
         return_type: FunctionReturnType::SqlType(SqlType::Simple(SimpleSqlType::Integer, None)),
         body: "SELECT 1".into(),
         language: FunctionLanguage::SQL,
+        owner: None,
     }));
 }
 
@@ -120,6 +125,7 @@ fn it_can_parse_a_function_definition_returning_table() {
                    ORDER BY countries.iso"
             .into(),
         language: FunctionLanguage::SQL,
+        owner: None,
     }));
 }
 
@@ -179,6 +185,7 @@ fn it_can_parse_a_function_definition_with_parameters() {
                    ORDER BY states.iso"
             .into(),
         language: FunctionLanguage::SQL,
+        owner: None,
     }));
 }
 
@@ -292,3 +299,378 @@ fn it_can_parse_function_arguments() {
         },
     ]);
 }
+
+#[test]
+fn it_can_parse_ownership_statements() {
+    let sql = "CREATE SCHEMA my AUTHORIZATION app_owner;
+               ALTER TABLE my.contacts OWNER TO app_owner;
+               ALTER TYPE colors OWNER TO app_owner;
+               ALTER FUNCTION my.fn_contacts(int, text) OWNER TO app_owner;";
+
+    let tokens = lexer::tokenize_stmt(sql);
+    assert_that!(tokens).is_ok();
+    let tokens = tokens.unwrap();
+
+    let statements = StatementListParser::new().parse(tokens);
+    assert_that!(statements).is_ok();
+    let statements = statements.unwrap();
+    assert_that!(statements).has_length(4);
+
+    assert_that!(statements[0]).is_equal_to(Statement::Schema(SchemaDefinition {
+        name: "my".into(),
+        owner: Some("app_owner".into()),
+    }));
+    assert_that!(statements[1]).is_equal_to(Statement::Owner(OwnerDefinition {
+        kind: OwnedObjectKind::Table,
+        name: ObjectName {
+            schema: Some("my".into()),
+            name: "contacts".into(),
+        },
+        arguments: None,
+        owner: "app_owner".into(),
+    }));
+    assert_that!(statements[2]).is_equal_to(Statement::Owner(OwnerDefinition {
+        kind: OwnedObjectKind::Type,
+        name: ObjectName {
+            schema: None,
+            name: "colors".into(),
+        },
+        arguments: None,
+        owner: "app_owner".into(),
+    }));
+    assert_that!(statements[3]).is_equal_to(Statement::Owner(OwnerDefinition {
+        kind: OwnedObjectKind::Function,
+        name: ObjectName {
+            schema: Some("my".into()),
+            name: "fn_contacts".into(),
+        },
+        arguments: Some(vec![
+            FunctionArgument {
+                mode: None,
+                name: None,
+                sql_type: SqlType::Simple(SimpleSqlType::Integer, None),
+                default: None,
+            },
+            FunctionArgument {
+                mode: None,
+                name: None,
+                sql_type: SqlType::Simple(SimpleSqlType::Text, None),
+                default: None,
+            },
+        ]),
+        owner: "app_owner".into(),
+    }));
+}
+
+#[test]
+fn it_can_use_ownership_keywords_as_column_names() {
+    let sql = "CREATE TABLE my.accounts (owner text NOT NULL, authorization text, \"to\" text);
+               ALTER TABLE my.accounts OWNER TO app_owner;";
+
+    let tokens = lexer::tokenize_stmt(sql);
+    assert_that!(tokens).is_ok();
+    let tokens = tokens.unwrap();
+
+    let statements = StatementListParser::new().parse(tokens);
+    assert_that!(statements).is_ok();
+    let statements = statements.unwrap();
+    assert_that!(statements).has_length(2);
+
+    let table = match statements[0] {
+        Statement::Table(ref table) => table,
+        ref unexpected => panic!("Expected a table however saw {:?}", unexpected),
+    };
+    let columns = table.columns.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+    assert_that!(columns).is_equal_to(vec!["owner", "authorization", "to"]);
+    assert_that!(statements[1]).is_equal_to(Statement::Owner(OwnerDefinition {
+        kind: OwnedObjectKind::Table,
+        name: ObjectName {
+            schema: Some("my".into()),
+            name: "accounts".into(),
+        },
+        arguments: None,
+        owner: "app_owner".into(),
+    }));
+}
+
+#[test]
+fn it_keeps_variable_placeholders_for_later_substitution() {
+    let sql = "CREATE SCHEMA $(TenantSchema) AUTHORIZATION $(ReplicationRole);
+               CREATE TABLE $(TenantSchema).settings (tenant_id text NOT NULL DEFAULT '$(TenantId)');
+               CREATE FUNCTION tenant() RETURNS text AS $$ SELECT '$(TenantId)' $$ LANGUAGE SQL;";
+
+    let tokens = lexer::tokenize_stmt(sql);
+    assert_that!(tokens).is_ok();
+    let statements = StatementListParser::new().parse(tokens.unwrap());
+    assert_that!(statements).is_ok();
+    let statements = statements.unwrap();
+    assert_that!(statements).has_length(3);
+
+    assert_that!(statements[0]).is_equal_to(Statement::Schema(SchemaDefinition {
+        name: "$(TenantSchema)".into(),
+        owner: Some("$(ReplicationRole)".into()),
+    }));
+    match statements[1] {
+        Statement::Table(ref table) => {
+            assert_that!(table.name.schema).is_equal_to(Some("$(TenantSchema)".to_owned()));
+            assert_that!(table.columns[0].constraints).contains(ColumnConstraint::Default(Expression::Value(
+                AnyValue::String("$(TenantId)".into(), None),
+            )));
+        }
+        ref unexpected => panic!("Unexpected statement: {:?}", unexpected),
+    }
+    match statements[2] {
+        Statement::Function(ref function) => assert_that!(function.body).is_equal_to("SELECT '$(TenantId)'".to_owned()),
+        ref unexpected => panic!("Unexpected statement: {:?}", unexpected),
+    }
+
+    // An unterminated placeholder is an error
+    assert_that!(lexer::tokenize_stmt("ALTER TABLE contacts OWNER TO $(Role;")).is_err();
+}
+
+#[test]
+fn it_can_parse_extended_simple_types() {
+    let sql = "CREATE TABLE my.events (
+                   payload json,
+                   attributes jsonb,
+                   body bytea,
+                   duration interval,
+                   elapsed interval(3),
+                   period interval year to month,
+                   window_length interval day to second(2),
+                   address inet,
+                   network cidr,
+                   device macaddr,
+                   document xml,
+                   search tsvector,
+                   query tsquery,
+                   location point,
+                   created timestamp(3),
+                   updated timestamp(6) with time zone,
+                   day int
+               );";
+
+    let tokens = lexer::tokenize_stmt(sql);
+    assert_that!(tokens).is_ok();
+    let tokens = tokens.unwrap();
+
+    let statements = StatementListParser::new().parse(tokens);
+    assert_that!(statements).is_ok();
+    let statements = statements.unwrap();
+    assert_that!(statements).has_length(1);
+
+    let table = match statements[0] {
+        Statement::Table(ref table) => table,
+        ref unexpected => panic!("Unexpected statement: {:?}", unexpected),
+    };
+    let types = table
+        .columns
+        .iter()
+        .map(|c| match c.sql_type {
+            SqlType::Simple(ref simple, None) => simple.clone(),
+            ref unexpected => panic!("Unexpected type: {:?}", unexpected),
+        })
+        .collect::<Vec<_>>();
+    assert_that!(types).is_equal_to(vec![
+        SimpleSqlType::Json,
+        SimpleSqlType::Jsonb,
+        SimpleSqlType::Binary,
+        SimpleSqlType::Interval(None, None),
+        SimpleSqlType::Interval(None, Some(3)),
+        SimpleSqlType::Interval(Some(IntervalField::YearToMonth), None),
+        SimpleSqlType::Interval(Some(IntervalField::DayToSecond), Some(2)),
+        SimpleSqlType::Inet,
+        SimpleSqlType::Cidr,
+        SimpleSqlType::MacAddress,
+        SimpleSqlType::Xml,
+        SimpleSqlType::TsVector,
+        SimpleSqlType::TsQuery,
+        SimpleSqlType::Point,
+        SimpleSqlType::DateTime(Some(3)),
+        SimpleSqlType::DateTimeWithTimeZone(Some(6)),
+        SimpleSqlType::Integer,
+    ]);
+}
+
+#[test]
+fn it_can_round_trip_simple_types_through_display() {
+    let types = vec![
+        SimpleSqlType::Binary,
+        SimpleSqlType::DateTime(None),
+        SimpleSqlType::DateTime(Some(3)),
+        SimpleSqlType::DateTimeWithTimeZone(Some(0)),
+        SimpleSqlType::Time(Some(2)),
+        SimpleSqlType::TimeWithTimeZone(Some(4)),
+        SimpleSqlType::Interval(None, None),
+        SimpleSqlType::Interval(None, Some(1)),
+        SimpleSqlType::Interval(Some(IntervalField::Hour), None),
+        SimpleSqlType::Interval(Some(IntervalField::MinuteToSecond), Some(5)),
+        SimpleSqlType::Json,
+        SimpleSqlType::Jsonb,
+        SimpleSqlType::MacAddress,
+        SimpleSqlType::TsVector,
+        SimpleSqlType::Point,
+    ];
+    for ty in types {
+        let sql = ty.to_string();
+        let tokens = lexer::tokenize_body(&sql);
+        assert_that!(tokens).is_ok();
+        let parsed = SqlTypeParser::new().parse(tokens.unwrap());
+        assert_that!(parsed).is_ok().is_equal_to(SqlType::Simple(ty, None));
+    }
+}
+
+#[test]
+fn it_rejects_sizes_that_do_not_fit() {
+    for sql in &[
+        "varchar(-1)",
+        "char(-1)",
+        "numeric(99999999999)",
+        "numeric(10, -2)",
+        "time(-3)",
+    ] {
+        let tokens = lexer::tokenize_body(sql);
+        assert_that!(tokens).is_ok();
+        let parsed = SqlTypeParser::new().parse(tokens.unwrap());
+        assert_that!(parsed).is_err();
+    }
+}
+
+#[test]
+fn it_can_parse_expression_defaults() {
+    let sql = "CREATE TABLE my.items (
+                   id uuid DEFAULT gen_random_uuid(),
+                   code bigint DEFAULT nextval('my.item_code_seq'::regclass),
+                   created timestamp DEFAULT now(),
+                   modified timestamp DEFAULT CURRENT_TIMESTAMP NOT NULL,
+                   quantity int DEFAULT (1 + 2 * 3),
+                   label text DEFAULT 'item-'||'1',
+                   enabled bool DEFAULT true
+               );";
+
+    let tokens = lexer::tokenize_stmt(sql);
+    assert_that!(tokens).is_ok();
+    let tokens = tokens.unwrap();
+
+    let statements = StatementListParser::new().parse(tokens);
+    assert_that!(statements).is_ok();
+    let statements = statements.unwrap();
+    assert_that!(statements).has_length(1);
+
+    let table = match statements[0] {
+        Statement::Table(ref table) => table,
+        ref unexpected => panic!("Unexpected statement: {:?}", unexpected),
+    };
+    let defaults = table
+        .columns
+        .iter()
+        .map(|c| match c.constraints[0] {
+            ColumnConstraint::Default(ref expr) => expr.to_string(),
+            ref unexpected => panic!("Unexpected constraint: {:?}", unexpected),
+        })
+        .collect::<Vec<_>>();
+    assert_that!(defaults).is_equal_to(vec![
+        "gen_random_uuid()".to_owned(),
+        "nextval('my.item_code_seq'::regclass)".to_owned(),
+        "now()".to_owned(),
+        "current_timestamp".to_owned(),
+        "(1 + (2 * 3))".to_owned(),
+        "('item-' || '1')".to_owned(),
+        "true".to_owned(),
+    ]);
+    assert_that!(table.columns[3].constraints).has_length(2);
+}
+
+#[test]
+fn it_can_parse_wide_and_negative_numeric_literals() {
+    let sql = "big bigint DEFAULT 5000000000,
+               negative int DEFAULT -1,
+               spaced int DEFAULT - 2,
+               huge numeric DEFAULT 99999999999999999999,
+               sci double precision DEFAULT 1.5e3,
+               negative_sci double precision DEFAULT -2.5E-2,
+               negative_decimal numeric DEFAULT -0.25";
+
+    let tokens = lexer::tokenize_body(sql);
+    assert_that!(tokens).is_ok();
+    let tokens = tokens.unwrap();
+
+    let arguments = FunctionArgumentListParser::new().parse(tokens);
+    assert_that!(arguments).is_ok();
+    let defaults = arguments
+        .unwrap()
+        .into_iter()
+        .map(|a| a.default.unwrap())
+        .collect::<Vec<_>>();
+    assert_that!(defaults).is_equal_to(vec![
+        AnyValue::Integer(5_000_000_000, None),
+        AnyValue::Integer(-1, None),
+        AnyValue::Integer(-2, None),
+        AnyValue::Decimal("99999999999999999999".parse::<Decimal>().unwrap(), None),
+        AnyValue::Decimal("1500".parse::<Decimal>().unwrap(), None),
+        AnyValue::Decimal("-0.025".parse::<Decimal>().unwrap(), None),
+        AnyValue::Decimal("-0.25".parse::<Decimal>().unwrap(), None),
+    ]);
+}
+
+#[test]
+fn it_distinguishes_subtraction_from_negative_literals() {
+    let tokens = lexer::tokenize_body("(10-1) * -2");
+    assert_that!(tokens).is_ok();
+
+    let expr = ExpressionParser::new().parse(tokens.unwrap());
+    assert_that!(expr).is_ok().is_equal_to(Expression::BinaryOperation(
+        Box::new(Expression::BinaryOperation(
+            Box::new(Expression::Value(AnyValue::Integer(10, None))),
+            BinaryOperator::Subtract,
+            Box::new(Expression::Value(AnyValue::Integer(1, None))),
+        )),
+        BinaryOperator::Multiply,
+        Box::new(Expression::Value(AnyValue::Integer(-2, None))),
+    ));
+}
+
+#[test]
+fn it_can_parse_escaped_quotes_and_fold_unquoted_identifiers() {
+    let tokens = lexer::tokenize_body("concat(MyColumn, 'it''s', \"Say \"\"Hi\"\"\")");
+    assert_that!(tokens).is_ok();
+
+    let expr = ExpressionParser::new().parse(tokens.unwrap());
+    assert_that!(expr).is_ok().is_equal_to(Expression::FunctionCall(
+        ObjectName {
+            schema: None,
+            name: "concat".into(),
+        },
+        vec![
+            Expression::Identifier("mycolumn".into()),
+            Expression::Value(AnyValue::String("it's".into(), None)),
+            Expression::Identifier("Say \"Hi\"".into()),
+        ],
+    ));
+}
+
+#[test]
+fn it_quotes_identifiers_and_literals_only_when_required() {
+    assert_that!(quote_identifier("contacts").into_owned()).is_equal_to("contacts".to_owned());
+    assert_that!(quote_identifier("MyTable").into_owned()).is_equal_to("\"MyTable\"".to_owned());
+    assert_that!(quote_identifier("user").into_owned()).is_equal_to("\"user\"".to_owned());
+    assert_that!(quote_identifier("first name").into_owned()).is_equal_to("\"first name\"".to_owned());
+    assert_that!(quote_identifier("Say \"Hi\"").into_owned()).is_equal_to("\"Say \"\"Hi\"\"\"".to_owned());
+    assert_that!(quote_literal("it's")).is_equal_to("'it''s'".to_owned());
+
+    let name = ObjectName {
+        schema: Some("Sales".into()),
+        name: "order".into(),
+    };
+    assert_that!(name.to_string()).is_equal_to("\"Sales\".\"order\"".to_owned());
+
+    // Identifiers within expressions are quoted too, other than keywords evaluated as functions
+    let expression = Expression::BinaryOperation(
+        Box::new(Expression::Identifier("Age".into())),
+        BinaryOperator::Add,
+        Box::new(Expression::Identifier("order".into())),
+    );
+    assert_that!(expression.to_string()).is_equal_to("(\"Age\" + \"order\")".to_owned());
+    assert_that!(Expression::Identifier("current_timestamp".into()).to_string())
+        .is_equal_to("current_timestamp".to_owned());
+}
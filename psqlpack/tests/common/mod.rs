@@ -55,6 +55,7 @@ macro_rules! generate_simple_package {
             let mut package = Package::new();
             package.push_schema(SchemaDefinition {
                 name: $namespace.to_string(),
+                owner: None,
             });
             let table_name = ObjectName {
                 schema: Some($namespace.to_string()),
@@ -75,6 +76,7 @@ macro_rules! generate_simple_package {
                     },
                 ],
                 constraints: Vec::new(),
+                owner: None,
             });
             package.push_index(IndexDefinition {
                 name: "idx_contacts_name".to_owned(),
@@ -11,14 +11,19 @@ use psqlpack::ast::*;
 use psqlpack::*;
 use slog::{Discard, Drain, Logger};
 use spectral::prelude::*;
+use std::collections::BTreeMap;
 
 macro_rules! publish_package {
     ($db_name:ident, $connection:ident, $package:ident) => {{
+        publish_package!($db_name, $connection, $package, |_publish_profile| {})
+    }};
+    ($db_name:ident, $connection:ident, $package:ident, |$publish_profile:ident| $configure:block) => {{
         // Use the default publish profile
-        let mut publish_profile = PublishProfile::default();
-        publish_profile.generation_options.drop_tables = Toggle::Ignore; // We reuse the same database
-        publish_profile.generation_options.drop_columns = Toggle::Allow; // We allow this in some tests
-        publish_profile.generation_options.drop_indexes = Toggle::Allow; // We allow this in some tests
+        let mut $publish_profile = PublishProfile::default();
+        $publish_profile.generation_options.drop_tables = Toggle::Ignore; // We reuse the same database
+        $publish_profile.generation_options.drop_columns = Toggle::Allow; // We allow this in some tests
+        $publish_profile.generation_options.drop_indexes = Toggle::Allow; // We allow this in some tests
+        $configure
 
         // Create a target package from connection string
         let log = Logger::root(Discard.fuse(), o!());
@@ -32,10 +37,10 @@ macro_rules! publish_package {
             target_package,
             $db_name,
             &capabilities,
-            &publish_profile,
+            &$publish_profile,
         )
         .unwrap();
-        delta.apply(&log, &$connection).unwrap();
+        delta.apply(&log, &$connection, &$publish_profile).unwrap();
 
         // Confirm db exists with data
         let capabilities = Capabilities::from_connection(&log, &$connection).unwrap();
@@ -84,6 +89,42 @@ fn it_can_add_a_new_table_to_an_existing_database() {
     assert_simple_package!(final_package, NAMESPACE);
 }
 
+#[test]
+fn it_can_extract_a_table_with_a_quoted_name() {
+    const DB_NAME: &str = "psqlpack_existing_db";
+    const NAMESPACE: &str = "it_can_extract_a_table_with_a_quoted_name";
+
+    // Preliminary: create a table whose name needs quoting
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "\"Order\"");
+    conn.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", NAMESPACE))
+        .unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {}.\"Order\" (id serial PRIMARY KEY NOT NULL, \"Total\" integer NOT NULL)",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // The columns and constraints are found alongside the table
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    let table = package
+        .tables
+        .iter()
+        .find(|t| t.name.schema == Some(NAMESPACE.into()) && t.name.name == "Order")
+        .unwrap();
+    let columns = table.columns.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+    assert_that!(columns).is_equal_to(vec!["id", "Total"]);
+    assert_that!(table.constraints).has_length(1);
+}
+
 #[test]
 fn it_can_add_a_new_column_to_an_existing_table() {
     const DB_NAME: &str = "psqlpack_existing_db";
@@ -248,3 +289,1159 @@ fn it_can_drop_an_index_on_a_table() {
     let final_package = publish_package!(DB_NAME, connection, package);
     assert_simple_package!(final_package, NAMESPACE);
 }
+
+#[test]
+fn it_can_modify_a_column_default() {
+    const DB_NAME: &str = "psqlpack_existing_db";
+    const NAMESPACE: &str = "it_can_modify_a_column_default";
+
+    // Preliminary: create a database with a table using a different default
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    dump_capabilities!(connection);
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", NAMESPACE))
+        .unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {}.contacts (id serial PRIMARY KEY NOT NULL, name character varying(50) NOT NULL DEFAULT 'unknown')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with an expression default
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.tables[0].columns[1]
+        .constraints
+        .push(ColumnConstraint::Default(Expression::FunctionCall(
+            ObjectName {
+                schema: None,
+                name: "upper".into(),
+            },
+            vec![Expression::Value(AnyValue::String("anonymous".into(), None))],
+        )));
+    let final_package = publish_package!(DB_NAME, connection, package);
+
+    // Postgres will have normalized the expression
+    let table = final_package
+        .tables
+        .iter()
+        .find(|s| s.name.to_string().eq(&format!("{}.contacts", NAMESPACE)))
+        .unwrap();
+    let col_name = &table.columns[1];
+    assert_that!(col_name.constraints).contains(ColumnConstraint::Default(Expression::FunctionCall(
+        ObjectName {
+            schema: None,
+            name: "upper".into(),
+        },
+        vec![Expression::Value(AnyValue::String(
+            "anonymous".into(),
+            Some(SqlType::Simple(SimpleSqlType::Text, None)),
+        ))],
+    )));
+}
+
+#[test]
+fn it_can_rename_a_table_using_the_refactor_log() {
+    const DB_NAME: &str = "psqlpack_refactor_db";
+    const NAMESPACE: &str = "it_can_rename_a_table_using_the_refactor_log";
+
+    // Preliminary: start from a fresh database so no refactorings have been recorded
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = connection.connect_host().unwrap();
+    drop_db!(conn, connection.database());
+    conn.finish().unwrap();
+    let conn = create_db!(connection);
+    conn.batch_execute(&format!("CREATE SCHEMA {}", NAMESPACE)).unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {}.people (id serial PRIMARY KEY NOT NULL, name character varying(50) NOT NULL);
+         INSERT INTO {}.people (name) VALUES ('Jane')",
+        NAMESPACE, NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with the table renamed. Tables are ignored rather than dropped so data would be lost otherwise.
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.push_refactor(RefactorDefinition {
+        id: "rename-people".into(),
+        operation: RefactorOperation::RenameTable {
+            table: ObjectName {
+                schema: Some(NAMESPACE.into()),
+                name: "people".into(),
+            },
+            new_name: "contacts".into(),
+        },
+    });
+    let final_package = publish_package!(DB_NAME, connection, package);
+    assert_simple_package!(final_package, NAMESPACE);
+    assert_that!(final_package.refactors).has_length(1);
+
+    // The data moved with the table
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(&format!("SELECT name FROM {}.contacts", NAMESPACE), &[])
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(1);
+    let name: String = rows.get(0).get(0);
+    assert_that!(name).is_equal_to("Jane".to_owned());
+    conn.finish().unwrap();
+
+    // Publishing again doesn't attempt to reapply the rename
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.push_refactor(final_package.refactors[0].clone());
+    let final_package = publish_package!(DB_NAME, connection, package);
+    assert_that!(final_package.refactors).has_length(1);
+}
+
+#[test]
+fn it_keeps_the_data_of_an_accepted_rename() {
+    const DB_NAME: &str = "psqlpack_existing_db";
+    const NAMESPACE: &str = "it_keeps_the_data_of_an_accepted_rename";
+
+    // Preliminary: create a table where the name column is yet to be renamed
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", NAMESPACE))
+        .unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {0}.contacts (id serial PRIMARY KEY NOT NULL, full_name character varying(50) NOT NULL);
+         INSERT INTO {0}.contacts (full_name) VALUES ('Jane')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with the rename accepted. Columns are allowed to be dropped so data would be lost otherwise.
+    let package = generate_simple_package!(NAMESPACE);
+    let final_package = publish_package!(DB_NAME, connection, package, |publish_profile| {
+        publish_profile.generation_options.accept_renames = Some(RenameConfidence::High);
+    });
+    assert_simple_package!(final_package, NAMESPACE);
+
+    // The data moved with the column
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(&format!("SELECT name FROM {}.contacts", NAMESPACE), &[])
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(1);
+    let name: String = rows.get(0).get(0);
+    assert_that!(name).is_equal_to("Jane".to_owned());
+    conn.finish().unwrap();
+}
+
+#[test]
+fn it_can_remove_and_reorder_enum_values_in_use() {
+    const DB_NAME: &str = "psqlpack_enum_db";
+    const NAMESPACE: &str = "it_can_remove_and_reorder_enum_values_in_use";
+
+    // Preliminary: start from a fresh database since types are shared across tables
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = connection.connect_host().unwrap();
+    drop_db!(conn, connection.database());
+    conn.finish().unwrap();
+    let conn = create_db!(connection);
+    conn.batch_execute(&format!(
+        "CREATE SCHEMA {0};
+         CREATE TYPE {0}.colors AS ENUM ('red', 'green', 'blue');
+         CREATE TABLE {0}.contacts (
+           id serial PRIMARY KEY NOT NULL,
+           name character varying(50) NOT NULL,
+           color {0}.colors NOT NULL DEFAULT 'green'
+         );
+         INSERT INTO {0}.contacts (name) VALUES ('Jane')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with red removed and the remaining values reordered
+    let colors = ObjectName {
+        schema: Some(NAMESPACE.into()),
+        name: "colors".into(),
+    };
+    let generate_package = |values: Vec<&str>| {
+        let mut package = generate_simple_package!(NAMESPACE);
+        package.push_type(TypeDefinition {
+            name: colors.clone(),
+            kind: TypeDefinitionKind::Enum(values.into_iter().map(String::from).collect()),
+            owner: None,
+        });
+        package.tables[0].columns.push(ColumnDefinition {
+            name: "color".into(),
+            sql_type: SqlType::Custom(colors.clone(), None, None),
+            constraints: vec![
+                ColumnConstraint::NotNull,
+                ColumnConstraint::Default(Expression::Value(AnyValue::String("green".into(), None))),
+            ],
+        });
+        package
+    };
+    let package = generate_package(vec!["blue", "green"]);
+    let final_package = publish_package!(DB_NAME, connection, package, |publish_profile| {
+        publish_profile.generation_options.drop_enum_values = Toggle::Allow;
+    });
+    let ty = final_package.types.iter().find(|t| t.name == colors).unwrap();
+    assert_that!(ty.kind).is_equal_to(TypeDefinitionKind::Enum(vec!["blue".into(), "green".into()]));
+
+    // The data and default survived the swap
+    let conn = connection.connect_database().unwrap();
+    conn.batch_execute(&format!("INSERT INTO {}.contacts (name) VALUES ('John')", NAMESPACE))
+        .unwrap();
+    let rows = conn
+        .query(&format!("SELECT color::text FROM {}.contacts", NAMESPACE), &[])
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(2);
+    for row in &rows {
+        let color: String = row.get(0);
+        assert_that!(color).is_equal_to("green".to_owned());
+    }
+    conn.finish().unwrap();
+
+    // Removing a value that is still in use fails before the type is modified
+    let package = generate_package(vec!["blue"]);
+    let mut publish_profile = PublishProfile::default();
+    publish_profile.generation_options.drop_enum_values = Toggle::Allow;
+    publish_profile.generation_options.drop_columns = Toggle::Allow;
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    assert_that!(delta.apply(&log, &connection, &publish_profile)).is_err();
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    let ty = final_package.types.iter().find(|t| t.name == colors).unwrap();
+    assert_that!(ty.kind).is_equal_to(TypeDefinitionKind::Enum(vec!["blue".into(), "green".into()]));
+
+    // A view using the type would stop it from being replaced, so is reported before anything is generated
+    let conn = connection.connect_database().unwrap();
+    conn.batch_execute(&format!(
+        "CREATE VIEW {0}.contact_colors AS SELECT color FROM {0}.contacts",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+    let package = generate_package(vec!["green", "blue"]);
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let result = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile);
+    match result {
+        Err(PsqlpackError(PsqlpackErrorKind::PublishUnsafeOperationError(message), _)) => {
+            assert_that!(message).contains(&format!("column color of view {}.contact_colors", NAMESPACE)[..])
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Expected the view to prevent the type being replaced"),
+    }
+}
+
+#[test]
+fn it_can_rename_an_enum_value_using_the_refactor_log() {
+    const DB_NAME: &str = "psqlpack_enum_rename_db";
+    const NAMESPACE: &str = "it_can_rename_an_enum_value_using_the_refactor_log";
+
+    // Preliminary: start from a fresh database so no refactorings have been recorded
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = connection.connect_host().unwrap();
+    drop_db!(conn, connection.database());
+    conn.finish().unwrap();
+    let conn = create_db!(connection);
+    conn.batch_execute(&format!(
+        "CREATE SCHEMA {0};
+         CREATE TYPE {0}.colors AS ENUM ('red', 'green');
+         CREATE TABLE {0}.contacts (
+           id serial PRIMARY KEY NOT NULL,
+           name character varying(50) NOT NULL,
+           color {0}.colors NOT NULL
+         );
+         INSERT INTO {0}.contacts (name, color) VALUES ('Jane', 'red')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with red renamed. Enum values can't be dropped by default, so this fails unless the value is renamed.
+    let colors = ObjectName {
+        schema: Some(NAMESPACE.into()),
+        name: "colors".into(),
+    };
+    let generate_package = || {
+        let mut package = generate_simple_package!(NAMESPACE);
+        package.push_type(TypeDefinition {
+            name: colors.clone(),
+            kind: TypeDefinitionKind::Enum(vec!["crimson".into(), "green".into()]),
+            owner: None,
+        });
+        package.tables[0].columns.push(ColumnDefinition {
+            name: "color".into(),
+            sql_type: SqlType::Custom(colors.clone(), None, None),
+            constraints: vec![ColumnConstraint::NotNull],
+        });
+        package.push_refactor(RefactorDefinition {
+            id: "rename-red".into(),
+            operation: RefactorOperation::RenameEnumValue {
+                type_name: colors.clone(),
+                value: "red".into(),
+                new_name: "crimson".into(),
+            },
+        });
+        package
+    };
+    let package = generate_package();
+    let final_package = publish_package!(DB_NAME, connection, package);
+    let ty = final_package.types.iter().find(|t| t.name == colors).unwrap();
+    assert_that!(ty.kind).is_equal_to(TypeDefinitionKind::Enum(vec!["crimson".into(), "green".into()]));
+    assert_that!(final_package.refactors).has_length(1);
+
+    // The rows using the value were renamed along with it
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(&format!("SELECT color::text FROM {}.contacts", NAMESPACE), &[])
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(1);
+    let color: String = rows.get(0).get(0);
+    assert_that!(color).is_equal_to("crimson".to_owned());
+    conn.finish().unwrap();
+
+    // Publishing again doesn't attempt to reapply the rename
+    let package = generate_package();
+    let final_package = publish_package!(DB_NAME, connection, package);
+    assert_that!(final_package.refactors).has_length(1);
+}
+
+#[test]
+fn it_rolls_back_a_failed_publish() {
+    const DB_NAME: &str = "psqlpack_transaction_db";
+    const NAMESPACE: &str = "it_rolls_back_a_failed_publish";
+
+    // Preliminary: create a database with no tables
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.finish().unwrap();
+
+    // Publish a new table alongside a post deployment script that fails
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.push_script(ScriptDefinition {
+        name: "fail".into(),
+        kind: ScriptKind::PostDeployment,
+        order: 0,
+        contents: "SELECT 1/0".into(),
+        run: ScriptRunPolicy::Always,
+    });
+    let mut publish_profile = PublishProfile::default();
+    publish_profile.generation_options.force_concurrent_indexes = false;
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    let result = delta.apply(&log, &connection, &publish_profile);
+    assert_that!(result).is_err();
+    assert_that!(result.unwrap_err().to_string()).starts_with("Couldn't publish database as phase 1 of 1 failed.");
+
+    // Nothing was committed so the table doesn't exist
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_that!(final_package
+        .tables
+        .iter()
+        .any(|t| t.name.schema == Some(NAMESPACE.into())))
+    .is_false();
+
+    // Concurrent indexes can't be created within a transaction, so earlier phases are committed
+    publish_profile.generation_options.force_concurrent_indexes = true;
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    let result = delta.apply(&log, &connection, &publish_profile);
+    assert_that!(result).is_err();
+    assert_that!(result.unwrap_err().to_string()).starts_with("Couldn't publish database as phase 3 of 3 failed.");
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_simple_package!(final_package, NAMESPACE);
+}
+
+#[test]
+fn it_can_verify_a_publish_without_keeping_changes() {
+    const DB_NAME: &str = "psqlpack_verify_db";
+    const NAMESPACE: &str = "it_can_verify_a_publish_without_keeping_changes";
+
+    // Preliminary: create a database with no tables
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.finish().unwrap();
+
+    // Verifying a valid package succeeds, including concurrent indexes, but keeps nothing
+    let mut package = generate_simple_package!(NAMESPACE);
+    let mut publish_profile = PublishProfile::default();
+    publish_profile.generation_options.force_concurrent_indexes = true;
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    delta.verify(&log, &connection, &publish_profile).unwrap();
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_that!(final_package
+        .tables
+        .iter()
+        .any(|t| t.name.schema == Some(NAMESPACE.into())))
+    .is_false();
+
+    // A failing change is reported alongside the instruction that failed
+    package.push_script(ScriptDefinition {
+        name: "fail".into(),
+        kind: ScriptKind::PostDeployment,
+        order: 0,
+        contents: "SELECT 1/0".into(),
+        run: ScriptRunPolicy::Always,
+    });
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    let result = delta.verify(&log, &connection, &publish_profile);
+    assert_that!(result).is_err();
+    assert_that!(result.unwrap_err().to_string()).is_equal_to(
+        "Couldn't verify publish as a change failed: Run script: fail. All changes were rolled back.".to_owned(),
+    );
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_that!(final_package
+        .tables
+        .iter()
+        .any(|t| t.name.schema == Some(NAMESPACE.into())))
+    .is_false();
+}
+
+#[test]
+fn it_can_verify_a_publish_using_an_added_enum_value() {
+    const DB_NAME: &str = "psqlpack_verify_enum_db";
+    const NAMESPACE: &str = "it_can_verify_a_publish_using_an_added_enum_value";
+
+    // Preliminary: start from a fresh database since types are shared across tables
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = connection.connect_host().unwrap();
+    drop_db!(conn, connection.database());
+    conn.finish().unwrap();
+    let conn = create_db!(connection);
+    conn.batch_execute(&format!(
+        "CREATE SCHEMA {0};
+         CREATE TYPE {0}.colors AS ENUM ('red', 'green')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // The new value can't be used until it is committed, so the script using it is unverifiable rather than failing
+    let colors = ObjectName {
+        schema: Some(NAMESPACE.into()),
+        name: "colors".into(),
+    };
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.push_type(TypeDefinition {
+        name: colors.clone(),
+        kind: TypeDefinitionKind::Enum(vec!["red".into(), "green".into(), "blue".into()]),
+        owner: None,
+    });
+    package.push_script(ScriptDefinition {
+        name: "blue".into(),
+        kind: ScriptKind::PostDeployment,
+        order: 0,
+        contents: format!("SELECT 'blue'::{}.colors", NAMESPACE),
+        run: ScriptRunPolicy::Always,
+    });
+    let publish_profile = PublishProfile::default();
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    delta.verify(&log, &connection, &publish_profile).unwrap();
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    let ty = final_package.types.iter().find(|t| t.name == colors).unwrap();
+    assert_that!(ty.kind).is_equal_to(TypeDefinitionKind::Enum(vec!["red".into(), "green".into()]));
+
+    // Publishing succeeds, as verification suggested
+    let final_package = publish_package!(DB_NAME, connection, package);
+    let ty = final_package.types.iter().find(|t| t.name == colors).unwrap();
+    assert_that!(ty.kind).is_equal_to(TypeDefinitionKind::Enum(vec![
+        "red".into(),
+        "green".into(),
+        "blue".into(),
+    ]));
+}
+
+#[test]
+fn it_records_each_publish_within_the_deployment_history() {
+    const DB_NAME: &str = "psqlpack_history_db";
+    const NAMESPACE: &str = "it_records_each_publish_within_the_deployment_history";
+
+    // Preliminary: create a database without any history
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute("DROP SCHEMA IF EXISTS psqlpack CASCADE").unwrap();
+    conn.finish().unwrap();
+    let log = Logger::root(Discard.fuse(), o!());
+    assert_that!(Deployment::history(&log, &connection).unwrap()).is_empty();
+
+    // Verifying a package leaves no history behind
+    let package = generate_simple_package!(NAMESPACE);
+    let publish_profile = PublishProfile::default();
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    delta.verify(&log, &connection, &publish_profile).unwrap();
+    assert_that!(Deployment::history(&log, &connection).unwrap()).is_empty();
+
+    // Publishing records the package that was applied
+    publish_package!(DB_NAME, connection, package);
+    let history = Deployment::history(&log, &connection).unwrap();
+    assert_that!(history).has_length(1);
+    let deployment = &history[0];
+    assert_that!(deployment.package_hash).is_equal_to(package.hash());
+    assert_that!(deployment.psqlpack_version).is_equal_to(package.meta.version().to_string());
+    assert_that!(deployment.executed_by).is_equal_to("postgres".to_owned());
+    assert_that!(deployment.started_at <= deployment.finished_at).is_true();
+    assert_that!(deployment.applied_sql.contains("CREATE TABLE")).is_true();
+
+    // The deployment history is not part of the extracted package
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_that!(final_package.schemas.iter().any(|s| s.name == "psqlpack")).is_false();
+
+    // Publishing again appends to the history
+    publish_package!(DB_NAME, connection, package);
+    let history = Deployment::history(&log, &connection).unwrap();
+    assert_that!(history).has_length(2);
+    assert_that!(history[1].package_hash).is_equal_to(package.hash());
+}
+
+#[test]
+fn it_detects_drift_since_the_last_publish() {
+    const DB_NAME: &str = "psqlpack_drift_db";
+    const NAMESPACE: &str = "it_detects_drift_since_the_last_publish";
+
+    // Preliminary: create a database that has never been published to
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute("DROP SCHEMA IF EXISTS psqlpack CASCADE").unwrap();
+    conn.finish().unwrap();
+    let log = Logger::root(Discard.fuse(), o!());
+    let result = Drift::detect(&log, &connection);
+    assert_that!(result).is_err();
+    assert_that!(result.unwrap_err().to_string())
+        .is_equal_to("No deployment has been recorded within the target database".to_owned());
+
+    // A database that matches the published package has no drift
+    let package = generate_simple_package!(NAMESPACE);
+    publish_package!(DB_NAME, connection, package);
+    let drift = Drift::detect(&log, &connection).unwrap();
+    assert_that!(drift.package_hash).is_equal_to(package.hash());
+    assert_that!(drift.changes).is_empty();
+
+    // Changes made by hand are reported
+    let conn = connection.connect_database().unwrap();
+    conn.batch_execute(&format!(
+        "CREATE INDEX idx_contacts_hotfix ON {ns}.contacts (id, name);
+         DROP INDEX {ns}.idx_contacts_name;
+         ALTER TABLE {ns}.contacts ALTER COLUMN name TYPE varchar(100);",
+        ns = NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+    let drift = Drift::detect(&log, &connection).unwrap();
+    assert_that!(drift.changes).is_equal_to(vec![
+        DriftEntry {
+            kind: DriftKind::Added,
+            object_type: ObjectType::Index,
+            name: format!("{}.idx_contacts_hotfix", NAMESPACE),
+            deployed: None,
+            live: None,
+        },
+        DriftEntry {
+            kind: DriftKind::Modified,
+            object_type: ObjectType::Column,
+            name: format!("{}.contacts.name", NAMESPACE),
+            deployed: Some("varchar(50)".into()),
+            live: Some("varchar(100)".into()),
+        },
+        DriftEntry {
+            kind: DriftKind::Removed,
+            object_type: ObjectType::Index,
+            name: format!("{}.idx_contacts_name", NAMESPACE),
+            deployed: None,
+            live: None,
+        },
+    ]);
+    assert_that!(drift.changes[1].to_string()).is_equal_to(format!(
+        "Modified column {}.contacts.name (published: varchar(50), now: varchar(100))",
+        NAMESPACE
+    ));
+}
+
+#[test]
+fn it_only_runs_scripts_again_when_their_run_policy_allows() {
+    const DB_NAME: &str = "psqlpack_scripts_db";
+    const NAMESPACE: &str = "it_only_runs_scripts_again_when_their_run_policy_allows";
+
+    // Preliminary: create a database where no scripts have run
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute("DROP TABLE IF EXISTS psqlpack.script_log").unwrap();
+    conn.finish().unwrap();
+
+    // Each script inserts a contact named after its run policy
+    let script = |name: &str, run| ScriptDefinition {
+        name: format!("{}.sql", name),
+        kind: ScriptKind::PostDeployment,
+        order: 0,
+        contents: format!("INSERT INTO {}.contacts (name) VALUES ('{}')", NAMESPACE, name),
+        run,
+    };
+    let count = |name: &str| -> i64 {
+        let conn = connection.connect_database().unwrap();
+        let rows = conn
+            .query(
+                &format!("SELECT COUNT(*) FROM {}.contacts WHERE name = $1", NAMESPACE),
+                &[&name],
+            )
+            .unwrap();
+        let count = rows.get(0).get(0);
+        conn.finish().unwrap();
+        count
+    };
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.push_script(script("always", ScriptRunPolicy::Always));
+    package.push_script(script("once", ScriptRunPolicy::Once));
+    package.push_script(script("onChange", ScriptRunPolicy::OnChange));
+
+    // Publishing twice only repeats the script that always runs
+    publish_package!(DB_NAME, connection, package);
+    publish_package!(DB_NAME, connection, package);
+    assert_that!(count("always")).is_equal_to(2);
+    assert_that!(count("once")).is_equal_to(1);
+    assert_that!(count("onChange")).is_equal_to(1);
+
+    // Changing a script runs it again if it runs on change
+    let mut package = generate_simple_package!(NAMESPACE);
+    let mut changed = script("once", ScriptRunPolicy::Once);
+    changed.contents.push_str("; SELECT 1");
+    package.push_script(changed);
+    let mut changed = script("onChange", ScriptRunPolicy::OnChange);
+    changed.contents.push_str("; SELECT 1");
+    package.push_script(changed);
+    publish_package!(DB_NAME, connection, package);
+    assert_that!(count("once")).is_equal_to(1);
+    assert_that!(count("onChange")).is_equal_to(2);
+}
+
+#[test]
+fn it_substitutes_variables_when_publishing() {
+    const DB_NAME: &str = "psqlpack_variables_db";
+    const NAMESPACE: &str = "it_substitutes_variables_when_publishing";
+
+    // Preliminary: create a database without the settings table
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "acme_settings");
+    conn.finish().unwrap();
+
+    // The table and the script seeding it both use variables
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.push_table(TableDefinition {
+        name: ObjectName {
+            schema: Some(NAMESPACE.to_owned()),
+            name: "$(SettingsTable)".to_owned(),
+        },
+        columns: vec![ColumnDefinition {
+            name: "region".to_owned(),
+            sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(10), None),
+            constraints: vec![ColumnConstraint::NotNull],
+        }],
+        constraints: vec![],
+        owner: None,
+    });
+    package.push_script(ScriptDefinition {
+        name: "seed.sql".to_owned(),
+        kind: ScriptKind::PostDeployment,
+        order: 0,
+        contents: format!(
+            "INSERT INTO {}.$(SettingsTable) (region) VALUES ('$(Region)')",
+            NAMESPACE
+        ),
+        run: ScriptRunPolicy::Always,
+    });
+    package.push_variable(Variable {
+        name: "SettingsTable".to_owned(),
+        default: None,
+    });
+    package.push_variable(Variable {
+        name: "Region".to_owned(),
+        default: Some("eu".to_owned()),
+    });
+
+    // The region falls back to its default as no value is given
+    let mut values = BTreeMap::new();
+    values.insert("SettingsTable".to_owned(), "acme_settings".to_owned());
+    let package = package.resolve_variables(&values).unwrap();
+    publish_package!(DB_NAME, connection, package);
+
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(&format!("SELECT region FROM {}.acme_settings", NAMESPACE), &[])
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(1);
+    let region: String = rows.get(0).get(0);
+    assert_that!(region).is_equal_to("eu".to_owned());
+    conn.finish().unwrap();
+}
+
+#[test]
+fn it_retries_a_phase_that_times_out_waiting_for_a_lock() {
+    const DB_NAME: &str = "psqlpack_lock_db";
+    const NAMESPACE: &str = "it_retries_a_phase_that_times_out_waiting_for_a_lock";
+
+    // Preliminary: create a database with a partial table
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", NAMESPACE))
+        .unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {}.contacts (id serial PRIMARY KEY NOT NULL)",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Hold a lock on the table from another session for a short time
+    let hold_lock = |duration: u64| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+                .build()
+                .unwrap();
+            let conn = connection.connect_database().unwrap();
+            let transaction = conn.transaction().unwrap();
+            transaction
+                .batch_execute(&format!("LOCK TABLE {}.contacts IN ACCESS SHARE MODE", NAMESPACE))
+                .unwrap();
+            sender.send(()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(duration));
+            transaction.commit().unwrap();
+        });
+        receiver.recv().unwrap();
+        handle
+    };
+
+    // Without retries the publish fails rather than waiting behind the lock
+    let package = generate_simple_package!(NAMESPACE);
+    let mut publish_profile = PublishProfile::default();
+    publish_profile.generation_options.lock_timeout = Some(50);
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let handle = hold_lock(1000);
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    let result = delta.apply(&log, &connection, &publish_profile);
+    handle.join().unwrap();
+    assert_that!(result).is_err();
+    assert_that!(result.unwrap_err().display_chain().to_string()).contains("lock timeout");
+
+    // Retrying waits for the lock to be released
+    publish_profile.generation_options.lock_timeout_retries = 5;
+    publish_profile.generation_options.lock_timeout_retry_delay = 50;
+    let handle = hold_lock(300);
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    assert_that!(delta.apply(&log, &connection, &publish_profile)).is_ok();
+    handle.join().unwrap();
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_simple_package!(final_package, NAMESPACE);
+}
+
+#[test]
+fn it_retries_a_concurrent_index_build_that_times_out_waiting_for_a_lock() {
+    const DB_NAME: &str = "psqlpack_index_lock_db";
+    const NAMESPACE: &str = "it_retries_a_concurrent_index_build";
+
+    // Preliminary: create the table without its index
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", NAMESPACE))
+        .unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {}.contacts (id serial PRIMARY KEY NOT NULL, name character varying(50) NOT NULL)",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // A writer holding its lock makes the concurrent build time out after the index has been created as invalid
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn(move || {
+        let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+            .build()
+            .unwrap();
+        let conn = connection.connect_database().unwrap();
+        let transaction = conn.transaction().unwrap();
+        transaction
+            .batch_execute(&format!("LOCK TABLE {}.contacts IN ROW EXCLUSIVE MODE", NAMESPACE))
+            .unwrap();
+        sender.send(()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        transaction.commit().unwrap();
+    });
+    receiver.recv().unwrap();
+
+    // The invalid index is dropped before retrying, so the build succeeds once the lock is released
+    let package = generate_simple_package!(NAMESPACE);
+    let final_package = publish_package!(DB_NAME, connection, package, |publish_profile| {
+        publish_profile.generation_options.force_concurrent_indexes = true;
+        publish_profile.generation_options.lock_timeout = Some(50);
+        publish_profile.generation_options.lock_timeout_retries = 5;
+        publish_profile.generation_options.lock_timeout_retry_delay = 50;
+    });
+    handle.join().unwrap();
+    assert_simple_package!(final_package, NAMESPACE);
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(
+            "SELECT indisvalid FROM pg_index WHERE indexrelid = to_regclass($1)",
+            &[&format!("{}.idx_contacts_name", NAMESPACE)],
+        )
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(1);
+    let valid: bool = rows.get(0).get(0);
+    assert_that!(valid).is_true();
+    conn.finish().unwrap();
+}
+
+#[test]
+fn it_can_drop_a_table_referenced_by_another_table() {
+    const DB_NAME: &str = "psqlpack_drop_db";
+    const NAMESPACE: &str = "it_can_drop_a_table_referenced_by_another_table";
+
+    // Preliminary: start from a fresh database since tables are dropped
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = connection.connect_host().unwrap();
+    drop_db!(conn, connection.database());
+    conn.finish().unwrap();
+    let conn = create_db!(connection);
+    conn.batch_execute(&format!(
+        "CREATE SCHEMA {0};
+         CREATE TABLE {0}.companies (id serial PRIMARY KEY NOT NULL);
+         CREATE TABLE {0}.orders (
+           id serial PRIMARY KEY NOT NULL,
+           company_id integer NOT NULL REFERENCES {0}.companies (id)
+         );
+         CREATE TABLE {0}.contacts (
+           id serial PRIMARY KEY NOT NULL,
+           name character varying(50) NOT NULL,
+           company_id integer NOT NULL REFERENCES {0}.companies (id)
+         )",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with only the contacts table remaining
+    let package = generate_simple_package!(NAMESPACE);
+    let final_package = publish_package!(DB_NAME, connection, package, |publish_profile| {
+        publish_profile.generation_options.drop_tables = Toggle::Allow;
+    });
+    assert_simple_package!(final_package, NAMESPACE);
+    assert_that!(final_package.tables).has_length(1);
+}
+
+#[test]
+fn it_checks_for_data_loss_before_dropping_or_narrowing() {
+    const DB_NAME: &str = "psqlpack_data_loss_db";
+    const NAMESPACE: &str = "it_checks_for_data_loss_before_dropping_or_narrowing";
+
+    // Preliminary: start from a fresh database since tables are dropped
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = connection.connect_host().unwrap();
+    drop_db!(conn, connection.database());
+    conn.finish().unwrap();
+    let conn = create_db!(connection);
+    conn.batch_execute(&format!(
+        "CREATE SCHEMA {0};
+         CREATE TABLE {0}.archive (id serial PRIMARY KEY NOT NULL);
+         CREATE TABLE {0}.contacts (
+           id serial PRIMARY KEY NOT NULL,
+           name character varying(100) NOT NULL,
+           nickname text NULL
+         );
+         INSERT INTO {0}.contacts (name, nickname) VALUES (repeat('a', 80), 'Al')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Dropping the nickname and narrowing the name would lose data, so the publish is stopped
+    let package = generate_simple_package!(NAMESPACE);
+    let mut publish_profile = PublishProfile::default();
+    publish_profile.generation_options.drop_tables = Toggle::Allow;
+    publish_profile.generation_options.drop_columns = Toggle::Allow;
+    publish_profile.generation_options.narrow_column_types = Toggle::Allow;
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    let result = delta.apply(&log, &connection, &publish_profile);
+    assert_that!(result).is_err();
+    assert_that!(result.unwrap_err().display_chain().to_string()).contains("would lose data");
+
+    // Once the names are shortened, only dropping the nickname would lose data which is reported as a warning
+    let conn = connection.connect_database().unwrap();
+    conn.batch_execute(&format!("UPDATE {}.contacts SET name = left(name, 50)", NAMESPACE))
+        .unwrap();
+    conn.finish().unwrap();
+    let final_package = publish_package!(DB_NAME, connection, package, |publish_profile| {
+        publish_profile.generation_options.drop_tables = Toggle::Allow;
+        publish_profile.generation_options.narrow_column_types = Toggle::Allow;
+        publish_profile.generation_options.block_on_possible_data_loss = false;
+    });
+    assert_simple_package!(final_package, NAMESPACE);
+    assert_that!(final_package.tables).has_length(1);
+}
+
+#[test]
+fn it_can_add_a_not_null_column_to_a_populated_table() {
+    const DB_NAME: &str = "psqlpack_existing_db";
+    const NAMESPACE: &str = "it_can_add_a_not_null_column_to_a_populated_table";
+
+    // Preliminary: create a database with a populated table
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", NAMESPACE))
+        .unwrap();
+    conn.batch_execute(&format!(
+        "CREATE TABLE {0}.contacts (id serial PRIMARY KEY NOT NULL, name character varying(50) NOT NULL);
+         INSERT INTO {0}.contacts (name) VALUES ('Jane')",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish a new NOT NULL column without a default, populated from the name
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.tables[0].columns.push(ColumnDefinition {
+        name: "display_name".into(),
+        sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(50), None),
+        constraints: vec![ColumnConstraint::NotNull],
+    });
+    package.push_conversion(ColumnConversion {
+        table: ObjectName {
+            schema: Some(NAMESPACE.into()),
+            name: "contacts".into(),
+        },
+        column: "display_name".into(),
+        using: None,
+        backfill: Some("upper(name)".into()),
+    });
+    let final_package = publish_package!(DB_NAME, connection, package);
+    let table = final_package
+        .tables
+        .iter()
+        .find(|t| t.name.schema == Some(NAMESPACE.into()))
+        .unwrap();
+    let column = table.columns.iter().find(|c| c.name == "display_name").unwrap();
+    assert_that!(column.constraints).contains(ColumnConstraint::NotNull);
+
+    // The existing row was populated and the temporary constraint removed
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(&format!("SELECT display_name FROM {}.contacts", NAMESPACE), &[])
+        .unwrap();
+    let display_name: String = rows.get(0).get(0);
+    assert_that!(display_name).is_equal_to("JANE".to_owned());
+    let rows = conn
+        .query(
+            "SELECT 1 FROM pg_constraint WHERE conname = 'contacts_display_name_not_null'",
+            &[],
+        )
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(0);
+    conn.finish().unwrap();
+}
+
+#[test]
+fn it_can_rollback_a_publish() {
+    const DB_NAME: &str = "psqlpack_existing_db";
+    const NAMESPACE: &str = "it_can_rollback_a_publish";
+
+    // Preliminary: publish the simple package
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    conn.finish().unwrap();
+    let package = generate_simple_package!(NAMESPACE);
+    let final_package = publish_package!(DB_NAME, connection, package);
+    assert_simple_package!(final_package, NAMESPACE);
+
+    // Widen the name and add a new column with an index
+    let mut package = generate_simple_package!(NAMESPACE);
+    package.tables[0].columns[1].sql_type = SqlType::Simple(SimpleSqlType::VariableLengthString(100), None);
+    package.tables[0].columns.push(ColumnDefinition {
+        name: "nickname".into(),
+        sql_type: SqlType::Simple(SimpleSqlType::VariableLengthString(50), None),
+        constraints: vec![ColumnConstraint::Null],
+    });
+    package.push_index(IndexDefinition {
+        name: "idx_contacts_nickname".to_owned(),
+        table: package.tables[0].name.clone(),
+        columns: vec![IndexColumn {
+            name: "nickname".to_owned(),
+            order: None,
+            null_position: None,
+        }],
+        unique: false,
+        index_type: None,
+        storage_parameters: None,
+    });
+    let mut publish_profile = PublishProfile::default();
+    publish_profile.generation_options.drop_tables = Toggle::Ignore; // We reuse the same database
+    publish_profile.generation_options.drop_indexes = Toggle::Ignore;
+    let log = Logger::root(Discard.fuse(), o!());
+    let capabilities = Capabilities::from_connection(&log, &connection).unwrap();
+    let target_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let delta = Delta::generate(&log, &package, target_package, DB_NAME, &capabilities, &publish_profile).unwrap();
+    let before_package = Package::from_connection(&log, &connection, &capabilities).unwrap();
+    let rollback = delta.rollback(before_package.as_ref());
+    delta.apply(&log, &connection, &publish_profile).unwrap();
+
+    // Applying the rollback returns us to where we started
+    rollback.apply(&log, &connection, &publish_profile).unwrap();
+    let final_package = Package::from_connection(&log, &connection, &capabilities)
+        .unwrap()
+        .unwrap();
+    assert_simple_package!(final_package, NAMESPACE);
+}
+
+#[test]
+fn it_can_add_a_foreign_key_online() {
+    const DB_NAME: &str = "psqlpack_existing_db";
+    const NAMESPACE: &str = "it_can_add_a_foreign_key_online";
+
+    // Preliminary: create a database with populated tables
+    let connection = ConnectionBuilder::new(DB_NAME, "localhost", "postgres")
+        .build()
+        .unwrap();
+    let conn = create_db!(connection);
+    drop_table!(conn, NAMESPACE, "contacts");
+    drop_table!(conn, NAMESPACE, "companies");
+    conn.batch_execute(&format!(
+        "CREATE SCHEMA IF NOT EXISTS {0};
+         CREATE TABLE {0}.companies (id serial PRIMARY KEY NOT NULL);
+         CREATE TABLE {0}.contacts (
+           id serial PRIMARY KEY NOT NULL,
+           name character varying(50) NOT NULL,
+           company_id integer NOT NULL
+         );
+         INSERT INTO {0}.companies DEFAULT VALUES;
+         INSERT INTO {0}.contacts (name, company_id) VALUES ('Jane', 1)",
+        NAMESPACE
+    ))
+    .unwrap();
+    conn.finish().unwrap();
+
+    // Publish with a foreign key between the two
+    let mut package = generate_simple_package!(NAMESPACE);
+    let companies = ObjectName {
+        schema: Some(NAMESPACE.into()),
+        name: "companies".into(),
+    };
+    package.tables[0].columns.push(ColumnDefinition {
+        name: "company_id".into(),
+        sql_type: SqlType::Simple(SimpleSqlType::Integer, None),
+        constraints: vec![ColumnConstraint::NotNull],
+    });
+    package.tables[0].constraints.push(TableConstraint::Foreign {
+        name: "fk_contacts_companies".into(),
+        columns: vec!["company_id".into()],
+        ref_table: companies.clone(),
+        ref_columns: vec!["id".into()],
+        match_type: None,
+        events: None,
+    });
+    package.push_table(TableDefinition {
+        name: companies,
+        columns: vec![ColumnDefinition {
+            name: "id".into(),
+            sql_type: SqlType::Simple(SimpleSqlType::Serial, None),
+            constraints: vec![ColumnConstraint::NotNull],
+        }],
+        constraints: vec![TableConstraint::Primary {
+            name: "companies_pkey".into(),
+            columns: vec!["id".into()],
+            parameters: None,
+        }],
+        owner: None,
+    });
+    publish_package!(DB_NAME, connection, package, |publish_profile| {
+        publish_profile.generation_options.online_constraints = true;
+    });
+
+    // The constraint was validated after being added
+    let conn = connection.connect_database().unwrap();
+    let rows = conn
+        .query(
+            "SELECT convalidated FROM pg_constraint WHERE conname = 'fk_contacts_companies'",
+            &[],
+        )
+        .unwrap();
+    assert_that!(rows.len()).is_equal_to(1);
+    let validated: bool = rows.get(0).get(0);
+    assert_that!(validated).is_true();
+    conn.finish().unwrap();
+}